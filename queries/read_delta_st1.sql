@@ -1,2 +1,3 @@
-select COUNT(*) from delta_scan('./data/deltalake/st1') limit 10;
-select * from delta_scan('./data/deltalake/st1') limit 10;
\ No newline at end of file
+-- aer_parser query --file queries/read_delta_st1.sql
+select count(*) from st1;
+select * from st1 limit 10;
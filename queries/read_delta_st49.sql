@@ -1,2 +1,3 @@
-select COUNT(*) from delta_scan('./data/deltalake/st49') limit 10;
-select * from delta_scan('./data/deltalake/st49') limit 10;
+-- aer_parser query --file queries/read_delta_st49.sql
+select count(*) from st49;
+select * from st49 limit 10;
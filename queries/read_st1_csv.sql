@@ -1,2 +1,3 @@
-select COUNT(*) from 'data/csv/*WELLS*.csv' limit 10;
-select * from 'data/csv/*WELLS*.csv' limit 10;
\ No newline at end of file
+-- aer_parser query --file queries/read_st1_csv.sql
+select count(*) from st1_csv;
+select * from st1_csv limit 10;
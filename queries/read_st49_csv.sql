@@ -1,2 +1,3 @@
-select COUNT(*) from 'data/csv/*SPUD*.csv' limit 10;
-select * from 'data/csv/*SPUD*.csv' limit 10;
+-- aer_parser query --file queries/read_st49_csv.sql
+select count(*) from st49_csv;
+select * from st49_csv limit 10;
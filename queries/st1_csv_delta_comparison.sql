@@ -1,11 +1,14 @@
+-- aer_parser query --file queries/st1_csv_delta_comparison.sql
+-- Compares whole rows, so the table must have no partition or lineage columns;
+-- `aer_parser reconcile --report-type st1` ignores them and counts by date.
 (
-  select * from 'data/csv/*WELLS*.csv'
+  select * from st1_csv
   except
-  select * from delta_scan('./data/deltalake/st1')
+  select * from st1
 )
 union all
 (
-  select * from delta_scan('./data/deltalake/st1')
+  select * from st1
   except
-  select * from 'data/csv/*WELLS*.csv'
-);
\ No newline at end of file
+  select * from st1_csv
+);
@@ -1,11 +1,14 @@
+-- aer_parser query --file queries/st49_csv_delta_comparison.sql
+-- Compares whole rows, so the table must have no partition or lineage columns;
+-- `aer_parser reconcile --report-type st49` ignores them and counts by date.
 (
-  select * from 'data/csv/*SPUD*csv'
+  select * from st49_csv
   except
-  select * from delta_scan('./data/deltalake/st49')
+  select * from st49
 )
-union al
+union all
 (
-  select * from delta_scan('./data/deltalake/ st49')
+  select * from st49
   except
-  select * from 'data/csv/*SPUD*.csv'
-);
\ No newline at end of file
+  select * from st49_csv
+);
@@ -0,0 +1,383 @@
+//! The derived `company_registry` and `st1_licensees` tables
+//!
+//! The same company is spelled differently across reports
+//! (`TOURMALINE OIL CORP.` in ST-1, `Tourmaline Oil Corp.` in ST-49) and its
+//! name changes across mergers. ST-49 lists the Business Associate (BA) ID of
+//! every licensee and drilling contractor, ST-1 lists names only.
+//!
+//! The `company_registry` table holds every BA ID with each of its
+//! normalised names (see [`normalize_company_name`]) and when ST-49 listed
+//! it, plus the aliases of an optional user-supplied file mapping other names
+//! to BA IDs. The `st1_licensees` table resolves each ST-1 licensee name to
+//! a BA ID through the registry.
+//!
+//! The rows of both tables are recomputed and merged (see
+//! [`super::derived`]).
+//!
+//! [`normalize_company_name`]: crate::parsers::common::field_parsing::normalize_company_name
+
+use std::path::Path;
+use std::sync::Arc;
+
+use datafusion::prelude::SessionContext;
+use deltalake::arrow::array::{RecordBatch, StringArray};
+use deltalake::arrow::datatypes::{DataType as ArrowDataType, Field, Schema};
+use deltalake::kernel::{DataType, PrimitiveType, StructField};
+use deltalake::DeltaTable;
+use log::info;
+use serde::Deserialize;
+
+use super::derived::{create_or_open_derived_table, merge_derived_rows, row_counts};
+use super::functions::register_aer_functions;
+use super::{ensure_typed_schema, DeltaReportType};
+use crate::AppError;
+
+/// Columns of the `company_registry` table; the first two identify a row.
+const REGISTRY_COLUMNS: [&str; 9] = [
+    "ba_id",
+    "name_key",
+    "name",
+    "first_seen",
+    "last_seen",
+    "records",
+    "as_licensee",
+    "as_contractor",
+    "source",
+];
+
+/// Columns of the `st1_licensees` table; the first identifies a row.
+const LICENSEE_COLUMNS: [&str; 8] = [
+    "licensee",
+    "name_key",
+    "first_listed",
+    "last_listed",
+    "licences",
+    "ba_id",
+    "ba_name",
+    "resolved_by",
+];
+
+/// A name mapped to a BA ID by the user, e.g. the name of a company before
+/// it was acquired mapped to the BA ID of the acquirer.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct CompanyAlias {
+    /// Company name as listed in a report, in any spelling
+    pub name: String,
+    /// BA ID the name resolves to
+    pub ba_id: String,
+}
+
+/// Rows changed in the `company_registry` table by [`update_company_registry`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CompanyRegistrySummary {
+    /// New BA ID and name pairs
+    pub inserted: usize,
+    /// Pairs whose spelling, dates or roles changed
+    pub updated: usize,
+    /// Pairs no longer in the ST-49 table or the aliases
+    pub deleted: usize,
+    /// Distinct BA IDs in the table after the update
+    pub companies: usize,
+    /// BA ID and name pairs in the table after the update
+    pub names: usize,
+}
+
+/// Rows changed in the `st1_licensees` table by [`resolve_st1_licensees`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LicenseeResolutionSummary {
+    /// New licensee names
+    pub inserted: usize,
+    /// Licensees whose listing or resolved BA ID changed
+    pub updated: usize,
+    /// Licensees no longer in the ST-1 table
+    pub deleted: usize,
+    /// Licensees in the table after the update
+    pub licensees: usize,
+    /// Licensees resolved to a BA ID
+    pub resolved: usize,
+}
+
+/// Read a company alias file.
+///
+/// The file is a comma-separated CSV with a `name,ba_id` header and one alias
+/// per line.
+///
+/// # Arguments
+/// * `path` - Local path of the alias file
+///
+/// # Returns
+/// The aliases in file order
+pub fn read_company_aliases(path: &Path) -> Result<Vec<CompanyAlias>, AppError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    Ok(reader.deserialize().collect::<Result<_, _>>()?)
+}
+
+/// Create the `company_registry` table, or open it if it exists.
+async fn create_or_open_registry_table(table_path: &Path) -> Result<DeltaTable, AppError> {
+    let string = DataType::Primitive(PrimitiveType::String);
+    let date = DataType::Primitive(PrimitiveType::Date);
+    let boolean = DataType::Primitive(PrimitiveType::Boolean);
+    let columns = vec![
+        StructField::new("ba_id", string.clone(), false),
+        StructField::new("name_key", string.clone(), false),
+        StructField::new("name", string.clone(), false),
+        StructField::new("first_seen", date.clone(), true),
+        StructField::new("last_seen", date, true),
+        StructField::new("records", DataType::Primitive(PrimitiveType::Long), false),
+        StructField::new("as_licensee", boolean.clone(), false),
+        StructField::new("as_contractor", boolean, false),
+        StructField::new("source", string, false),
+    ];
+    create_or_open_derived_table(table_path, columns).await
+}
+
+/// Create the `st1_licensees` table, or open it if it exists.
+async fn create_or_open_licensees_table(table_path: &Path) -> Result<DeltaTable, AppError> {
+    let string = DataType::Primitive(PrimitiveType::String);
+    let date = DataType::Primitive(PrimitiveType::Date);
+    let columns = vec![
+        StructField::new("licensee", string.clone(), false),
+        StructField::new("name_key", string.clone(), false),
+        StructField::new("first_listed", date.clone(), true),
+        StructField::new("last_listed", date, true),
+        StructField::new("licences", DataType::Primitive(PrimitiveType::Long), false),
+        StructField::new("ba_id", string.clone(), true),
+        StructField::new("ba_name", string.clone(), true),
+        StructField::new("resolved_by", string, true),
+    ];
+    create_or_open_derived_table(table_path, columns).await
+}
+
+/// SQL listing every BA ID and name pair of the `st49` and `aliases` tables.
+///
+/// Each pair keeps its most frequent spelling, the first and last report
+/// dates listing it and whether it was listed as a licensee or contractor.
+/// An alias of a pair already listed in ST-49 adds nothing.
+fn company_registry_sql() -> &'static str {
+    "WITH named AS (
+        SELECT trim(ba_id) AS ba_id, company_name_key(licensee) AS name_key,
+            trim(licensee) AS name, date, 'licensee' AS role
+        FROM st49
+        UNION ALL
+        SELECT trim(contractor_ba_id), company_name_key(contractor_name),
+            trim(contractor_name), date, 'contractor'
+        FROM st49
+    ),
+    listed AS (
+        SELECT * FROM named
+        WHERE ba_id IS NOT NULL AND ba_id <> '' AND name_key IS NOT NULL
+    ),
+    spellings AS (
+        SELECT ba_id, name_key, name, count(*) AS records, max(date) AS last_seen
+        FROM listed
+        GROUP BY ba_id, name_key, name
+    ),
+    ranked AS (
+        SELECT ba_id, name_key, name,
+            ROW_NUMBER() OVER (
+                PARTITION BY ba_id, name_key ORDER BY records DESC, last_seen DESC, name
+            ) AS spelling_rank
+        FROM spellings
+    ),
+    observed AS (
+        SELECT ba_id, name_key, min(date) AS first_seen, max(date) AS last_seen,
+            count(*) AS records,
+            bool_or(role = 'licensee') AS as_licensee,
+            bool_or(role = 'contractor') AS as_contractor
+        FROM listed
+        GROUP BY ba_id, name_key
+    ),
+    aliased AS (
+        SELECT trim(ba_id) AS ba_id, company_name_key(name) AS name_key, min(trim(name)) AS name
+        FROM aliases
+        WHERE trim(ba_id) <> '' AND company_name_key(name) IS NOT NULL
+        GROUP BY trim(ba_id), company_name_key(name)
+    )
+    SELECT o.ba_id, o.name_key, r.name, o.first_seen, o.last_seen, o.records,
+        o.as_licensee, o.as_contractor, 'st49' AS source
+    FROM observed o
+    JOIN ranked r ON r.ba_id = o.ba_id AND r.name_key = o.name_key AND r.spelling_rank = 1
+    UNION ALL
+    SELECT a.ba_id, a.name_key, a.name, CAST(NULL AS DATE) AS first_seen,
+        CAST(NULL AS DATE) AS last_seen, CAST(0 AS BIGINT) AS records,
+        false AS as_licensee, false AS as_contractor, 'alias' AS source
+    FROM aliased a
+    WHERE NOT EXISTS (
+        SELECT 1 FROM observed o WHERE o.ba_id = a.ba_id AND o.name_key = a.name_key
+    )"
+}
+
+/// SQL resolving every `st1` licensee to a BA ID of the `registry` table.
+///
+/// When several BA IDs carry the licensee's normalised name, an alias wins,
+/// then a BA ID listed by ST-49 while ST-1 listed the licensee, then the BA
+/// ID listed most recently.
+fn st1_licensees_sql() -> &'static str {
+    "WITH licensees AS (
+        SELECT licensee, company_name_key(licensee) AS name_key,
+            min(date) AS first_listed, max(date) AS last_listed,
+            count(DISTINCT licence_number) AS licences
+        FROM st1
+        WHERE company_name_key(licensee) IS NOT NULL
+        GROUP BY licensee
+    ),
+    candidates AS (
+        SELECT l.licensee, r.ba_id, r.name, r.source,
+            ROW_NUMBER() OVER (
+                PARTITION BY l.licensee
+                ORDER BY CASE WHEN r.source = 'alias' THEN 0 ELSE 1 END,
+                    CASE WHEN r.first_seen <= l.last_listed AND r.last_seen >= l.first_listed
+                        THEN 0 ELSE 1 END,
+                    r.last_seen DESC NULLS LAST,
+                    r.ba_id
+            ) AS candidate_rank
+        FROM licensees l
+        JOIN registry r ON r.name_key = l.name_key
+    )
+    SELECT l.licensee, l.name_key, l.first_listed, l.last_listed, l.licences,
+        c.ba_id, c.name AS ba_name, c.source AS resolved_by
+    FROM licensees l
+    LEFT JOIN candidates c ON c.licensee = l.licensee AND c.candidate_rank = 1"
+}
+
+/// Bring the `company_registry` table in line with an ST-49 table and aliases.
+///
+/// Creates the table (with Change Data Feed enabled) on first use. Every BA
+/// ID and normalised name pair is recomputed and merged on (`ba_id`,
+/// `name_key`): new pairs are inserted, pairs whose spelling, dates or roles
+/// changed are updated, and pairs no longer listed or aliased are deleted.
+/// Nothing is committed when the table is up to date.
+///
+/// # Arguments
+/// * `st49_table` - The typed ST-49 table listing BA IDs and names
+/// * `aliases` - Names mapped to BA IDs by the user, see [`read_company_aliases`]
+/// * `table_path` - Directory or object store URI of the `company_registry` table
+///
+/// # Returns
+/// Number of pairs inserted, updated and deleted, and the size of the registry
+///
+/// # Example
+/// ```rust,ignore
+/// let st49 = deltalake::open_table("data/deltalake/st49").await?;
+/// let aliases = read_company_aliases(Path::new("company_aliases.csv"))?;
+/// let summary =
+///     update_company_registry(&st49, &aliases, Path::new("data/deltalake/company_registry"))
+///         .await?;
+/// println!("{} companies under {} names", summary.companies, summary.names);
+/// ```
+pub async fn update_company_registry(
+    st49_table: &DeltaTable,
+    aliases: &[CompanyAlias],
+    table_path: &Path,
+) -> Result<CompanyRegistrySummary, AppError> {
+    ensure_typed_schema(st49_table, DeltaReportType::St49)?;
+    let registry = create_or_open_registry_table(table_path).await?;
+
+    let ctx = SessionContext::new();
+    register_aer_functions(&ctx);
+    ctx.register_table("st49", Arc::new(st49_table.clone()))?;
+    let alias_schema = Arc::new(Schema::new(vec![
+        Field::new("name", ArrowDataType::Utf8, false),
+        Field::new("ba_id", ArrowDataType::Utf8, false),
+    ]));
+    let alias_batch = RecordBatch::try_new(
+        alias_schema,
+        vec![
+            Arc::new(StringArray::from_iter_values(
+                aliases.iter().map(|alias| &alias.name),
+            )),
+            Arc::new(StringArray::from_iter_values(
+                aliases.iter().map(|alias| &alias.ba_id),
+            )),
+        ],
+    )?;
+    ctx.register_batch("aliases", alias_batch)?;
+    let rows = ctx.sql(company_registry_sql()).await?;
+    ctx.register_table("company_registry", rows.clone().into_view())?;
+    let registry_counts = ctx
+        .sql("SELECT count(DISTINCT ba_id) AS companies, count(*) AS names FROM company_registry")
+        .await?
+        .collect()
+        .await?;
+
+    let metrics =
+        merge_derived_rows(registry, rows, &REGISTRY_COLUMNS[..2], &REGISTRY_COLUMNS).await?;
+
+    let [companies, names] = row_counts(&registry_counts);
+    let summary = CompanyRegistrySummary {
+        inserted: metrics.num_target_rows_inserted,
+        updated: metrics.num_target_rows_updated,
+        deleted: metrics.num_target_rows_deleted,
+        companies,
+        names,
+    };
+    info!(
+        "Updated company_registry at {table_path:?}: {} names inserted, {} updated, {} deleted; {} companies under {} names",
+        summary.inserted, summary.updated, summary.deleted, summary.companies, summary.names
+    );
+    Ok(summary)
+}
+
+/// Bring the `st1_licensees` table in line with an ST-1 table and the registry.
+///
+/// Creates the table (with Change Data Feed enabled) on first use. The BA ID
+/// of every ST-1 licensee name is resolved through the `company_registry`
+/// table by normalised name and merged on `licensee`. Licensees without a
+/// registry entry are kept with a null `ba_id`. Nothing is committed when the
+/// table is up to date.
+///
+/// # Arguments
+/// * `st1_table` - The typed ST-1 table listing licensees
+/// * `registry_table` - The `company_registry` table, see [`update_company_registry`]
+/// * `table_path` - Directory or object store URI of the `st1_licensees` table
+///
+/// # Returns
+/// Number of licensees inserted, updated and deleted, and how many are resolved
+///
+/// # Example
+/// ```rust,ignore
+/// let st1 = deltalake::open_table("data/deltalake/st1").await?;
+/// let registry = deltalake::open_table("data/deltalake/company_registry").await?;
+/// let summary =
+///     resolve_st1_licensees(&st1, &registry, Path::new("data/deltalake/st1_licensees")).await?;
+/// println!("{} of {} licensees resolved", summary.resolved, summary.licensees);
+/// ```
+pub async fn resolve_st1_licensees(
+    st1_table: &DeltaTable,
+    registry_table: &DeltaTable,
+    table_path: &Path,
+) -> Result<LicenseeResolutionSummary, AppError> {
+    ensure_typed_schema(st1_table, DeltaReportType::St1)?;
+    let licensees = create_or_open_licensees_table(table_path).await?;
+
+    let ctx = SessionContext::new();
+    register_aer_functions(&ctx);
+    ctx.register_table("st1", Arc::new(st1_table.clone()))?;
+    ctx.register_table("registry", Arc::new(registry_table.clone()))?;
+    let rows = ctx.sql(st1_licensees_sql()).await?;
+    ctx.register_table("st1_licensees", rows.clone().into_view())?;
+    let licensee_counts = ctx
+        .sql("SELECT count(*) AS licensees, count(ba_id) AS resolved FROM st1_licensees")
+        .await?
+        .collect()
+        .await?;
+
+    let metrics =
+        merge_derived_rows(licensees, rows, &LICENSEE_COLUMNS[..1], &LICENSEE_COLUMNS).await?;
+
+    let [licensees, resolved] = row_counts(&licensee_counts);
+    let summary = LicenseeResolutionSummary {
+        inserted: metrics.num_target_rows_inserted,
+        updated: metrics.num_target_rows_updated,
+        deleted: metrics.num_target_rows_deleted,
+        licensees,
+        resolved,
+    };
+    info!(
+        "Updated st1_licensees at {table_path:?}: {} licensees inserted, {} updated, {} deleted; {} of {} resolved to a BA ID",
+        summary.inserted, summary.updated, summary.deleted, summary.resolved, summary.licensees
+    );
+    Ok(summary)
+}
@@ -0,0 +1,168 @@
+//! NOT NULL and CHECK constraints of the typed tables
+//!
+//! New tables get NOT NULL key columns and the CHECK constraints of their
+//! report type. Parsed records are validated against the rules the target
+//! table declares before they are written, so a violation names the source
+//! file and line instead of failing somewhere inside the Delta writer.
+
+use datafusion::common::DFSchema;
+use datafusion::prelude::SessionContext;
+use deltalake::arrow::array::{Array, AsArray, RecordBatch};
+use deltalake::DeltaTable;
+
+use super::DeltaReportType;
+use crate::AppError;
+
+/// Violations listed in an error before the rest are only counted.
+const MAX_REPORTED_VIOLATIONS: usize = 10;
+
+/// Prefix of the table properties declaring CHECK constraints.
+const CONSTRAINT_PROPERTY_PREFIX: &str = "delta.constraints.";
+
+/// Table properties declaring the report type's CHECK constraints, for a new table.
+///
+/// Declaring them when the table is created needs no extra commit, and an
+/// empty table cannot violate them.
+pub(super) fn constraint_properties(report_type: DeltaReportType) -> Vec<(String, String)> {
+    report_type
+        .check_constraints()
+        .iter()
+        .map(|(name, expression)| {
+            (
+                format!("{CONSTRAINT_PROPERTY_PREFIX}{name}"),
+                expression.to_string(),
+            )
+        })
+        .collect()
+}
+
+/// The NOT NULL columns and CHECK constraints a table declares.
+///
+/// Tables created by this tool declare those of their report type, but tables
+/// created before constraints were added declare none, and rows are only
+/// checked against what the table itself enforces.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TableConstraints {
+    /// Columns whose schema field is not nullable
+    pub not_null: Vec<String>,
+    /// Name and SQL expression of each `delta.constraints.*` table property
+    pub checks: Vec<(String, String)>,
+}
+
+impl TableConstraints {
+    /// The constraints tables of a report type are created with.
+    pub fn for_report_type(report_type: DeltaReportType) -> Self {
+        Self {
+            not_null: report_type
+                .not_null_columns()
+                .iter()
+                .map(|column| column.to_string())
+                .collect(),
+            checks: report_type
+                .check_constraints()
+                .iter()
+                .map(|(name, expression)| (name.to_string(), expression.to_string()))
+                .collect(),
+        }
+    }
+
+    /// Reads the constraints declared in a table's metadata and schema.
+    pub fn for_table(table: &DeltaTable) -> Result<Self, AppError> {
+        let mut checks: Vec<(String, String)> = table
+            .metadata()?
+            .configuration()
+            .iter()
+            .filter_map(|(key, expression)| {
+                let name = key.strip_prefix(CONSTRAINT_PROPERTY_PREFIX)?;
+                Some((name.to_string(), expression.clone()))
+            })
+            .collect();
+        checks.sort();
+        let not_null = table
+            .snapshot()?
+            .schema()
+            .fields()
+            .filter(|field| !field.is_nullable())
+            .map(|field| field.name().clone())
+            .collect();
+        Ok(Self { not_null, checks })
+    }
+}
+
+/// Check parsed records against the NOT NULL columns and CHECK constraints of
+/// the table they are written to.
+///
+/// As in Delta, a CHECK constraint that evaluates to null passes. Columns the
+/// batch does not have are not checked.
+///
+/// # Arguments
+/// * `constraints` - Constraints of the target table
+/// * `batch` - Typed records, as produced by the parsers
+/// * `source_name` - Name of the file the records came from, used in the error
+/// * `source_lines` - 1-based line of each row in that file
+///
+/// # Returns
+/// `AppError::ConstraintViolation` naming the file and line of the offending
+/// rows, if any row violates a constraint
+///
+/// # Example
+/// ```rust,ignore
+/// let constraints = TableConstraints::for_table(&table)?;
+/// let batch = License::to_record_batch(&report.records)?;
+/// validate_constraints(&constraints, &batch, &report.source.file_name, &report.source_lines)?;
+/// ```
+pub fn validate_constraints(
+    constraints: &TableConstraints,
+    batch: &RecordBatch,
+    source_name: &str,
+    source_lines: &[Option<u32>],
+) -> Result<(), AppError> {
+    let mut violations: Vec<(usize, String)> = Vec::new();
+
+    for column in &constraints.not_null {
+        if let Some(values) = batch.column_by_name(column) {
+            for row in (0..values.len()).filter(|&row| values.is_null(row)) {
+                violations.push((row, format!("`{column}` is empty")));
+            }
+        }
+    }
+
+    let ctx = SessionContext::new();
+    let schema = DFSchema::try_from(batch.schema().as_ref().clone())?;
+    for (name, expression) in &constraints.checks {
+        // Fails only when the batch lacks a column the constraint uses
+        let Ok(expr) = ctx.parse_sql_expr(expression, &schema) else {
+            continue;
+        };
+        let result = ctx
+            .create_physical_expr(expr, &schema)?
+            .evaluate(batch)?
+            .into_array(batch.num_rows())?;
+        let passed = result.as_boolean();
+        for row in (0..passed.len()).filter(|&row| passed.is_valid(row) && !passed.value(row)) {
+            violations.push((row, format!("{name} ({expression})")));
+        }
+    }
+
+    if violations.is_empty() {
+        return Ok(());
+    }
+    violations.sort();
+    let listed = violations
+        .iter()
+        .take(MAX_REPORTED_VIOLATIONS)
+        .map(
+            |(row, violation)| match source_lines.get(*row).copied().flatten() {
+                Some(line) => format!("{source_name} line {line}: {violation}"),
+                None => format!("{source_name} record {}: {violation}", row + 1),
+            },
+        )
+        .collect::<Vec<_>>()
+        .join("; ");
+    let more = violations.len().saturating_sub(MAX_REPORTED_VIOLATIONS);
+    Err(AppError::ConstraintViolation(if more > 0 {
+        format!("{listed}; and {more} more")
+    } else {
+        listed
+    }))
+}
@@ -0,0 +1,128 @@
+//! Tables derived from the report tables
+//!
+//! Derived tables such as `licence_history` and `licence_spud` are
+//! recomputed from the report tables and merged into a table with Change
+//! Data Feed enabled, so only rows that changed are rewritten and the change
+//! data feed carries just those rows.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use datafusion::prelude::{DataFrame, SessionContext};
+use deltalake::arrow::array::{AsArray, RecordBatch};
+use deltalake::arrow::datatypes::Int64Type;
+use deltalake::kernel::StructField;
+use deltalake::operations::merge::MergeMetrics;
+use deltalake::protocol::SaveMode;
+use deltalake::{DeltaOps, DeltaTable, TableProperty};
+
+use super::table_uri;
+use crate::{storage, AppError};
+
+/// Create a derived table with Change Data Feed enabled, or open it if it exists.
+pub(super) async fn create_or_open_derived_table(
+    table_path: &Path,
+    columns: Vec<StructField>,
+) -> Result<DeltaTable, AppError> {
+    let uri = table_uri(table_path)?;
+    if storage::delta_table_exists(uri).await? {
+        return Ok(deltalake::open_table(uri).await?);
+    }
+
+    if !storage::is_object_store_uri(uri) {
+        if let Some(parent) = table_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+    }
+    Ok(DeltaOps::try_from_uri(uri)
+        .await?
+        .create()
+        .with_save_mode(SaveMode::Ignore)
+        .with_columns(columns)
+        .with_configuration_property(TableProperty::EnableChangeDataFeed, Some("true"))
+        .await?)
+}
+
+/// Merge recomputed rows into a derived table.
+///
+/// Rows are matched on `keys`: new rows are inserted, rows where any other
+/// column changed are updated, and rows of the table that are no longer
+/// derived are deleted. Nothing is committed when the table is up to date.
+///
+/// # Arguments
+/// * `table` - The derived table
+/// * `rows` - Every row the table should hold, with at least `columns`
+/// * `keys` - Columns identifying a row
+/// * `columns` - Columns of the table
+///
+/// # Returns
+/// The merge metrics, with the rows inserted, updated and deleted
+pub(super) async fn merge_derived_rows(
+    table: DeltaTable,
+    rows: DataFrame,
+    keys: &[&str],
+    columns: &[&str],
+) -> Result<MergeMetrics, AppError> {
+    let ctx = SessionContext::new();
+    ctx.register_table("existing", Arc::new(table.clone()))?;
+    ctx.register_table("derived", rows.into_view())?;
+
+    let select = columns.join(", ");
+    let key_match = |left: &str, right: &str| {
+        keys.iter()
+            .map(|key| format!("{left}.{key} = {right}.{key}"))
+            .collect::<Vec<_>>()
+            .join(" AND ")
+    };
+    // Rows only the table still has are sent along flagged for deletion
+    let source = ctx
+        .sql(&format!(
+            "SELECT {select}, false AS deleted FROM derived
+            UNION ALL
+            SELECT {select}, true AS deleted FROM existing e
+            WHERE NOT EXISTS (SELECT 1 FROM derived d WHERE {})",
+            key_match("d", "e")
+        ))
+        .await?;
+
+    let changed = columns
+        .iter()
+        .filter(|column| !keys.contains(column))
+        .map(|column| format!("(target.{column} IS DISTINCT FROM source.{column})"))
+        .collect::<Vec<_>>()
+        .join(" OR ");
+    let (_, metrics) = DeltaOps::from(table)
+        .merge(source, key_match("target", "source"))
+        .with_source_alias("source")
+        .with_target_alias("target")
+        .when_matched_delete(|delete| delete.predicate("source.deleted"))?
+        .when_matched_update(|update| {
+            columns
+                .iter()
+                .fold(update.predicate(changed), |update, column| {
+                    update.update(*column, format!("source.{column}"))
+                })
+        })?
+        .when_not_matched_insert(|insert| {
+            columns
+                .iter()
+                .fold(insert.predicate("NOT source.deleted"), |insert, column| {
+                    insert.set(*column, format!("source.{column}"))
+                })
+        })?
+        .await?;
+    Ok(metrics)
+}
+
+/// Values of the first `N` columns of a one-row count query, such as
+/// `SELECT count(*), count(ba_id) FROM ...`, run over a derived table's rows.
+///
+/// # Returns
+/// The Int64 value of each column, 0 when the query returned no row
+pub(super) fn row_counts<const N: usize>(batches: &[RecordBatch]) -> [usize; N] {
+    let row = batches.iter().find(|batch| batch.num_rows() > 0);
+    std::array::from_fn(|column| {
+        row.map(|batch| batch.column(column).as_primitive::<Int64Type>().value(0) as usize)
+            .unwrap_or_default()
+    })
+}
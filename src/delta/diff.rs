@@ -0,0 +1,396 @@
+//! Differences between two reports or report date ranges
+//!
+//! Each side of a diff is a raw WELLS or SPUD TXT report, or a report date or
+//! date range of a Delta table. Records are matched on the licence number
+//! (ST-1) or the well ID and activity type (ST-49); within a date range the
+//! latest listing of a record counts. Matched records are compared field by
+//! field, leaving out the report date.
+//!
+//! When both sides are the same single report date but differ, the AER
+//! republished the report for that date with different content.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use chrono::NaiveDate;
+use datafusion::prelude::SessionContext;
+use deltalake::arrow::array::{Array, AsArray, RecordBatch};
+use deltalake::arrow::datatypes::Date32Type;
+use deltalake::arrow::util::display::{ArrayFormatter, FormatOptions};
+
+use super::{open_delta_table_at, DeltaReportType, TableVersion};
+use crate::parsers::lineage::sha256_file;
+use crate::{parse_file_to_batch, AppError, ReportType};
+
+/// One side of a diff.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffSide {
+    /// A raw WELLS or SPUD TXT report
+    File(PathBuf),
+    /// The report dates `start` to `end` (inclusive) of a Delta table
+    Dates { start: NaiveDate, end: NaiveDate },
+}
+
+impl DiffSide {
+    /// Read a side written as a report date (`2025-01-02`), a date range
+    /// (`2025-01-01..2025-01-31`) or, failing both, a file path.
+    ///
+    /// # Arguments
+    /// * `value` - Date, date range or path
+    ///
+    /// # Returns
+    /// The side, or an error for a date range ending before it starts
+    pub fn parse(value: &str) -> Result<Self, AppError> {
+        let date = |value: &str| NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d").ok();
+        let dates = match value.split_once("..") {
+            Some((start, end)) => date(start).zip(date(end)),
+            None => date(value).map(|date| (date, date)),
+        };
+        match dates {
+            Some((start, end)) if start > end => Err(AppError::Cli(format!(
+                "Date range `{value}` ends before it starts"
+            ))),
+            Some((start, end)) => Ok(DiffSide::Dates { start, end }),
+            None => Ok(DiffSide::File(PathBuf::from(value))),
+        }
+    }
+}
+
+impl fmt::Display for DiffSide {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiffSide::File(path) => write!(f, "{}", path.display()),
+            DiffSide::Dates { start, end } if start == end => write!(f, "{start}"),
+            DiffSide::Dates { start, end } => write!(f, "{start}..{end}"),
+        }
+    }
+}
+
+/// A record only one side lists.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffRecord {
+    /// Key of the record, e.g. the licence number
+    pub key: String,
+    /// Every compared field and its value, empty for nulls
+    pub fields: Vec<(String, String)>,
+}
+
+/// A field whose value differs between the sides.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldChange {
+    /// Column name
+    pub field: String,
+    /// Value on the old side, empty for null
+    pub old: String,
+    /// Value on the new side, empty for null
+    pub new: String,
+}
+
+/// A record both sides list with different field values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangedRecord {
+    /// Key of the record, e.g. the licence number
+    pub key: String,
+    /// The fields that changed, in column order
+    pub changes: Vec<FieldChange>,
+}
+
+/// Differences between two sides, from old to new.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReportDiff {
+    /// Report dates the old side covers
+    pub old_dates: BTreeSet<NaiveDate>,
+    /// Report dates the new side covers
+    pub new_dates: BTreeSet<NaiveDate>,
+    /// Records only the new side lists
+    pub added: Vec<DiffRecord>,
+    /// Records only the old side lists
+    pub removed: Vec<DiffRecord>,
+    /// Records whose fields changed
+    pub changed: Vec<ChangedRecord>,
+    /// Records listed unchanged on both sides
+    pub unchanged: usize,
+    /// The report date, if both sides are the same single report date with
+    /// different content, i.e. the AER republished it
+    pub republished: Option<NaiveDate>,
+}
+
+impl ReportDiff {
+    /// Whether the sides list the same records with the same values.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl DeltaReportType {
+    /// Columns identifying a record within a report.
+    fn diff_keys(self) -> Vec<&'static str> {
+        self.merge_keys()
+            .iter()
+            .copied()
+            .filter(|column| *column != "date")
+            .collect()
+    }
+}
+
+/// Records of one side, by key, with the report dates it covers.
+struct SideRecords {
+    dates: BTreeSet<NaiveDate>,
+    records: BTreeMap<String, Vec<String>>,
+    sha256: Option<String>,
+}
+
+/// Values of every column of every row, formatted as text.
+fn batch_rows(batch: &RecordBatch) -> Result<Vec<Vec<String>>, AppError> {
+    let options = FormatOptions::default();
+    let formatters = batch
+        .columns()
+        .iter()
+        .map(|column| ArrayFormatter::try_new(column.as_ref(), &options))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((0..batch.num_rows())
+        .map(|row| {
+            formatters
+                .iter()
+                .map(|formatter| formatter.value(row).to_string())
+                .collect()
+        })
+        .collect())
+}
+
+/// Key the records of batches sorted by report date; later listings win.
+fn side_records(
+    report_type: DeltaReportType,
+    batches: &[RecordBatch],
+    sha256: Option<String>,
+) -> Result<SideRecords, AppError> {
+    let schema = report_type.arrow_schema();
+    let date_index = schema.index_of("date")?;
+    let key_indices = report_type
+        .diff_keys()
+        .iter()
+        .map(|key| schema.index_of(key))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut side = SideRecords {
+        dates: BTreeSet::new(),
+        records: BTreeMap::new(),
+        sha256,
+    };
+    for batch in batches {
+        let dates = batch.column(date_index).as_primitive::<Date32Type>();
+        side.dates.extend(
+            (0..dates.len())
+                .filter(|row| dates.is_valid(*row))
+                .filter_map(|row| dates.value_as_date(row)),
+        );
+        for mut values in batch_rows(batch)? {
+            let key = key_indices
+                .iter()
+                .map(|index| values[*index].as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            values.remove(date_index);
+            side.records.insert(key, values);
+        }
+    }
+    Ok(side)
+}
+
+/// Parse a raw report, or read a report date range of the Delta table.
+async fn read_side(
+    report_type: DeltaReportType,
+    side: &DiffSide,
+    table_path: &Path,
+) -> Result<SideRecords, AppError> {
+    match side {
+        DiffSide::File(path) => {
+            let stem = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .ok_or_else(|| AppError::Cli(format!("Not a report file: {path:?}")))?;
+            let folder = match path.parent().and_then(|parent| parent.to_str()) {
+                Some("") | None => ".",
+                Some(folder) => folder,
+            };
+            let parser_type = match report_type {
+                DeltaReportType::St1 => ReportType::St1,
+                DeltaReportType::St49 => ReportType::St49,
+            };
+            let batch =
+                parse_file_to_batch(parser_type, stem, folder, None, None, None, false).await?;
+            side_records(report_type, &[batch], Some(sha256_file(path)?))
+        }
+        DiffSide::Dates { start, end } => {
+            let table = open_delta_table_at(table_path, TableVersion::Latest).await?;
+            let ctx = SessionContext::new();
+            ctx.register_table("report", Arc::new(table))?;
+            let columns = report_type
+                .arrow_schema()
+                .fields()
+                .iter()
+                .map(|field| field.name().clone())
+                .collect::<Vec<_>>()
+                .join(", ");
+            let batches = ctx
+                .sql(&format!(
+                    "SELECT {columns} FROM report
+                    WHERE date BETWEEN DATE '{start}' AND DATE '{end}'
+                    ORDER BY date"
+                ))
+                .await?
+                .collect()
+                .await?;
+            side_records(report_type, &batches, None)
+        }
+    }
+}
+
+/// Compare two reports, report dates or report date ranges.
+///
+/// # Arguments
+/// * `report_type` - Type of report on both sides
+/// * `old` - The earlier side
+/// * `new` - The later side
+/// * `table_path` - Directory or object store URI of the Delta table that
+///   [`DiffSide::Dates`] sides are read from
+///
+/// # Returns
+/// The added, removed and changed records, and whether the report was republished
+///
+/// # Example
+/// ```rust,ignore
+/// let diff = diff_reports(
+///     DeltaReportType::St1,
+///     &DiffSide::parse("2025-01-02")?,
+///     &DiffSide::parse("data/txt/WELLS0103.TXT")?,
+///     Path::new("data/deltalake/st1"),
+/// )
+/// .await?;
+/// print!("{diff}");
+/// ```
+pub async fn diff_reports(
+    report_type: DeltaReportType,
+    old: &DiffSide,
+    new: &DiffSide,
+    table_path: &Path,
+) -> Result<ReportDiff, AppError> {
+    let old = read_side(report_type, old, table_path).await?;
+    let mut new = read_side(report_type, new, table_path).await?;
+    let schema = report_type.arrow_schema();
+    let fields: Vec<&String> = schema
+        .fields()
+        .iter()
+        .map(|field| field.name())
+        .filter(|name| *name != "date")
+        .collect();
+    let named = |values: Vec<String>| {
+        fields
+            .iter()
+            .map(|field| field.to_string())
+            .zip(values)
+            .collect()
+    };
+
+    let mut diff = ReportDiff {
+        old_dates: old.dates.clone(),
+        new_dates: new.dates.clone(),
+        ..ReportDiff::default()
+    };
+    for (key, old_values) in old.records {
+        let Some(new_values) = new.records.remove(&key) else {
+            diff.removed.push(DiffRecord {
+                key,
+                fields: named(old_values),
+            });
+            continue;
+        };
+        let changes: Vec<FieldChange> = fields
+            .iter()
+            .zip(old_values.into_iter().zip(new_values))
+            .filter(|(_, (old, new))| old != new)
+            .map(|(field, (old, new))| FieldChange {
+                field: field.to_string(),
+                old,
+                new,
+            })
+            .collect();
+        if changes.is_empty() {
+            diff.unchanged += 1;
+        } else {
+            diff.changed.push(ChangedRecord { key, changes });
+        }
+    }
+    diff.added = new
+        .records
+        .into_iter()
+        .map(|(key, values)| DiffRecord {
+            key,
+            fields: named(values),
+        })
+        .collect();
+
+    let same_content = match (&old.sha256, &new.sha256) {
+        (Some(old), Some(new)) => old == new,
+        _ => diff.is_empty(),
+    };
+    if diff.old_dates.len() == 1 && diff.old_dates == diff.new_dates && !same_content {
+        diff.republished = diff.old_dates.first().copied();
+    }
+    Ok(diff)
+}
+
+impl fmt::Display for ReportDiff {
+    /// Prints the differences as text, one section per kind of change.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(date) = self.republished {
+            writeln!(
+                f,
+                "The report for {date} was republished with different content"
+            )?;
+        }
+        let well_name = |record: &DiffRecord| {
+            record
+                .fields
+                .iter()
+                .find(|(field, _)| field == "well_name")
+                .map(|(_, value)| value.clone())
+                .unwrap_or_default()
+        };
+        if !self.added.is_empty() {
+            writeln!(f, "Added ({}):", self.added.len())?;
+            for record in &self.added {
+                writeln!(f, "  + {}  {}", record.key, well_name(record))?;
+            }
+        }
+        if !self.removed.is_empty() {
+            writeln!(f, "Removed ({}):", self.removed.len())?;
+            for record in &self.removed {
+                writeln!(f, "  - {}  {}", record.key, well_name(record))?;
+            }
+        }
+        if !self.changed.is_empty() {
+            writeln!(f, "Changed ({}):", self.changed.len())?;
+            for record in &self.changed {
+                writeln!(f, "  ~ {}", record.key)?;
+                for change in &record.changes {
+                    writeln!(
+                        f,
+                        "      {}: \"{}\" -> \"{}\"",
+                        change.field, change.old, change.new
+                    )?;
+                }
+            }
+        }
+        writeln!(
+            f,
+            "{} added, {} removed, {} changed, {} unchanged",
+            self.added.len(),
+            self.removed.len(),
+            self.changed.len(),
+            self.unchanged
+        )
+    }
+}
@@ -0,0 +1,164 @@
+//! Exporting a delta table snapshot to CSV, Parquet or JSON
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use chrono::NaiveDate;
+use clap::ValueEnum;
+use datafusion::common::config::CsvOptions;
+use datafusion::dataframe::DataFrameWriteOptions;
+use datafusion::prelude::{col, lit, SessionContext};
+use deltalake::arrow::array::{AsArray, RecordBatch};
+use deltalake::arrow::datatypes::UInt64Type;
+use log::info;
+
+use super::{date_scalar, open_delta_table_at, TableVersion};
+use crate::{storage, AppError};
+
+/// File format written by [`export_delta_table`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// Pipe-delimited CSV with a header row, like the parser output
+    Csv,
+    /// A single Parquet file
+    Parquet,
+    /// Newline-delimited JSON objects
+    Json,
+}
+
+/// Options for [`export_delta_table`], one field per `export` command line flag.
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    /// Directory of the delta table
+    pub table_path: PathBuf,
+    /// File to write, a local path or an object store URI
+    pub output: PathBuf,
+    /// Format of the output file
+    pub format: ExportFormat,
+    /// Table version to export
+    pub version: TableVersion,
+    /// Only rows with a report `date` on or after this date
+    pub start_date: Option<NaiveDate>,
+    /// Only rows with a report `date` on or before this date
+    pub end_date: Option<NaiveDate>,
+    /// Columns to export, in order; every column when empty
+    pub columns: Vec<String>,
+}
+
+impl ExportOptions {
+    /// Options exporting every row and column of the latest version.
+    pub fn new(
+        table_path: impl Into<PathBuf>,
+        output: impl Into<PathBuf>,
+        format: ExportFormat,
+    ) -> Self {
+        Self {
+            table_path: table_path.into(),
+            output: output.into(),
+            format,
+            version: TableVersion::Latest,
+            start_date: None,
+            end_date: None,
+            columns: Vec::new(),
+        }
+    }
+}
+
+/// Outcome of an export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExportSummary {
+    /// Table version that was exported
+    pub version: i64,
+    /// Rows written to the output file
+    pub rows: u64,
+}
+
+/// Rows written, from the count batch returned by DataFusion's writers.
+fn written_rows(batches: &[RecordBatch]) -> u64 {
+    batches
+        .iter()
+        .filter_map(|batch| batch.column_by_name("count"))
+        .filter_map(|count| count.as_primitive_opt::<UInt64Type>())
+        .flat_map(|count| count.values().iter().copied())
+        .sum()
+}
+
+/// Write a delta table snapshot to a single file.
+///
+/// The table is read at `options.version`, so an export of a past version or
+/// time returns exactly the rows the table held then. Rows are optionally
+/// limited to a report date range and a subset of columns.
+///
+/// # Arguments
+/// * `options` - Table, version, filters and output file
+///
+/// # Returns
+/// The exported version and the number of rows written
+///
+/// # Example
+/// ```rust,ignore
+/// let mut options = ExportOptions::new("data/deltalake/st1", "st1_2024.csv", ExportFormat::Csv);
+/// options.version = TableVersion::AsOf("2025-01-31T23:59:59Z".parse()?);
+/// options.start_date = NaiveDate::from_ymd_opt(2024, 1, 1);
+/// let summary = export_delta_table(&options).await?;
+/// ```
+pub async fn export_delta_table(options: &ExportOptions) -> Result<ExportSummary, AppError> {
+    let table = open_delta_table_at(&options.table_path, options.version).await?;
+    let version = table.version().unwrap_or_default();
+    info!(
+        "Exporting version {version} of {:?} to {:?}",
+        options.table_path, options.output
+    );
+
+    let ctx = SessionContext::new();
+    let output = output_path(&options.output)?;
+    if storage::is_object_store_uri(output) {
+        storage::register_with_session(&ctx, output)?;
+    } else if let Some(parent) = options.output.parent() {
+        if !parent.as_os_str().is_empty() {
+            std::fs::create_dir_all(parent)?;
+        }
+    }
+
+    let mut df = ctx.read_table(Arc::new(table))?;
+    if options.start_date.is_some() || options.end_date.is_some() {
+        if df.schema().field_with_unqualified_name("date").is_err() {
+            return Err(AppError::Cli(
+                "The table has no `date` column to filter on".to_string(),
+            ));
+        }
+        if let Some(start) = options.start_date {
+            df = df.filter(col("date").gt_eq(lit(date_scalar(start))))?;
+        }
+        if let Some(end) = options.end_date {
+            df = df.filter(col("date").lt_eq(lit(date_scalar(end))))?;
+        }
+    }
+    if !options.columns.is_empty() {
+        let columns: Vec<&str> = options.columns.iter().map(String::as_str).collect();
+        df = df.select_columns(&columns)?;
+    }
+
+    let write_options = DataFrameWriteOptions::new().with_single_file_output(true);
+    let written = match options.format {
+        ExportFormat::Csv => {
+            let csv_options = CsvOptions::default()
+                .with_has_header(true)
+                .with_delimiter(b'|');
+            df.write_csv(output, write_options, Some(csv_options))
+                .await?
+        }
+        ExportFormat::Parquet => df.write_parquet(output, write_options, None).await?,
+        ExportFormat::Json => df.write_json(output, write_options, None).await?,
+    };
+
+    Ok(ExportSummary {
+        version,
+        rows: written_rows(&written),
+    })
+}
+
+fn output_path(path: &Path) -> Result<&str, AppError> {
+    path.to_str()
+        .ok_or_else(|| AppError::Cli(format!("Invalid output path: {path:?}")))
+}
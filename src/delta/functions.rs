@@ -0,0 +1,119 @@
+//! SQL functions for AER report fields
+//!
+//! Scalar functions decoding the text fields of the reports inside SQL,
+//! registered by the `query` command and by [`register_aer_functions`] on any
+//! DataFusion session:
+//!
+//! - `uwi_lsd`, `uwi_section`, `uwi_township`, `uwi_range`, `uwi_meridian`:
+//!   parts of the DLS location in a unique identifier, well ID or surface
+//!   location (see [`DlsLocation`])
+//! - `dls_to_lat`, `dls_to_lon`: approximate coordinates of that location
+//! - `metres`: an elevation or depth such as `931.70M` as a number
+//! - `classification_code`, `is_confidential`: the code and confidentiality
+//!   of an AER classification such as `DEV (NC)`
+//! - `company_name_key`: a licensee or contractor name normalised so its
+//!   spellings compare equal (see [`normalize_company_name`])
+//!
+//! Every function takes text, and returns null for values it cannot read.
+
+use std::sync::Arc;
+
+use datafusion::logical_expr::{create_udf, ColumnarValue, ScalarUDF, Volatility};
+use datafusion::prelude::SessionContext;
+use deltalake::arrow::array::{
+    Array, AsArray, BooleanArray, Float64Array, Int32Array, StringArray,
+};
+use deltalake::arrow::datatypes::DataType;
+
+use crate::parsers::common::field_parsing::{
+    normalize_company_name, parse_classification, parse_metres,
+};
+use crate::parsers::location::DlsLocation;
+
+/// The AER SQL functions, for registering on a session.
+///
+/// # Returns
+/// One scalar function per name listed in the module documentation
+pub fn aer_functions() -> Vec<ScalarUDF> {
+    vec![
+        location_function::<Int32Array, _>("uwi_lsd", DataType::Int32, |l| i32::from(l.lsd)),
+        location_function::<Int32Array, _>("uwi_section", DataType::Int32, |l| {
+            i32::from(l.section)
+        }),
+        location_function::<Int32Array, _>("uwi_township", DataType::Int32, |l| {
+            i32::from(l.township)
+        }),
+        location_function::<Int32Array, _>("uwi_range", DataType::Int32, |l| i32::from(l.range)),
+        location_function::<Int32Array, _>("uwi_meridian", DataType::Int32, |l| {
+            i32::from(l.meridian)
+        }),
+        location_function::<Float64Array, _>("dls_to_lat", DataType::Float64, |l| {
+            l.approximate_lat_lon().0
+        }),
+        location_function::<Float64Array, _>("dls_to_lon", DataType::Float64, |l| {
+            l.approximate_lat_lon().1
+        }),
+        text_function::<Float64Array, _>("metres", DataType::Float64, parse_metres),
+        text_function::<StringArray, _>("classification_code", DataType::Utf8, |value| {
+            parse_classification(value).map(|(code, _)| code.to_string())
+        }),
+        text_function::<BooleanArray, _>("is_confidential", DataType::Boolean, |value| {
+            parse_classification(value).map(|(_, confidential)| confidential)
+        }),
+        text_function::<StringArray, _>("company_name_key", DataType::Utf8, normalize_company_name),
+    ]
+}
+
+/// Register the AER SQL functions on a session.
+///
+/// # Example
+/// ```rust,ignore
+/// let ctx = SessionContext::new();
+/// register_aer_functions(&ctx);
+/// let df = ctx
+///     .sql("SELECT uwi_meridian(unique_identifier), count(*) FROM st1 GROUP BY 1")
+///     .await?;
+/// ```
+pub fn register_aer_functions(ctx: &SessionContext) {
+    for function in aer_functions() {
+        ctx.register_udf(function);
+    }
+}
+
+/// A function of one text argument, null for nulls and unreadable values.
+fn text_function<A, T>(
+    name: &str,
+    return_type: DataType,
+    parse: impl Fn(&str) -> Option<T> + Send + Sync + 'static,
+) -> ScalarUDF
+where
+    A: Array + FromIterator<Option<T>> + 'static,
+{
+    create_udf(
+        name,
+        vec![DataType::Utf8],
+        return_type,
+        Volatility::Immutable,
+        Arc::new(move |args: &[ColumnarValue]| {
+            let arrays = ColumnarValue::values_to_arrays(args)?;
+            let values = arrays[0].as_string::<i32>();
+            let result: A = values.iter().map(|value| value.and_then(&parse)).collect();
+            Ok(ColumnarValue::Array(Arc::new(result)))
+        }),
+    )
+}
+
+/// A function of the DLS location in a text argument.
+fn location_function<A, T>(
+    name: &str,
+    return_type: DataType,
+    part: fn(&DlsLocation) -> T,
+) -> ScalarUDF
+where
+    A: Array + FromIterator<Option<T>> + 'static,
+    T: 'static,
+{
+    text_function::<A, T>(name, return_type, move |value| {
+        DlsLocation::parse(value).map(|location| part(&location))
+    })
+}
@@ -0,0 +1,166 @@
+//! The derived `licence_history` table
+//!
+//! ST-1 lists a licence when it is issued and again whenever it reappears in
+//! a later daily list, possibly with changed details. The history table keeps
+//! one row per version of each licence (a type 2 slowly changing dimension):
+//! `valid_from` is the report date the version first appeared and `valid_to`
+//! the report date it was replaced, or null for the current version.
+//!
+//! The versions are recomputed from the whole ST-1 table and merged into the
+//! history table, so only versions that changed are rewritten and the
+//! table's change data feed carries just those rows (see [`super::derived`]).
+
+use std::path::Path;
+use std::sync::Arc;
+
+use datafusion::prelude::SessionContext;
+use deltalake::kernel::{DataType, PrimitiveType, StructField};
+use deltalake::DeltaTable;
+use log::info;
+
+use super::derived::{create_or_open_derived_table, merge_derived_rows};
+use super::{get_schema, DeltaReportType};
+use crate::st1::LICENSE_COLUMNS;
+use crate::AppError;
+
+/// Columns that identify a licence version rather than describe it.
+const VERSION_COLUMNS: [&str; 4] = ["licence_number", "valid_from", "valid_to", "is_current"];
+
+/// Rows changed in the history table by [`update_licence_history`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LicenceHistorySummary {
+    /// New licence versions
+    pub inserted: usize,
+    /// Versions whose `valid_to` or details changed
+    pub updated: usize,
+    /// Versions that no longer exist, e.g. after a backfill merged them
+    pub deleted: usize,
+}
+
+/// ST-1 columns describing a licence, carried into each version.
+fn detail_columns() -> impl Iterator<Item = &'static str> {
+    LICENSE_COLUMNS
+        .iter()
+        .copied()
+        .filter(|column| *column != "date" && *column != "licence_number")
+}
+
+/// Create the history table, or open it if it exists.
+async fn create_or_open_history_table(history_path: &Path) -> Result<DeltaTable, AppError> {
+    let st1_fields = get_schema(DeltaReportType::St1)?;
+    let field = |name: &str| {
+        st1_fields
+            .iter()
+            .find(|field| field.name() == name)
+            .cloned()
+            .ok_or_else(|| AppError::DeltaTable(format!("ST-1 schema has no column `{name}`")))
+    };
+    let date = DataType::Primitive(PrimitiveType::Date);
+    let mut columns = vec![
+        field("licence_number")?,
+        StructField::new("valid_from", date.clone(), false),
+        StructField::new("valid_to", date, true),
+        StructField::new(
+            "is_current",
+            DataType::Primitive(PrimitiveType::Boolean),
+            false,
+        ),
+    ];
+    for name in detail_columns() {
+        columns.push(field(name)?);
+    }
+    create_or_open_derived_table(history_path, columns).await
+}
+
+/// SQL computing every licence version from the `st1` table.
+///
+/// Rows are fingerprinted on their details; a licence gets a new version
+/// whenever its fingerprint differs from the previous report date's. If a
+/// licence is listed twice on one date, one of the rows is picked consistently.
+fn versions_sql() -> String {
+    let details: Vec<&str> = detail_columns().collect();
+    let fingerprint = details
+        .iter()
+        .map(|column| format!("coalesce(CAST({column} AS VARCHAR), chr(30))"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let details = details.join(", ");
+
+    format!(
+        "WITH daily AS (
+            SELECT *, ROW_NUMBER() OVER (PARTITION BY licence_number, date ORDER BY state DESC) AS day_rank
+            FROM (
+                SELECT date, licence_number, {details}, concat_ws(chr(31), {fingerprint}) AS state
+                FROM st1
+                WHERE licence_number IS NOT NULL AND date IS NOT NULL
+            )
+        ),
+        changes AS (
+            SELECT *, LAG(state) OVER (PARTITION BY licence_number ORDER BY date) AS previous_state
+            FROM daily
+            WHERE day_rank = 1
+        ),
+        versions AS (
+            SELECT *, LEAD(date) OVER (PARTITION BY licence_number ORDER BY date) AS valid_to
+            FROM changes
+            WHERE previous_state IS NULL OR previous_state <> state
+        )
+        SELECT licence_number, date AS valid_from, valid_to, valid_to IS NULL AS is_current, {details}
+        FROM versions"
+    )
+}
+
+/// Bring the `licence_history` table in line with an ST-1 table.
+///
+/// Creates the history table (with Change Data Feed enabled) on first use.
+/// The versions of every licence are recomputed and merged on
+/// (`licence_number`, `valid_from`): new versions are inserted, versions whose
+/// `valid_to` or details changed are updated, and versions that no longer
+/// exist are deleted. Nothing is committed when the history is up to date.
+///
+/// # Arguments
+/// * `st1_table` - The typed ST-1 table to derive the history from
+/// * `history_path` - Directory of the history table
+///
+/// # Returns
+/// Number of versions inserted, updated and deleted
+///
+/// # Example
+/// ```rust,ignore
+/// let st1 = deltalake::open_table("data/deltalake/st1").await?;
+/// let summary = update_licence_history(&st1, Path::new("data/deltalake/licence_history")).await?;
+/// ```
+pub async fn update_licence_history(
+    st1_table: &DeltaTable,
+    history_path: &Path,
+) -> Result<LicenceHistorySummary, AppError> {
+    let history = create_or_open_history_table(history_path).await?;
+
+    let ctx = SessionContext::new();
+    ctx.register_table("st1", Arc::new(st1_table.clone()))?;
+    let versions = ctx.sql(&versions_sql()).await?;
+
+    let columns: Vec<&str> = VERSION_COLUMNS
+        .iter()
+        .copied()
+        .chain(detail_columns())
+        .collect();
+    let metrics = merge_derived_rows(
+        history,
+        versions,
+        &["licence_number", "valid_from"],
+        &columns,
+    )
+    .await?;
+
+    let summary = LicenceHistorySummary {
+        inserted: metrics.num_target_rows_inserted,
+        updated: metrics.num_target_rows_updated,
+        deleted: metrics.num_target_rows_deleted,
+    };
+    info!(
+        "Updated licence history at {history_path:?}: {} versions inserted, {} updated, {} deleted",
+        summary.inserted, summary.updated, summary.deleted
+    );
+    Ok(summary)
+}
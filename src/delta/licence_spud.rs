@@ -0,0 +1,202 @@
+//! The derived `licence_spud` table
+//!
+//! ST-49 spud records name the licence of the well (`licence`), which ST-1
+//! listed when the licence was issued (`licence_number`). The `licence_spud`
+//! table links the two: one row per ST-49 record with the date its licence
+//! first appeared in ST-1 and the days from then to the activity. Spuds whose
+//! licence never appeared in the ST-1 table are kept and flagged, as they
+//! usually point at a gap in the loaded ST-1 history.
+//!
+//! The rows are recomputed from both tables and merged into the
+//! `licence_spud` table (see [`super::derived`]).
+
+use std::path::Path;
+use std::sync::Arc;
+
+use datafusion::prelude::SessionContext;
+use deltalake::kernel::{DataType, PrimitiveType, StructField};
+use deltalake::DeltaTable;
+use log::info;
+
+use super::derived::{create_or_open_derived_table, merge_derived_rows, row_counts};
+use super::{ensure_typed_schema, get_schema, DeltaReportType};
+use crate::AppError;
+
+/// ST-49 columns carried into each row; the first three identify it.
+const SPUD_COLUMNS: [&str; 9] = [
+    "date",
+    "well_id",
+    "activity_type",
+    "licence",
+    "well_name",
+    "licensee",
+    "activity_date",
+    "contractor_name",
+    "rig_number",
+];
+
+/// Columns computed from the ST-1 table.
+const LINK_COLUMNS: [&str; 3] = ["licence_date", "days_to_spud", "licence_in_st1"];
+
+/// Rows changed in the `licence_spud` table by [`update_licence_spud`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LicenceSpudSummary {
+    /// New spud rows
+    pub inserted: usize,
+    /// Rows whose spud details or licence link changed
+    pub updated: usize,
+    /// Rows whose spud record no longer exists
+    pub deleted: usize,
+    /// Rows in the table after the update
+    pub spuds: usize,
+    /// Rows whose licence is not in the ST-1 table
+    pub unmatched: usize,
+}
+
+/// Create the `licence_spud` table, or open it if it exists.
+async fn create_or_open_licence_spud_table(table_path: &Path) -> Result<DeltaTable, AppError> {
+    let st49_fields = get_schema(DeltaReportType::St49)?;
+    let mut columns = SPUD_COLUMNS
+        .iter()
+        .map(|name| {
+            st49_fields
+                .iter()
+                .find(|field| field.name() == name)
+                .cloned()
+                .ok_or_else(|| AppError::DeltaTable(format!("ST-49 schema has no column `{name}`")))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    columns.extend([
+        StructField::new(
+            "licence_date",
+            DataType::Primitive(PrimitiveType::Date),
+            true,
+        ),
+        StructField::new(
+            "days_to_spud",
+            DataType::Primitive(PrimitiveType::Integer),
+            true,
+        ),
+        StructField::new(
+            "licence_in_st1",
+            DataType::Primitive(PrimitiveType::Boolean),
+            false,
+        ),
+    ]);
+    create_or_open_derived_table(table_path, columns).await
+}
+
+/// SQL linking every `st49` record to its licence in the `st1` table.
+///
+/// The licence date is the first ST-1 report date listing the licence, and
+/// the days to spud count from it to the activity date (or the ST-49 report
+/// date when the activity date is missing). If a record is listed twice on
+/// one report date, one of the rows is picked consistently.
+fn licence_spud_sql() -> String {
+    let spud_columns = SPUD_COLUMNS.join(", ");
+    let selected = SPUD_COLUMNS
+        .iter()
+        .map(|column| format!("s.{column}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    format!(
+        "WITH licences AS (
+            SELECT licence_number, min(date) AS licence_date
+            FROM st1
+            WHERE licence_number IS NOT NULL AND date IS NOT NULL
+            GROUP BY licence_number
+        ),
+        spuds AS (
+            SELECT {spud_columns},
+                ROW_NUMBER() OVER (
+                    PARTITION BY date, well_id, activity_type
+                    ORDER BY activity_date DESC NULLS LAST, licence, licensee, contractor_name, rig_number
+                ) AS day_rank
+            FROM st49
+            WHERE date IS NOT NULL AND well_id IS NOT NULL AND activity_type IS NOT NULL
+        )
+        SELECT {selected},
+            l.licence_date,
+            CAST(
+                CAST(coalesce(CAST(s.activity_date AS DATE), s.date) AS INT)
+                - CAST(l.licence_date AS INT) AS INT
+            ) AS days_to_spud,
+            l.licence_number IS NOT NULL AS licence_in_st1
+        FROM spuds s
+        LEFT JOIN licences l ON trim(s.licence) = l.licence_number
+        WHERE s.day_rank = 1"
+    )
+}
+
+/// Bring the `licence_spud` table in line with the ST-1 and ST-49 tables.
+///
+/// Creates the table (with Change Data Feed enabled) on first use. The link
+/// of every ST-49 record is recomputed and merged on (`date`, `well_id`,
+/// `activity_type`): new records are inserted, records whose details or
+/// licence link changed (e.g. once a missing ST-1 day is loaded) are updated,
+/// and records no longer in the ST-49 table are deleted. Nothing is
+/// committed when the table is up to date.
+///
+/// # Arguments
+/// * `st1_table` - The typed ST-1 table with the licences
+/// * `st49_table` - The typed ST-49 table with the spuds
+/// * `table_path` - Directory or object store URI of the `licence_spud` table
+///
+/// # Returns
+/// Number of rows inserted, updated and deleted, and how many spuds are unmatched
+///
+/// # Example
+/// ```rust,ignore
+/// let st1 = deltalake::open_table("data/deltalake/st1").await?;
+/// let st49 = deltalake::open_table("data/deltalake/st49").await?;
+/// let summary = update_licence_spud(&st1, &st49, Path::new("data/deltalake/licence_spud")).await?;
+/// println!("{} of {} spuds have no ST-1 licence", summary.unmatched, summary.spuds);
+/// ```
+pub async fn update_licence_spud(
+    st1_table: &DeltaTable,
+    st49_table: &DeltaTable,
+    table_path: &Path,
+) -> Result<LicenceSpudSummary, AppError> {
+    ensure_typed_schema(st1_table, DeltaReportType::St1)?;
+    ensure_typed_schema(st49_table, DeltaReportType::St49)?;
+    let licence_spud = create_or_open_licence_spud_table(table_path).await?;
+
+    let ctx = SessionContext::new();
+    ctx.register_table("st1", Arc::new(st1_table.clone()))?;
+    ctx.register_table("st49", Arc::new(st49_table.clone()))?;
+    let rows = ctx.sql(&licence_spud_sql()).await?;
+    ctx.register_table("licence_spud", rows.clone().into_view())?;
+    let counts = ctx
+        .sql(
+            "SELECT count(*) AS spuds,
+                coalesce(sum(CASE WHEN licence_in_st1 THEN 0 ELSE 1 END), 0) AS unmatched
+            FROM licence_spud",
+        )
+        .await?
+        .collect()
+        .await?;
+
+    let columns: Vec<&str> = SPUD_COLUMNS.iter().chain(&LINK_COLUMNS).copied().collect();
+    let metrics = merge_derived_rows(
+        licence_spud,
+        rows,
+        &["date", "well_id", "activity_type"],
+        &columns,
+    )
+    .await?;
+
+    let [spuds, unmatched] = row_counts(&counts);
+    let summary = LicenceSpudSummary {
+        inserted: metrics.num_target_rows_inserted,
+        updated: metrics.num_target_rows_updated,
+        deleted: metrics.num_target_rows_deleted,
+        spuds,
+        unmatched,
+    };
+    info!(
+        "Updated licence_spud at {table_path:?}: {} rows inserted, {} updated, {} deleted; {} of {} spuds have no ST-1 licence",
+        summary.inserted, summary.updated, summary.deleted, summary.unmatched, summary.spuds
+    );
+    Ok(summary)
+}
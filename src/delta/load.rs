@@ -0,0 +1,273 @@
+//! The `load-delta` workflow: find new CSVs, load them, then maintain the table
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use deltalake::DeltaTable;
+use log::info;
+
+use super::tracking::seed_from_load_log;
+use super::{
+    create_or_open_delta_table, load_csvs_to_delta, log_loaded_csv, maintain_delta_table,
+    sha256_file, table_uri, update_licence_history, DeltaReportType, LicenceHistorySummary,
+    LoadMode, LoadSummary, LoadedFiles, MaintenanceOptions, MaintenanceReport, Partitioning,
+    StreamingOptions,
+};
+use crate::{move_to_conversion_errors, storage, AppError};
+
+/// Options for [`load_delta`], one field per `load-delta` command line flag.
+#[derive(Debug, Clone)]
+pub struct LoadDeltaOptions {
+    /// Report type stored in the table
+    pub report_type: DeltaReportType,
+    /// Directory of the delta table
+    pub table_path: PathBuf,
+    /// A single CSV file to load
+    pub csv_path: Option<PathBuf>,
+    /// Folder whose CSVs for the report type are loaded
+    pub csv_folder: Option<PathBuf>,
+    /// Audit log location, defaults to `delta_load_log.json` inside the table
+    pub log_path: Option<PathBuf>,
+    /// Delete the table and its load log before loading
+    pub recreate_table: bool,
+    /// Partition columns used when the table is created
+    pub partitioning: Partitioning,
+    /// Append rows or merge them on the natural key
+    pub load_mode: LoadMode,
+    /// Store source file, line and ingestion time with every row
+    pub lineage: bool,
+    /// Memory budget of the load and row group size of appends
+    pub streaming: StreamingOptions,
+    /// Also load files again whose loading commit a RESTORE by another tool skipped
+    pub external_restores: bool,
+    /// Optimize and vacuum steps run after the load
+    pub maintenance: MaintenanceOptions,
+    /// `licence_history` table to update after an ST-1 load
+    pub licence_history: Option<PathBuf>,
+}
+
+impl LoadDeltaOptions {
+    /// Options with no CSV inputs and the command line defaults for everything else.
+    pub fn new(report_type: DeltaReportType, table_path: impl Into<PathBuf>) -> Self {
+        Self {
+            report_type,
+            table_path: table_path.into(),
+            csv_path: None,
+            csv_folder: None,
+            log_path: None,
+            recreate_table: false,
+            partitioning: Partitioning::None,
+            load_mode: LoadMode::Append,
+            lineage: false,
+            streaming: StreamingOptions::default(),
+            external_restores: false,
+            maintenance: MaintenanceOptions::default(),
+            licence_history: None,
+        }
+    }
+
+    /// Location of the load log for these options.
+    pub fn log_path(&self) -> PathBuf {
+        self.log_path
+            .clone()
+            .unwrap_or_else(|| self.table_path.join("delta_load_log.json"))
+    }
+}
+
+/// Outcome of a [`load_delta`] run.
+#[derive(Debug)]
+pub struct LoadDeltaReport {
+    /// The table after loading and maintenance
+    pub table: DeltaTable,
+    /// Rows and partitions written by the load
+    pub summary: LoadSummary,
+    /// CSV files loaded and recorded in the load log
+    pub loaded_files: Vec<PathBuf>,
+    /// CSV files skipped because their contents are already in the table
+    pub skipped_files: Vec<PathBuf>,
+    /// CSV files moved to `data/conversion_errors` after the load failed
+    pub failed_files: Vec<PathBuf>,
+    /// Versions changed in the licence history, if it was updated
+    pub licence_history: Option<LicenceHistorySummary>,
+    /// Files optimized and vacuumed after the load
+    pub maintenance: MaintenanceReport,
+}
+
+/// Returns true if a file with the same contents is already in the table, or
+/// earlier in this run. Records the file's hash as seen.
+async fn already_loaded(
+    loaded: &LoadedFiles,
+    seen_hashes: &mut HashSet<String>,
+    path: &Path,
+) -> Result<bool, AppError> {
+    let hash = sha256_file(path)?;
+    Ok(loaded.contains(&hash).await? || !seen_hashes.insert(hash))
+}
+
+/// Load new CSV files into a delta table, then run the configured maintenance.
+///
+/// This is the `load-delta` command. CSVs whose SHA-256 is already recorded
+/// in the table (see [`LoadedFiles`]) are skipped, wherever they are stored
+/// now. With `options.external_restores`, CSVs whose loading commit a RESTORE
+/// made by another tool skipped are loaded again. The first load into a table
+/// made before loads were recorded in it records the CSVs of its load log first.
+/// The remaining files are read one at a time within the memory budget of
+/// `options.streaming`: appends write them in a single commit, merges in one
+/// commit per group of files that fits the budget. Each loaded file is
+/// appended to the JSON load log for auditing. If the batch fails, its files
+/// that no commit recorded are moved to `data/conversion_errors` and
+/// maintenance still runs.
+/// With `options.licence_history` set, the licence history table is brought
+/// up to date with the ST-1 table before maintenance.
+///
+/// # Arguments
+/// * `options` - Inputs, table location and load settings
+///
+/// # Returns
+/// The updated table and which files were loaded, skipped or failed
+///
+/// # Example
+/// ```rust,ignore
+/// let mut options = LoadDeltaOptions::new(DeltaReportType::St49, "data/deltalake/st49");
+/// options.csv_folder = Some("data/csv".into());
+/// let report = load_delta(&options).await?;
+/// ```
+pub async fn load_delta(options: &LoadDeltaOptions) -> Result<LoadDeltaReport, AppError> {
+    let table_path = options.table_path.as_path();
+    let log_path = options.log_path();
+    if options.licence_history.is_some() && options.report_type != DeltaReportType::St1 {
+        return Err(AppError::Cli(
+            "Licence history can only be derived from ST1 loads".to_string(),
+        ));
+    }
+
+    if options.recreate_table {
+        if storage::remove_all(table_uri(table_path)?).await? {
+            info!("Recreating delta table at {table_path:?}");
+        }
+        if storage::remove_all(table_uri(&log_path)?).await? {
+            info!("Removed log file at {log_path:?}");
+        }
+    }
+
+    let mut table = create_or_open_delta_table(
+        table_path,
+        options.report_type,
+        options.partitioning,
+        options.lineage,
+    )
+    .await?;
+    seed_from_load_log(&mut table, &log_path).await?;
+    let loaded = if options.external_restores {
+        LoadedFiles::with_external_restores(&table).await?
+    } else {
+        LoadedFiles::for_table(&table).await?
+    };
+    let mut seen_hashes = HashSet::new();
+
+    let mut csv_files = Vec::new();
+    let mut skipped_files = Vec::new();
+    if let Some(folder) = &options.csv_folder {
+        info!("Searching for CSV files in folder: {folder:?}");
+        let prefix = options.report_type.csv_prefix();
+
+        for entry in fs::read_dir(folder)? {
+            let path = entry?.path();
+            if let Some(filename) = path.file_name().and_then(|n| n.to_str()) {
+                if filename.contains(prefix) && filename.ends_with(".csv") {
+                    if already_loaded(&loaded, &mut seen_hashes, &path).await? {
+                        info!("Skipping already processed file: {path:?}");
+                        skipped_files.push(path);
+                    } else {
+                        info!("Found CSV file: {path:?}");
+                        csv_files.push(path);
+                    }
+                }
+            }
+        }
+    }
+    if let Some(path) = &options.csv_path {
+        if already_loaded(&loaded, &mut seen_hashes, path).await? {
+            info!("Skipping already processed file: {path:?}");
+            skipped_files.push(path.clone());
+        } else {
+            csv_files.push(path.clone());
+        }
+    }
+
+    let mut summary = LoadSummary::default();
+    let mut loaded_files = Vec::new();
+    let mut failed_files = Vec::new();
+    if !csv_files.is_empty() {
+        info!(
+            "Loading {} CSV files with a {} MiB memory budget",
+            csv_files.len(),
+            options.streaming.memory_budget / (1024 * 1024)
+        );
+        let csv_paths: Vec<&Path> = csv_files.iter().map(|p| p.as_path()).collect();
+
+        match load_csvs_to_delta(
+            &mut table,
+            options.report_type,
+            &csv_paths,
+            options.load_mode,
+            options.streaming,
+        )
+        .await
+        {
+            Ok(loaded) => {
+                info!(
+                    "Successfully loaded {} total rows ({} inserted, {} updated) from {} CSV files",
+                    loaded.rows,
+                    loaded.inserted,
+                    loaded.updated,
+                    csv_files.len()
+                );
+                for csv_path in &csv_files {
+                    log_loaded_csv(&log_path, csv_path).await?;
+                }
+                summary = loaded;
+                loaded_files = csv_files;
+            }
+            Err(e) => {
+                eprintln!("Failed to load CSV files as batch: {e}");
+                // A merge may have committed some groups of files before failing
+                table.update().await?;
+                let loaded = LoadedFiles::for_table(&table).await?;
+                for csv in csv_files {
+                    if loaded.contains(&sha256_file(&csv)?).await? {
+                        log_loaded_csv(&log_path, &csv).await?;
+                        loaded_files.push(csv);
+                        continue;
+                    }
+                    if let Err(e) = move_to_conversion_errors(&csv, &e.to_string()).await {
+                        eprintln!("Failed to move file to conversion_errors: {e}");
+                    }
+                    failed_files.push(csv);
+                }
+            }
+        }
+    } else {
+        info!("No new CSV files to process");
+    }
+
+    let licence_history = match &options.licence_history {
+        Some(history_path) => Some(update_licence_history(&table, history_path).await?),
+        None => None,
+    };
+
+    info!("Running maintenance on delta table at {table_path:?}");
+    let maintenance =
+        maintain_delta_table(&mut table, &options.maintenance, &summary.partitions).await?;
+
+    Ok(LoadDeltaReport {
+        table,
+        summary,
+        loaded_files,
+        skipped_files,
+        failed_files,
+        licence_history,
+        maintenance,
+    })
+}
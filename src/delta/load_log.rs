@@ -0,0 +1,83 @@
+//! Load log tracking which CSV files have been loaded into a delta table
+//!
+//! The log is a JSON-lines file (by default `delta_load_log.json` inside the
+//! table directory) with one entry per loaded CSV. Like the table, it may be
+//! stored under an object store URI.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::{storage, AppError};
+
+#[derive(Serialize, Deserialize, Debug)]
+struct LogEntry {
+    csv_file: String,
+    timestamp: String,
+}
+
+/// Converts a log path into a location for [`storage`].
+fn log_location(log_path: &Path) -> Result<&str, AppError> {
+    log_path
+        .to_str()
+        .ok_or_else(|| AppError::FileProcessing(format!("Invalid log path: {log_path:?}")))
+}
+
+/// Reads the log file and returns a set of processed CSV file paths.
+pub async fn read_load_log(log_path: &Path) -> Result<HashSet<String>, AppError> {
+    let Some(contents) = storage::read(log_location(log_path)?).await? else {
+        return Ok(HashSet::new());
+    };
+
+    let mut processed_files = HashSet::new();
+    for line in String::from_utf8_lossy(&contents).lines() {
+        if let Ok(entry) = serde_json::from_str::<LogEntry>(line) {
+            processed_files.insert(entry.csv_file);
+        }
+    }
+
+    Ok(processed_files)
+}
+
+/// Logs a successfully loaded CSV file.
+pub async fn log_loaded_csv(log_path: &Path, csv_path: &Path) -> Result<(), AppError> {
+    let log_entry = LogEntry {
+        csv_file: csv_path.canonicalize()?.to_string_lossy().into_owned(),
+        timestamp: Utc::now().to_rfc3339(),
+    };
+
+    let line = format!("{}\n", serde_json::to_string(&log_entry)?);
+    storage::append(log_location(log_path)?, line.as_bytes()).await
+}
+
+/// Removes the entries for CSV files matching `remove`, keeping every other line as-is.
+///
+/// Returns the number of entries removed.
+pub async fn remove_logged_csvs(
+    log_path: &Path,
+    remove: impl Fn(&Path) -> bool,
+) -> Result<usize, AppError> {
+    let location = log_location(log_path)?;
+    let Some(contents) = storage::read(location).await? else {
+        return Ok(0);
+    };
+
+    let mut kept = String::new();
+    let mut removed = 0;
+    for line in String::from_utf8_lossy(&contents).lines() {
+        match serde_json::from_str::<LogEntry>(line) {
+            Ok(entry) if remove(Path::new(&entry.csv_file)) => removed += 1,
+            _ => {
+                kept.push_str(line);
+                kept.push('\n');
+            }
+        }
+    }
+
+    if removed > 0 {
+        storage::write(location, kept.into_bytes()).await?;
+    }
+    Ok(removed)
+}
@@ -0,0 +1,400 @@
+//! Table maintenance: compaction, Z-ordering, vacuum and schema migration
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use chrono::Duration;
+use datafusion::catalog::streaming::StreamingTable;
+use datafusion::error::DataFusionError;
+use datafusion::execution::{SendableRecordBatchStream, TaskContext};
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::physical_plan::streaming::PartitionStream;
+use datafusion::prelude::SessionContext;
+use deltalake::arrow::array::RecordBatch;
+use deltalake::arrow::datatypes::{DataType as ArrowDataType, Field, Schema, SchemaRef};
+use deltalake::operations::optimize::OptimizeType;
+use deltalake::operations::write::SchemaMode;
+use deltalake::protocol::SaveMode;
+use deltalake::{DeltaOps, DeltaTable, PartitionFilter};
+use futures::StreamExt;
+use log::info;
+
+use super::tracking::seed_from_load_log;
+use super::write::{add_partition_columns, lock};
+use super::{
+    ensure_typed_schema, table_uri, typed_table_properties, validate_constraints, DeltaReportType,
+    PartitionValues, TableConstraints,
+};
+use crate::AppError;
+
+/// What [`maintain_delta_table`] runs after a load or from the `maintain` command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaintenanceOptions {
+    /// Compact small files with OPTIMIZE
+    pub optimize: bool,
+    /// Columns to Z-order by when optimizing; plain compaction when empty
+    pub z_order: Vec<String>,
+    /// Delete unreferenced files with VACUUM
+    pub vacuum: bool,
+    /// Keep unreferenced files this many hours; the table's
+    /// `delta.deletedFileRetentionDuration` (7 days by default) when unset
+    pub retention_hours: Option<u64>,
+    /// Only list the files VACUUM would delete
+    pub dry_run: bool,
+}
+
+impl Default for MaintenanceOptions {
+    fn default() -> Self {
+        Self {
+            optimize: true,
+            z_order: Vec::new(),
+            vacuum: true,
+            retention_hours: None,
+            dry_run: false,
+        }
+    }
+}
+
+impl MaintenanceOptions {
+    /// Options that run no maintenance at all.
+    pub fn skip() -> Self {
+        Self {
+            optimize: false,
+            vacuum: false,
+            ..Self::default()
+        }
+    }
+}
+
+/// Outcome of [`maintain_delta_table`].
+#[derive(Debug, Default)]
+pub struct MaintenanceReport {
+    /// Files written by OPTIMIZE
+    pub files_added: u64,
+    /// Files replaced by OPTIMIZE
+    pub files_removed: u64,
+    /// Files deleted by VACUUM, or that would be deleted on a dry run
+    pub vacuumed_files: Vec<String>,
+}
+
+/// Run the maintenance selected in `options` on a table.
+///
+/// # Arguments
+/// * `table` - Table to maintain, updated to the version written
+/// * `options` - Which steps to run and how
+/// * `partitions` - Partitions to optimize; the whole table when empty
+///
+/// # Returns
+/// The files optimized and vacuumed
+///
+/// # Example
+/// ```rust,ignore
+/// let options = MaintenanceOptions {
+///     z_order: vec!["licence_number".to_string()],
+///     retention_hours: Some(720),
+///     ..MaintenanceOptions::default()
+/// };
+/// maintain_delta_table(&mut table, &options, &BTreeSet::new()).await?;
+/// ```
+pub async fn maintain_delta_table(
+    table: &mut DeltaTable,
+    options: &MaintenanceOptions,
+    partitions: &BTreeSet<PartitionValues>,
+) -> Result<MaintenanceReport, AppError> {
+    let mut report = MaintenanceReport::default();
+    if options.optimize {
+        (report.files_added, report.files_removed) =
+            optimize_delta_table(table, partitions, &options.z_order).await?;
+    }
+    if options.vacuum {
+        report.vacuumed_files =
+            vacuum_delta_table(table, options.retention_hours, options.dry_run).await?;
+    }
+    Ok(report)
+}
+
+/// Compact the table, one partition at a time for the given partitions.
+///
+/// When no partitions are given (or the table is unpartitioned) the whole
+/// table is optimized in a single pass. With `z_order` columns the files are
+/// rewritten Z-ordered by them, so queries filtering on those columns skip
+/// more files.
+///
+/// # Returns
+/// Number of files added and removed
+pub async fn optimize_delta_table(
+    table: &mut DeltaTable,
+    partitions: &BTreeSet<PartitionValues>,
+    z_order: &[String],
+) -> Result<(u64, u64), AppError> {
+    let partition_columns = table.metadata()?.partition_columns().clone();
+    if !z_order.is_empty() {
+        let schema = table.get_schema()?;
+        for column in z_order {
+            if schema.field(column).is_none() || partition_columns.contains(column) {
+                return Err(AppError::DeltaTable(format!(
+                    "Cannot Z-order by `{column}`: it is not a data column of the table"
+                )));
+            }
+        }
+    }
+    let optimize_type = || {
+        if z_order.is_empty() {
+            OptimizeType::Compact
+        } else {
+            OptimizeType::ZOrder(z_order.to_vec())
+        }
+    };
+
+    if partition_columns.is_empty() || partitions.is_empty() {
+        let (optimized, metrics) = DeltaOps::from(table.clone())
+            .optimize()
+            .with_type(optimize_type())
+            .await?;
+        info!(
+            "Optimized table: {} files added, {} files removed",
+            metrics.num_files_added, metrics.num_files_removed
+        );
+        *table = optimized;
+        return Ok((metrics.num_files_added, metrics.num_files_removed));
+    }
+
+    let (mut added, mut removed) = (0, 0);
+    for partition in partitions {
+        let filters = partition
+            .iter()
+            .map(|(name, value)| PartitionFilter::try_from((name.as_str(), "=", value.as_str())))
+            .collect::<Result<Vec<_>, _>>()?;
+        let (optimized, metrics) = DeltaOps::from(table.clone())
+            .optimize()
+            .with_type(optimize_type())
+            .with_filters(&filters)
+            .await?;
+        info!(
+            "Optimized partition {partition:?}: {} files added, {} files removed",
+            metrics.num_files_added, metrics.num_files_removed
+        );
+        added += metrics.num_files_added;
+        removed += metrics.num_files_removed;
+        *table = optimized;
+    }
+    Ok((added, removed))
+}
+
+/// Delete data files that are no longer referenced by the table.
+///
+/// Files are kept for `retention_hours`, or the table's configured retention
+/// when unset. A retention shorter than the table's configured retention is
+/// refused, since it can break readers of older versions.
+///
+/// # Returns
+/// Paths of the files that were deleted, or would be deleted on a dry run
+pub async fn vacuum_delta_table(
+    table: &mut DeltaTable,
+    retention_hours: Option<u64>,
+    dry_run: bool,
+) -> Result<Vec<String>, AppError> {
+    let mut vacuum = DeltaOps::from(table.clone()).vacuum().with_dry_run(dry_run);
+    if let Some(hours) = retention_hours {
+        let hours = i64::try_from(hours)
+            .map_err(|_| AppError::DeltaTable(format!("Retention of {hours} hours is too long")))?;
+        vacuum = vacuum.with_retention_period(Duration::hours(hours));
+    }
+    let (vacuumed, metrics) = vacuum.await?;
+    if dry_run {
+        info!(
+            "Vacuum dry run: {} files would be deleted",
+            metrics.files_deleted.len()
+        );
+    } else {
+        info!(
+            "Vacuumed table: {} files deleted",
+            metrics.files_deleted.len()
+        );
+    }
+    *table = vacuumed;
+    Ok(metrics.files_deleted)
+}
+
+/// Outcome of upgrading a table to the typed schema.
+#[derive(Debug)]
+pub struct MigrationSummary {
+    /// Table version before the migration
+    pub from_version: Option<i64>,
+    /// Version written by the migration, `None` if the table was already typed
+    pub to_version: Option<i64>,
+    /// Number of rows rewritten
+    pub rows: usize,
+}
+
+/// Rewrite an all-string delta table into the typed schema in place.
+///
+/// The rewrite is committed as a new table version, so the previous string-typed
+/// versions stay in the history and remain readable through time travel. The
+/// rows are retyped as the delta writer reads them, so only the files being
+/// written are held in memory, and keep the table's partition columns. Every
+/// row is checked against the constraints of the report type before anything
+/// is committed. The table then gets the properties of a newly created table:
+/// the CSVs of the load log inside the table are recorded as loaded, and the
+/// CHECK constraints and Change Data Feed are set, each in a commit of its own.
+pub async fn migrate_delta_table(
+    table_path: &Path,
+    report_type: DeltaReportType,
+) -> Result<MigrationSummary, AppError> {
+    let table_uri = table_uri(table_path)?;
+    let mut table = deltalake::open_table(table_uri).await?;
+    let from_version = table.version();
+
+    if ensure_typed_schema(&table, report_type).is_ok() {
+        info!("Delta table at {table_uri} already uses the typed schema");
+        return Ok(MigrationSummary {
+            from_version,
+            to_version: None,
+            rows: 0,
+        });
+    }
+
+    seed_from_load_log(&mut table, &table_path.join("delta_load_log.json")).await?;
+    let partition_columns = table.metadata()?.partition_columns().clone();
+    let schema = migrated_schema(report_type, &partition_columns);
+    let migration = Arc::new(Migration {
+        report_type,
+        constraints: TableConstraints::for_report_type(report_type),
+        schema: schema.clone(),
+        partition_columns: partition_columns.clone(),
+        rows: AtomicUsize::new(0),
+        error: Mutex::default(),
+    });
+    let (table, stream) = DeltaOps::from(table).load().await?;
+    let rows = StreamingTable::try_new(
+        schema,
+        vec![Arc::new(MigrationRows {
+            migration: migration.clone(),
+            batches: Mutex::new(Some(stream)),
+        })],
+    )?;
+
+    info!("Rewriting {table_uri} into the typed schema");
+    let ctx = SessionContext::new();
+    let written = DeltaOps::from(table)
+        .write(Vec::new())
+        .with_input_execution_plan(Arc::new(
+            ctx.read_table(Arc::new(rows))?.into_unoptimized_plan(),
+        ))
+        .with_save_mode(SaveMode::Overwrite)
+        .with_schema_mode(SchemaMode::Overwrite)
+        .with_partition_columns(partition_columns)
+        .with_target_file_size(1_073_741_824) // 1GB in bytes
+        .await;
+    if let Some(error) = lock(&migration.error).take() {
+        return Err(error);
+    }
+    let table = DeltaOps::from(written?)
+        .set_tbl_properties()
+        .with_properties(typed_table_properties(report_type).into_iter().collect())
+        .with_raise_if_not_exists(false)
+        .await?;
+
+    Ok(MigrationSummary {
+        from_version,
+        to_version: table.version(),
+        rows: migration.rows.load(Ordering::Relaxed),
+    })
+}
+
+/// The typed schema of a report type followed by the table's partition
+/// columns, with the NOT NULL columns of new tables.
+fn migrated_schema(report_type: DeltaReportType, partition_columns: &[String]) -> SchemaRef {
+    let not_null = report_type.not_null_columns();
+    let fields = report_type
+        .arrow_schema()
+        .fields()
+        .iter()
+        .map(|field| {
+            let nullable = !not_null.contains(&field.name().as_str());
+            field.as_ref().clone().with_nullable(nullable)
+        })
+        .chain(
+            partition_columns
+                .iter()
+                .map(|name| Field::new(name, ArrowDataType::Int32, true)),
+        )
+        .collect::<Vec<_>>();
+    Arc::new(Schema::new(fields))
+}
+
+/// How the batches of a [`migrate_delta_table`] rewrite are retyped.
+#[derive(Debug)]
+struct Migration {
+    report_type: DeltaReportType,
+    constraints: TableConstraints,
+    schema: SchemaRef,
+    partition_columns: Vec<String>,
+    /// Rows retyped so far
+    rows: AtomicUsize,
+    /// Why retyping a batch failed; the write only passes on its message
+    error: Mutex<Option<AppError>>,
+}
+
+impl Migration {
+    /// Retypes a string batch, derives its partition columns and checks it
+    /// against the constraints. `index` locates its rows in constraint errors.
+    fn retype(&self, batch: RecordBatch, index: usize) -> Result<RecordBatch, AppError> {
+        let batch = self.report_type.retype_string_batch(&batch)?;
+        let batch = add_partition_columns(&batch, &self.partition_columns)?;
+        validate_constraints(
+            &self.constraints,
+            &batch,
+            &format!("batch {}", index + 1),
+            &[],
+        )?;
+        self.rows.fetch_add(batch.num_rows(), Ordering::Relaxed);
+        Ok(RecordBatch::try_new(
+            self.schema.clone(),
+            batch.columns().to_vec(),
+        )?)
+    }
+}
+
+/// The rows of a table being migrated, as a stream the delta writer reads.
+struct MigrationRows {
+    migration: Arc<Migration>,
+    batches: Mutex<Option<SendableRecordBatchStream>>,
+}
+
+impl fmt::Debug for MigrationRows {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MigrationRows")
+            .field("migration", &self.migration)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PartitionStream for MigrationRows {
+    fn schema(&self) -> &SchemaRef {
+        &self.migration.schema
+    }
+
+    /// Streams the batches once; the stream is empty when executed again.
+    fn execute(&self, _ctx: Arc<TaskContext>) -> SendableRecordBatchStream {
+        let migration = self.migration.clone();
+        let batches = futures::stream::iter(lock(&self.batches).take()).flatten();
+        let typed = batches.enumerate().map(move |(index, batch)| {
+            batch
+                .map_err(AppError::from)
+                .and_then(|batch| migration.retype(batch, index))
+                .map_err(|error| {
+                    let message = error.to_string();
+                    lock(&migration.error).get_or_insert(error);
+                    DataFusionError::Execution(message)
+                })
+        });
+        Box::pin(RecordBatchStreamAdapter::new(
+            self.migration.schema.clone(),
+            typed,
+        ))
+    }
+}
@@ -0,0 +1,612 @@
+//! Delta Lake storage for parsed ST-1 and ST-49 records
+//!
+//! This module creates typed Delta tables for each report type, loads parsed
+//! records into them and keeps them compact. It backs the `load-delta`,
+//! `reload`, `restore`, `export`, `query`, `stats`, `reconcile`, `diff`,
+//! `licence-spud`, `rig-timeline`, `companies`, `date-range --sink delta` and
+//! `migrate-delta` commands, and every operation those commands perform is
+//! available here for programs that embed the library.
+//!
+//! ## Components
+//!
+//! - **Tables**: [`create_or_open_delta_table`] with typed schemas, optional
+//!   report year/month partitioning and Change Data Feed enabled
+//! - **Loading**: [`load_delta`] runs the full `load-delta` workflow; the
+//!   lower-level [`load_csvs_to_delta`] and [`write_records`] append or merge
+//!   record batches, and [`ReportWriter`] writes reports as they are parsed;
+//!   [`reload_delta`] replaces a report date range in one commit and
+//!   [`restore_delta`] rolls a table back to an earlier version
+//! - **Export**: [`export_delta_table`] writes a table version, current or
+//!   past, to CSV, Parquet or JSON
+//! - **SQL**: [`run_query`] runs SQL over the tables, the parser CSVs and
+//!   other CSV or Parquet files; [`RawReportTable`] and the `st1_raw` and
+//!   `st49_raw` table functions read raw TXT reports without converting them;
+//!   [`register_aer_functions`] adds SQL functions decoding UWIs, elevations
+//!   and classifications
+//! - **Statistics**: [`compute_stats`] computes standard summaries such as
+//!   licences per month or spuds per contractor
+//! - **Reconciliation**: [`reconcile_delta`] compares a CSV folder with a
+//!   table and reports missing and extra rows per report date
+//! - **Diff**: [`diff_reports`] compares two reports or report date ranges
+//!   record by record and detects republished reports
+//! - **Load tracking**: [`loaded_file_hashes`] reads the SHA-256 of every loaded
+//!   source file from the table's commit metadata; [`read_load_log`] and
+//!   [`log_loaded_csv`] keep a JSON audit log alongside
+//! - **Licence history**: [`update_licence_history`] keeps a `licence_history`
+//!   table of every version of each ST-1 licence with `valid_from`/`valid_to`
+//! - **Licence to spud**: [`update_licence_spud`] keeps a `licence_spud` table
+//!   linking each ST-49 spud to its ST-1 licence, with the days in between
+//! - **Rig timeline**: [`update_rig_timeline`] keeps a `rig_timeline` table of
+//!   each rig's moves from well to well; [`read_rig_timeline`] reads it back
+//! - **Companies**: [`update_company_registry`] keeps a `company_registry`
+//!   table of the names of every BA ID, with user-supplied aliases;
+//!   [`resolve_st1_licensees`] keeps an `st1_licensees` table resolving ST-1
+//!   licensees to BA IDs
+//! - **Maintenance**: [`optimize_delta_table`], [`vacuum_delta_table`] and
+//!   [`migrate_delta_table`]
+//!
+//! Table paths may be local directories or object store URIs such as
+//! `s3://bucket/deltalake/st1`; see [`crate::storage`].
+//!
+//! ## Usage Example
+//!
+//! ```rust,ignore
+//! use aer_st1::delta::{load_delta, DeltaReportType, LoadDeltaOptions, LoadMode};
+//!
+//! let mut options = LoadDeltaOptions::new(DeltaReportType::St1, "data/deltalake/st1");
+//! options.csv_folder = Some("data/csv".into());
+//! options.load_mode = LoadMode::Merge;
+//! let report = load_delta(&options).await?;
+//! println!("Loaded {} rows from {} files", report.summary.rows, report.loaded_files.len());
+//! ```
+
+mod companies;
+mod constraints;
+mod derived;
+mod diff;
+mod export;
+mod functions;
+mod history;
+mod licence_spud;
+mod load;
+mod load_log;
+mod maintenance;
+mod query;
+mod raw;
+mod reconcile;
+mod reload;
+mod restore;
+mod rig_timeline;
+mod stats;
+mod stream;
+mod tracking;
+mod write;
+
+pub use companies::{
+    read_company_aliases, resolve_st1_licensees, update_company_registry, CompanyAlias,
+    CompanyRegistrySummary, LicenseeResolutionSummary,
+};
+pub use constraints::{validate_constraints, TableConstraints};
+pub use diff::{diff_reports, ChangedRecord, DiffRecord, DiffSide, FieldChange, ReportDiff};
+pub use export::{export_delta_table, ExportFormat, ExportOptions, ExportSummary};
+pub use functions::{aer_functions, register_aer_functions};
+pub use history::{update_licence_history, LicenceHistorySummary};
+pub use licence_spud::{update_licence_spud, LicenceSpudSummary};
+pub use load::{load_delta, LoadDeltaOptions, LoadDeltaReport};
+pub use load_log::{log_loaded_csv, read_load_log, remove_logged_csvs};
+pub use maintenance::{
+    maintain_delta_table, migrate_delta_table, optimize_delta_table, vacuum_delta_table,
+    MaintenanceOptions, MaintenanceReport, MigrationSummary,
+};
+pub use crate::parsers::lineage::{sha256_file, SourceFile};
+pub use query::{
+    default_query_tables, format_query_result, run_query, QueryFormat, QueryResult, QuerySource,
+};
+pub use raw::{register_raw_report_functions, RawReportTable};
+pub use reconcile::{reconcile_delta, DateDifference, ReconcileReport};
+pub use reload::{reload_delta, ReloadOptions, ReloadReport};
+pub use restore::{restore_delta, RestoreOptions, RestoreReport};
+pub use rig_timeline::{read_rig_timeline, update_rig_timeline, RigTimelineSummary};
+pub use stats::{compute_stats, Statistic, StatsOptions};
+pub use stream::StreamingOptions;
+pub use tracking::{
+    loaded_file_hashes, loaded_source_files, source_files_commit_properties, LoadedFiles,
+    SOURCE_FILES_KEY,
+};
+pub use write::{
+    load_csv_to_delta, load_csvs_to_delta, merge_batches_into_delta, replace_date_range,
+    write_batches_to_delta, write_records, ReportWriter,
+};
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use datafusion::scalar::ScalarValue;
+use clap::ValueEnum;
+use delta_kernel::engine::arrow_conversion::TryIntoKernel;
+use deltalake::arrow::array::RecordBatch;
+use deltalake::arrow::datatypes::SchemaRef;
+use deltalake::kernel::{DataType, PrimitiveType, StructField, StructType};
+use deltalake::protocol::SaveMode;
+use deltalake::{DeltaOps, DeltaTable, DeltaTableBuilder, TableProperty};
+use log::{info, warn};
+
+use constraints::constraint_properties;
+use crate::parsers::lineage::{
+    append_source_lineage, lineage_fields, CsvLineage, LINEAGE_COLUMNS,
+};
+use crate::parsers::record_batch::{string_batch_to_records, ArrowRecord};
+use crate::st1::License;
+use crate::st49::SpudData;
+use crate::{storage, AppError, ReportType};
+
+/// Supported report types for delta ingestion.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DeltaReportType {
+    St1,
+    St49,
+}
+
+impl From<ReportType> for DeltaReportType {
+    fn from(report_type: ReportType) -> Self {
+        match report_type {
+            ReportType::St1 => DeltaReportType::St1,
+            ReportType::St49 => DeltaReportType::St49,
+        }
+    }
+}
+
+/// Partition layout of a delta table, derived from the report date.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Partitioning {
+    /// Unpartitioned table
+    #[default]
+    None,
+    /// Partitioned by `report_year`
+    Year,
+    /// Partitioned by `report_year` and `report_month`
+    YearMonth,
+}
+
+impl Partitioning {
+    /// Names of the partition columns added to the table schema.
+    pub fn columns(self) -> &'static [&'static str] {
+        match self {
+            Partitioning::None => &[],
+            Partitioning::Year => &["report_year"],
+            Partitioning::YearMonth => &["report_year", "report_month"],
+        }
+    }
+}
+
+/// How parsed records are written into a delta table.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum LoadMode {
+    /// Append every row as-is
+    #[default]
+    Append,
+    /// Upsert rows keyed on the report's natural key
+    Merge,
+}
+
+/// Partition values touched by a load, as (column, value) pairs.
+pub type PartitionValues = Vec<(String, String)>;
+
+/// Result of writing records into a delta table.
+#[derive(Debug, Default)]
+pub struct LoadSummary {
+    /// Number of rows written
+    pub rows: usize,
+    /// Rows inserted as new records
+    pub inserted: usize,
+    /// Existing rows updated in place (merge loads only)
+    pub updated: usize,
+    /// Existing rows deleted (date range reloads only)
+    pub deleted: usize,
+    /// Partitions that received new rows (empty for unpartitioned tables)
+    pub partitions: BTreeSet<PartitionValues>,
+    /// Source files recorded in the commit metadata
+    pub source_files: Vec<SourceFile>,
+}
+
+impl DeltaReportType {
+    /// Typed Arrow schema of the records loaded for this report type.
+    pub fn arrow_schema(self) -> SchemaRef {
+        match self {
+            DeltaReportType::St1 => License::arrow_schema(),
+            DeltaReportType::St49 => SpudData::arrow_schema(),
+        }
+    }
+
+    /// Columns that uniquely identify a record, used as the MERGE key.
+    pub fn merge_keys(self) -> &'static [&'static str] {
+        match self {
+            DeltaReportType::St1 => &["date", "licence_number"],
+            DeltaReportType::St49 => &["date", "well_id", "activity_type"],
+        }
+    }
+
+    /// Columns that may not be null: the report date and the rest of the merge key.
+    pub fn not_null_columns(self) -> &'static [&'static str] {
+        self.merge_keys()
+    }
+
+    /// Named CHECK constraints of the table, as SQL expressions over its columns.
+    pub fn check_constraints(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            DeltaReportType::St1 => &[
+                (
+                    "licence_number_format",
+                    "regexp_like(licence_number, '^[0-9]{7}$')",
+                ),
+                (
+                    "date_range",
+                    "date >= DATE '1990-01-01' AND date < DATE '2100-01-01'",
+                ),
+                ("projected_depth_positive", "projected_depth > 0"),
+            ],
+            DeltaReportType::St49 => &[
+                ("licence_format", "regexp_like(licence, '^[0-9]{7}$')"),
+                (
+                    "date_range",
+                    "date >= DATE '1990-01-01' AND date < DATE '2100-01-01'",
+                ),
+                (
+                    "new_projected_total_depth_positive",
+                    "new_projected_total_depth > 0",
+                ),
+            ],
+        }
+    }
+
+    /// File name prefix of the CSVs written by the parsers for this report type.
+    pub fn csv_prefix(self) -> &'static str {
+        match self {
+            DeltaReportType::St1 => "WELLS",
+            DeltaReportType::St49 => "SPUD",
+        }
+    }
+
+    /// Reads a pipe-delimited CSV written by the parsers into a typed RecordBatch,
+    /// with the line of each row.
+    fn read_csv_batch(self, csv_path: &Path) -> Result<(RecordBatch, Vec<Option<u32>>), AppError> {
+        let (batch, lines) = match self {
+            DeltaReportType::St1 => {
+                let (records, lines) = write::read_csv_records(csv_path)?;
+                (License::to_record_batch(&records)?, lines)
+            }
+            DeltaReportType::St49 => {
+                let (records, lines) = write::read_csv_records(csv_path)?;
+                (SpudData::to_record_batch(&records)?, lines)
+            }
+        };
+
+        if batch.num_rows() == 0 {
+            return Err(AppError::DeltaTable(format!(
+                "No data in CSV file: {csv_path:?}"
+            )));
+        }
+        Ok((batch, lines))
+    }
+
+    /// Reads every CSV of this report type in a folder into the typed schema.
+    ///
+    /// Files that cannot be read are listed separately from the ones read. The
+    /// batches start with an empty one, so they carry the schema even when
+    /// there are no CSVs.
+    fn read_csv_folder(self, csv_folder: &Path) -> Result<CsvFolder, AppError> {
+        let prefix = self.csv_prefix();
+        let mut csv_files: Vec<PathBuf> = std::fs::read_dir(csv_folder)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<_, _>>()?;
+        csv_files.retain(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.contains(prefix) && name.ends_with(".csv"))
+        });
+        csv_files.sort();
+
+        let mut folder = CsvFolder {
+            batches: vec![RecordBatch::new_empty(self.arrow_schema())],
+            ..Default::default()
+        };
+        for csv_path in csv_files {
+            match self.read_csv_batch(&csv_path) {
+                Ok((batch, _)) => {
+                    folder.batches.push(batch);
+                    folder.read.push(csv_path);
+                }
+                Err(e) => {
+                    warn!("Could not read CSV file {csv_path:?}: {e}");
+                    folder.unreadable.push((csv_path, e.to_string()));
+                }
+            }
+        }
+        Ok(folder)
+    }
+
+    /// Reads a CSV to load into a table, with the line of each row for
+    /// constraint errors.
+    ///
+    /// With `source` given, lineage columns are appended. They point at the
+    /// report the CSV was written from when the CSV has a lineage sidecar
+    /// ([`CsvLineage`]), otherwise at the CSV file and the line of each row.
+    fn csv_to_record_batch(
+        self,
+        csv_path: &Path,
+        source: Option<&SourceFile>,
+    ) -> Result<write::SourceBatch, AppError> {
+        let (batch, lines) = self.read_csv_batch(csv_path)?;
+        let batch = match source {
+            Some(source) => match CsvLineage::read(csv_path)
+                .filter(|report| report.source_lines.len() == batch.num_rows())
+            {
+                Some(report) => append_source_lineage(
+                    &batch,
+                    &report.source,
+                    &report.source_lines,
+                    Some(&report.parser_version),
+                )?,
+                // The parser version that wrote the CSV is not known
+                None => append_source_lineage(&batch, source, &lines, None)?,
+            },
+            None => batch,
+        };
+        Ok(write::SourceBatch {
+            batch,
+            source_name: csv_path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            source_lines: lines,
+        })
+    }
+
+    /// Converts a batch read from an all-string table into the typed schema.
+    fn retype_string_batch(self, batch: &RecordBatch) -> Result<RecordBatch, AppError> {
+        Ok(match self {
+            DeltaReportType::St1 => {
+                License::to_record_batch(&string_batch_to_records::<License>(batch)?)?
+            }
+            DeltaReportType::St49 => {
+                SpudData::to_record_batch(&string_batch_to_records::<SpudData>(batch)?)?
+            }
+        })
+    }
+}
+
+/// The CSVs of a report type in a folder, read by [`DeltaReportType::read_csv_folder`].
+#[derive(Debug, Default)]
+struct CsvFolder {
+    /// CSV files read
+    read: Vec<PathBuf>,
+    /// CSV files that could not be read, with the reason
+    unreadable: Vec<(PathBuf, String)>,
+    /// Rows of the files read
+    batches: Vec<RecordBatch>,
+}
+
+/// Returns the schema for the given report type.
+///
+/// The columns mirror the typed Arrow schema produced by the parsers: Date for
+/// report dates, Timestamp (without time zone) for spud activity times, Double
+/// for elevations and depths and Integer for rig numbers. The
+/// [`not_null_columns`](DeltaReportType::not_null_columns) are NOT NULL.
+fn get_schema(report_type: DeltaReportType) -> Result<Vec<StructField>, AppError> {
+    let schema: StructType = report_type.arrow_schema().as_ref().try_into_kernel()?;
+    Ok(schema
+        .fields()
+        .map(|field| {
+            let nullable = !report_type.not_null_columns().contains(&field.name().as_str());
+            StructField::new(field.name(), field.data_type().clone(), nullable)
+        })
+        .collect())
+}
+
+/// Properties of tables in the typed schema: the CHECK constraints of their
+/// report type and the change data feed.
+fn typed_table_properties(report_type: DeltaReportType) -> Vec<(String, String)> {
+    let mut properties = constraint_properties(report_type);
+    properties.push((
+        TableProperty::EnableChangeDataFeed.as_ref().to_string(),
+        "true".to_string(),
+    ));
+    properties
+}
+
+/// Delta fields of the lineage columns.
+fn lineage_schema() -> Result<Vec<StructField>, AppError> {
+    lineage_fields()
+        .iter()
+        .map(|field| Ok(field.try_into_kernel()?))
+        .collect()
+}
+
+/// Whether the table stores lineage columns.
+pub fn has_lineage(table: &DeltaTable) -> bool {
+    table.schema().is_some_and(|schema| {
+        LINEAGE_COLUMNS
+            .iter()
+            .all(|name| schema.field(name).is_some())
+    })
+}
+
+/// Fails if the table does not have the typed columns for the report type,
+/// e.g. a table created before typed schemas that still stores every column as a string.
+fn ensure_typed_schema(table: &DeltaTable, report_type: DeltaReportType) -> Result<(), AppError> {
+    let schema = table
+        .schema()
+        .ok_or_else(|| AppError::DeltaTable("Failed to get table schema".to_string()))?;
+
+    for expected in get_schema(report_type)? {
+        match schema.field(expected.name()) {
+            Some(field) if field.data_type() == expected.data_type() => {}
+            Some(field) => {
+                return Err(AppError::DeltaTable(format!(
+                    "Column `{}` is stored as {:?} but {:?} is expected; run `migrate-delta` to upgrade the table",
+                    expected.name(),
+                    field.data_type(),
+                    expected.data_type()
+                )))
+            }
+            None => {
+                return Err(AppError::DeltaTable(format!(
+                    "Delta table is missing column `{}`",
+                    expected.name()
+                )))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Which version of a table to read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TableVersion {
+    /// The latest version
+    #[default]
+    Latest,
+    /// A specific version number
+    Version(i64),
+    /// The latest version committed at or before this time
+    AsOf(DateTime<Utc>),
+}
+
+/// Open an existing delta table at the given version.
+///
+/// # Arguments
+/// * `table_path` - Directory or object store URI of the delta table
+/// * `version` - Version to load
+///
+/// # Returns
+/// The table at that version, or an error if the table or version does not
+/// exist, including a time before the table's first commit
+pub async fn open_delta_table_at(
+    table_path: &Path,
+    version: TableVersion,
+) -> Result<DeltaTable, AppError> {
+    let builder = DeltaTableBuilder::from_valid_uri(table_uri(table_path)?)?;
+    let builder = match version {
+        TableVersion::Latest => builder,
+        TableVersion::Version(version) => builder.with_version(version),
+        TableVersion::AsOf(timestamp) => builder.with_timestamp(timestamp),
+    };
+    let table = builder.load().await?;
+
+    // deltalake falls back to the first version for times before it was committed
+    if let TableVersion::AsOf(timestamp) = version {
+        let committed = table
+            .history(Some(1))
+            .await?
+            .first()
+            .and_then(|commit| commit.timestamp);
+        if committed.is_some_and(|committed| committed > timestamp.timestamp_millis()) {
+            return Err(AppError::DeltaTable(format!(
+                "Delta table at {table_path:?} has no version as of {timestamp}"
+            )));
+        }
+    }
+    Ok(table)
+}
+
+/// A report date as a DataFusion literal of the `date` column's type.
+pub(super) fn date_scalar(date: NaiveDate) -> ScalarValue {
+    let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap_or_default();
+    ScalarValue::Date32(Some((date - epoch).num_days() as i32))
+}
+
+/// Converts a table path into the URI string expected by deltalake.
+///
+/// Object store URIs such as `s3://bucket/st1` are passed through as they are,
+/// with their schemes registered.
+fn table_uri(table_path: &Path) -> Result<&str, AppError> {
+    storage::register_object_stores();
+    table_path
+        .to_str()
+        .ok_or_else(|| AppError::DeltaTable(format!("Invalid table path: {table_path:?}")))
+}
+
+/// Create a delta table at the given path with the appropriate schema.
+/// If the table exists, open it.
+///
+/// New tables have Change Data Feed enabled, so consumers can read only the
+/// rows each commit changed, and get the NOT NULL columns and CHECK
+/// constraints of the report type. Existing tables keep their constraints.
+///
+/// `partitioning` only applies when the table is created; an existing table
+/// keeps the partition columns it was created with. `lineage` adds the
+/// lineage columns, to a new table or as a schema change to an existing one.
+///
+/// # Arguments
+/// * `table_path` - Directory or object store URI of the delta table
+/// * `report_type` - Report type whose typed schema the table uses
+/// * `partitioning` - Partition columns to create the table with
+/// * `lineage` - Whether the table should have lineage columns
+///
+/// # Returns
+/// The opened table, or an error if an existing table is not typed yet
+pub async fn create_or_open_delta_table(
+    table_path: &Path,
+    report_type: DeltaReportType,
+    partitioning: Partitioning,
+    lineage: bool,
+) -> Result<DeltaTable, AppError> {
+    let table_uri = table_uri(table_path)?;
+
+    if storage::delta_table_exists(table_uri).await? {
+        let mut table = deltalake::open_table(table_uri).await?;
+        ensure_typed_schema(&table, report_type)?;
+        let existing = table.metadata()?.partition_columns();
+        if partitioning != Partitioning::None && existing.as_slice() != partitioning.columns() {
+            warn!(
+                "Delta table at {table_uri} is partitioned by {existing:?}; ignoring requested partitioning {partitioning:?}"
+            );
+        }
+        if lineage && !has_lineage(&table) {
+            info!("Adding lineage columns to delta table at {table_uri}");
+            table = DeltaOps(table)
+                .add_columns()
+                .with_fields(lineage_schema()?)
+                .await?;
+        }
+        Ok(table)
+    } else {
+        if !storage::is_object_store_uri(table_uri) {
+            if let Some(parent) = table_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+        let mut columns = get_schema(report_type)?;
+        columns.extend(partitioning.columns().iter().map(|name| {
+            StructField::new(
+                name.to_string(),
+                DataType::Primitive(PrimitiveType::Integer),
+                true,
+            )
+        }));
+        if lineage {
+            columns.extend(lineage_schema()?);
+        }
+
+        let ops = DeltaOps::try_from_uri(table_uri).await?;
+        let table = ops
+            .create()
+            .with_save_mode(SaveMode::Ignore)
+            .with_columns(columns)
+            .with_partition_columns(partitioning.columns().to_vec())
+            .with_configuration(
+                typed_table_properties(report_type)
+                    .into_iter()
+                    .chain([(
+                        tracking::LOAD_LOG_SEEDED_PROPERTY.to_string(),
+                        "true".to_string(),
+                    )])
+                    .map(|(key, value)| (key, Some(value))),
+            )
+            .with_raise_if_key_not_exists(false)
+            .await?;
+        Ok(table)
+    }
+}
@@ -0,0 +1,244 @@
+//! Running SQL over delta tables and CSV or Parquet files with DataFusion
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use clap::ValueEnum;
+use datafusion::execution::context::SQLOptions;
+use datafusion::prelude::{CsvReadOptions, ParquetReadOptions, SessionContext};
+use datafusion::sql::parser::DFParser;
+use deltalake::arrow::array::RecordBatch;
+use deltalake::arrow::csv::WriterBuilder;
+use deltalake::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use deltalake::arrow::json::LineDelimitedWriter;
+use deltalake::arrow::util::pretty::pretty_format_batches;
+use log::info;
+
+use super::{
+    open_delta_table_at, register_aer_functions, register_raw_report_functions, table_uri,
+    DeltaReportType, RawReportTable, TableVersion,
+};
+use crate::{storage, AppError};
+
+/// How [`format_query_result`] prints rows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum QueryFormat {
+    /// An aligned text table
+    Table,
+    /// Pipe-delimited CSV with a header row, like the parser output
+    Csv,
+    /// Newline-delimited JSON objects
+    Json,
+}
+
+/// Data registered as a named table for [`run_query`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QuerySource {
+    /// Latest version of a delta table
+    Delta(PathBuf),
+    /// Parser CSVs of a report type in a folder, in the typed table schema,
+    /// read when a query scans them
+    ReportCsv(DeltaReportType, PathBuf),
+    /// A pipe-delimited CSV file with a header row, or a folder of them, read as text
+    Csv(PathBuf),
+    /// A Parquet file, or a folder of them
+    Parquet(PathBuf),
+}
+
+/// Rows returned by one SQL statement.
+#[derive(Debug, Clone)]
+pub struct QueryResult {
+    /// Columns of the result, also when it has no rows
+    pub schema: SchemaRef,
+    /// The rows
+    pub batches: Vec<RecordBatch>,
+}
+
+impl QueryResult {
+    /// Number of rows returned.
+    pub fn num_rows(&self) -> usize {
+        self.batches.iter().map(RecordBatch::num_rows).sum()
+    }
+}
+
+/// The tables `query` registers when they exist: `st1` and `st49` from the
+/// delta tables, `st1_csv` and `st49_csv` from the parser CSVs.
+///
+/// # Arguments
+/// * `st1_table` - Location of the ST-1 delta table
+/// * `st49_table` - Location of the ST-49 delta table
+/// * `csv_folder` - Folder of parser CSVs
+///
+/// # Returns
+/// Names and sources of the tables that exist
+pub async fn default_query_tables(
+    st1_table: &Path,
+    st49_table: &Path,
+    csv_folder: &Path,
+) -> Result<Vec<(String, QuerySource)>, AppError> {
+    let mut tables = Vec::new();
+    for (name, table_path) in [("st1", st1_table), ("st49", st49_table)] {
+        if storage::delta_table_exists(table_uri(table_path)?).await? {
+            tables.push((
+                name.to_string(),
+                QuerySource::Delta(table_path.to_path_buf()),
+            ));
+        } else {
+            info!("No delta table at {table_path:?}; `{name}` is not registered");
+        }
+    }
+    if csv_folder.is_dir() {
+        for (name, report_type) in [
+            ("st1_csv", DeltaReportType::St1),
+            ("st49_csv", DeltaReportType::St49),
+        ] {
+            tables.push((
+                name.to_string(),
+                QuerySource::ReportCsv(report_type, csv_folder.to_path_buf()),
+            ));
+        }
+    }
+    Ok(tables)
+}
+
+/// Register each source under its name in a new DataFusion session, along
+/// with the `st1_raw` and `st49_raw` table functions and the AER SQL functions.
+async fn query_context(tables: &[(String, QuerySource)]) -> Result<SessionContext, AppError> {
+    let ctx = SessionContext::new();
+    register_raw_report_functions(&ctx);
+    register_aer_functions(&ctx);
+    for (name, source) in tables {
+        info!("Registering {source:?} as `{name}`");
+        match source {
+            QuerySource::Delta(table_path) => {
+                let table = open_delta_table_at(table_path, TableVersion::Latest).await?;
+                ctx.register_table(name.as_str(), Arc::new(table))?;
+            }
+            QuerySource::ReportCsv(report_type, csv_folder) => {
+                // Read when a query scans it, so other queries parse no CSV
+                let folder = source_location(&ctx, csv_folder)?;
+                let table = RawReportTable::try_new_csv(*report_type, folder)?;
+                ctx.register_table(name.as_str(), Arc::new(table))?;
+            }
+            QuerySource::Csv(path) => {
+                let location = source_location(&ctx, path)?;
+                let options = CsvReadOptions::new()
+                    .has_header(true)
+                    .delimiter(b'|')
+                    .file_extension(".csv");
+                // Every column is text, so identifiers keep their leading zeros
+                let inferred = ctx.read_csv(location, options.clone()).await?;
+                let schema = Schema::new(
+                    inferred
+                        .schema()
+                        .fields()
+                        .iter()
+                        .map(|field| Field::new(field.name(), DataType::Utf8, true))
+                        .collect::<Vec<_>>(),
+                );
+                ctx.register_csv(name, location, options.schema(&schema))
+                    .await?;
+            }
+            QuerySource::Parquet(path) => {
+                let options = ParquetReadOptions::default();
+                ctx.register_parquet(name, source_location(&ctx, path)?, options)
+                    .await?;
+            }
+        }
+    }
+    Ok(ctx)
+}
+
+/// A file or folder location for DataFusion, registering its object store.
+fn source_location<'a>(ctx: &SessionContext, path: &'a Path) -> Result<&'a str, AppError> {
+    let location = path
+        .to_str()
+        .ok_or_else(|| AppError::Cli(format!("Invalid path: {path:?}")))?;
+    if storage::is_object_store_uri(location) {
+        storage::register_with_session(ctx, location)?;
+    }
+    Ok(location)
+}
+
+/// Run SQL statements over named tables.
+///
+/// `sql` may hold several statements separated by `;`, e.g. a file from
+/// `queries/`. Besides `tables`, raw TXT reports can be read with
+/// `st1_raw('folder')` and `st49_raw('folder')`, and fields decoded with the
+/// functions of [`register_aer_functions`], e.g. `uwi_meridian(well_id)`.
+/// Statements that modify data (`INSERT`, `UPDATE`, `DELETE`, `COPY`) are
+/// refused, so a query never changes a table; `CREATE VIEW` and other
+/// definitions only last for the session.
+///
+/// # Arguments
+/// * `tables` - Names and sources of the tables to query
+/// * `sql` - The SQL statements
+///
+/// # Returns
+/// The rows of each statement, in order
+///
+/// # Example
+/// ```rust,ignore
+/// let tables = vec![("st1".to_string(), QuerySource::Delta("data/deltalake/st1".into()))];
+/// let results = run_query(&tables, "SELECT licensee, count(*) FROM st1 GROUP BY licensee").await?;
+/// print!("{}", format_query_result(&results[0], QueryFormat::Table)?);
+/// ```
+pub async fn run_query(
+    tables: &[(String, QuerySource)],
+    sql: &str,
+) -> Result<Vec<QueryResult>, AppError> {
+    let ctx = query_context(tables).await?;
+    let read_only = SQLOptions::new().with_allow_dml(false);
+
+    let mut results = Vec::new();
+    for statement in DFParser::parse_sql(sql)? {
+        let plan = ctx.state().statement_to_plan(statement).await?;
+        read_only.verify_plan(&plan)?;
+        let df = ctx.execute_logical_plan(plan).await?;
+        let schema = df.schema().inner().clone();
+        let batches = df.collect().await?;
+        results.push(QueryResult { schema, batches });
+    }
+    Ok(results)
+}
+
+/// Print the rows of a query result.
+///
+/// # Arguments
+/// * `result` - Rows returned by [`run_query`]
+/// * `format` - Text table, CSV or JSON
+///
+/// # Returns
+/// The formatted rows; the table and CSV formats include the column names
+/// even when there are no rows
+pub fn format_query_result(result: &QueryResult, format: QueryFormat) -> Result<String, AppError> {
+    // Starts the output with the header when there are no rows
+    let header = RecordBatch::new_empty(result.schema.clone());
+    let output = match format {
+        QueryFormat::Table => {
+            let mut batches = vec![header];
+            batches.extend(result.batches.iter().cloned());
+            format!("{}\n", pretty_format_batches(&batches)?)
+        }
+        QueryFormat::Csv => {
+            let mut writer = WriterBuilder::new()
+                .with_header(true)
+                .with_delimiter(b'|')
+                .build(Vec::new());
+            writer.write(&header)?;
+            for batch in &result.batches {
+                writer.write(batch)?;
+            }
+            String::from_utf8_lossy(&writer.into_inner()).into_owned()
+        }
+        QueryFormat::Json => {
+            let mut writer = LineDelimitedWriter::new(Vec::new());
+            for batch in &result.batches {
+                writer.write(batch)?;
+            }
+            writer.finish()?;
+            String::from_utf8_lossy(&writer.into_inner()).into_owned()
+        }
+    };
+    Ok(output)
+}
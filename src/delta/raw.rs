@@ -0,0 +1,451 @@
+//! Querying raw AER TXT reports with SQL, parsing them as they are read
+//!
+//! `st1_raw('data/txt')` and `st49_raw('data/txt')` are DataFusion table
+//! functions over a folder of downloaded `WELLS*.TXT` or `SPUD*.txt` files,
+//! local or at an object store URI. Rows have the typed schema of the parser
+//! CSVs and delta tables. Each file is read and parsed when the query reads
+//! it, one partition per file, and only the selected columns are returned.
+//! Filters on `date` skip files whose name carries a report date outside the
+//! filter (`WELLS01022025.TXT` is the report of 2 January 2025); files without
+//! a full date in their name are always read.
+//!
+//! [`RawReportTable::try_new_csv`] reads the CSVs the parsers wrote from the
+//! reports the same way, one file per partition when a query scans them.
+//! The `st1_csv` and `st49_csv` tables of `query` use it, so a query only
+//! reads the CSVs of the dates it selects.
+
+use std::any::Any;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{Days, NaiveDate};
+use datafusion::catalog::{Session, TableFunctionImpl, TableProvider};
+use datafusion::common::{plan_err, ScalarValue};
+use datafusion::datasource::TableType;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::execution::TaskContext;
+use datafusion::logical_expr::{Between, BinaryExpr, Expr, Operator, TableProviderFilterPushDown};
+use datafusion::physical_expr::EquivalenceProperties;
+use datafusion::physical_plan::empty::EmptyExec;
+use datafusion::physical_plan::execution_plan::{Boundedness, EmissionType};
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::physical_plan::{
+    DisplayAs, DisplayFormatType, ExecutionPlan, Partitioning, PlanProperties,
+    SendableRecordBatchStream,
+};
+use datafusion::prelude::SessionContext;
+use deltalake::arrow::array::RecordBatch;
+use deltalake::arrow::datatypes::SchemaRef;
+use log::warn;
+
+use super::DeltaReportType;
+use crate::storage::{self, FetchedDir};
+use crate::{parse_file_to_batch, AppError, ReportType};
+
+/// Register the `st1_raw` and `st49_raw` table functions with a session.
+///
+/// # Example
+/// ```rust,ignore
+/// let ctx = SessionContext::new();
+/// register_raw_report_functions(&ctx);
+/// let df = ctx
+///     .sql("SELECT licensee, count(*) FROM st1_raw('data/txt') GROUP BY 1")
+///     .await?;
+/// ```
+pub fn register_raw_report_functions(ctx: &SessionContext) {
+    for report_type in [DeltaReportType::St1, DeltaReportType::St49] {
+        ctx.register_udtf(
+            report_type.raw_function_name(),
+            Arc::new(RawReportFunction(report_type)),
+        );
+    }
+}
+
+impl DeltaReportType {
+    /// Name of the table function reading raw TXT reports of this type.
+    fn raw_function_name(self) -> &'static str {
+        match self {
+            DeltaReportType::St1 => "st1_raw",
+            DeltaReportType::St49 => "st49_raw",
+        }
+    }
+
+    /// Extension of the raw TXT reports of this type, as the parsers open them.
+    fn txt_extension(self) -> &'static str {
+        match self {
+            DeltaReportType::St1 => "TXT",
+            DeltaReportType::St49 => "txt",
+        }
+    }
+}
+
+/// `st1_raw(folder)` and `st49_raw(folder)`.
+#[derive(Debug)]
+struct RawReportFunction(DeltaReportType);
+
+impl TableFunctionImpl for RawReportFunction {
+    fn call(&self, args: &[Expr]) -> DataFusionResult<Arc<dyn TableProvider>> {
+        let name = self.0.raw_function_name();
+        let folder = match args {
+            [Expr::Literal(value, _)] => value.try_as_str().flatten(),
+            _ => None,
+        };
+        let Some(folder) = folder else {
+            return plan_err!(
+                "{name} takes the folder of TXT reports as a string, e.g. {name}('data/txt')"
+            );
+        };
+        let table = RawReportTable::try_new(self.0, folder)
+            .map_err(|e| DataFusionError::External(Box::new(e)))?;
+        Ok(Arc::new(table))
+    }
+}
+
+/// Which files of a report type a [`RawReportTable`] reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReportFormat {
+    /// `WELLS*.TXT` or `SPUD*.txt` reports, parsed as they are read
+    Txt,
+    /// `*_WELLS.csv` or `*_SPUD.csv` files written by the parsers
+    Csv,
+}
+
+/// A folder of raw TXT reports of one type, or of the CSVs parsed from
+/// them, as a DataFusion table.
+#[derive(Debug, Clone)]
+pub struct RawReportTable {
+    report_type: DeltaReportType,
+    format: ReportFormat,
+    /// Local folder or object store URI of the reports
+    folder: String,
+}
+
+impl RawReportTable {
+    /// A table over the reports of a type in a folder, which is listed when
+    /// a query scans the table.
+    ///
+    /// # Arguments
+    /// * `report_type` - Which reports to read: `WELLS*.TXT` or `SPUD*.txt` files
+    /// * `folder` - Local folder or object store URI of downloaded or extracted TXT reports
+    ///
+    /// # Returns
+    /// The table, or an error if the folder is not a directory or not a valid URI
+    pub fn try_new(report_type: DeltaReportType, folder: &str) -> Result<Self, AppError> {
+        Self::with_format(report_type, ReportFormat::Txt, folder)
+    }
+
+    /// A table over the parser CSVs of a type in a folder, which is listed
+    /// when a query scans the table. CSVs that cannot be read are skipped
+    /// with a warning.
+    ///
+    /// # Arguments
+    /// * `report_type` - Which CSVs to read: `*WELLS*.csv` or `*SPUD*.csv` files
+    /// * `folder` - Local folder or object store URI of parser CSVs
+    ///
+    /// # Returns
+    /// The table, or an error if the folder is not a directory or not a valid URI
+    pub fn try_new_csv(report_type: DeltaReportType, folder: &str) -> Result<Self, AppError> {
+        Self::with_format(report_type, ReportFormat::Csv, folder)
+    }
+
+    fn with_format(
+        report_type: DeltaReportType,
+        format: ReportFormat,
+        folder: &str,
+    ) -> Result<Self, AppError> {
+        if storage::is_object_store_uri(folder) {
+            storage::object_store(folder)?;
+        } else if !Path::new(folder).is_dir() {
+            return Err(AppError::Cli(format!(
+                "{folder:?} is not a folder of {format:?} files"
+            )));
+        }
+        Ok(Self {
+            report_type,
+            format,
+            folder: folder.to_string(),
+        })
+    }
+
+    /// Report files of the table's type in the folder, with the date in
+    /// their name if it has one.
+    async fn files(&self) -> Result<Vec<(String, Option<NaiveDate>)>, AppError> {
+        let prefix = self.report_type.csv_prefix();
+        let extension = self.report_type.txt_extension();
+        Ok(storage::list_files(&self.folder)
+            .await?
+            .into_iter()
+            .filter_map(|name| match self.format {
+                ReportFormat::Txt => {
+                    let path = Path::new(&name);
+                    if path.extension().and_then(|ext| ext.to_str()) != Some(extension) {
+                        return None;
+                    }
+                    let date_part = path.file_stem()?.to_str()?.strip_prefix(prefix)?;
+                    let date = filename_date(date_part, "%m%d%Y");
+                    Some((name, date))
+                }
+                ReportFormat::Csv => {
+                    if !(name.contains(prefix) && name.ends_with(".csv")) {
+                        return None;
+                    }
+                    // Named `YYYYMMDD_WELLS.csv` by the parsers
+                    let date = name
+                        .split_once('_')
+                        .and_then(|(date_part, _)| filename_date(date_part, "%Y%m%d"));
+                    Some((name, date))
+                }
+            })
+            .collect())
+    }
+}
+
+/// Report date in a file name, eight digits in the given format.
+fn filename_date(date_part: &str, format: &str) -> Option<NaiveDate> {
+    if date_part.len() != 8 || !date_part.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    NaiveDate::parse_from_str(date_part, format).ok()
+}
+
+/// Inclusive report date bounds of a filter on the `date` column, if it is one.
+fn date_bounds(filter: &Expr) -> Option<(Option<NaiveDate>, Option<NaiveDate>)> {
+    match filter {
+        Expr::BinaryExpr(BinaryExpr { left, op, right }) => {
+            let (op, date) = match (date_column(left), date_literal(right)) {
+                (true, Some(date)) => (*op, date),
+                _ => match (date_literal(left), date_column(right)) {
+                    (Some(date), true) => (op.swap()?, date),
+                    _ => return None,
+                },
+            };
+            match op {
+                Operator::Eq => Some((Some(date), Some(date))),
+                Operator::GtEq => Some((Some(date), None)),
+                Operator::Gt => Some((date.checked_add_days(Days::new(1)), None)),
+                Operator::LtEq => Some((None, Some(date))),
+                Operator::Lt => Some((None, date.checked_sub_days(Days::new(1)))),
+                _ => None,
+            }
+        }
+        Expr::Between(Between {
+            expr,
+            negated: false,
+            low,
+            high,
+        }) if date_column(expr) => Some((Some(date_literal(low)?), Some(date_literal(high)?))),
+        _ => None,
+    }
+}
+
+fn date_column(expr: &Expr) -> bool {
+    matches!(expr, Expr::Column(column) if column.name == "date")
+}
+
+fn date_literal(expr: &Expr) -> Option<NaiveDate> {
+    match expr {
+        Expr::Literal(ScalarValue::Date32(Some(days)), _) => {
+            NaiveDate::default().checked_add_signed(chrono::Duration::days(i64::from(*days)))
+        }
+        _ => None,
+    }
+}
+
+#[async_trait]
+impl TableProvider for RawReportTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.report_type.arrow_schema()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    fn supports_filters_pushdown(
+        &self,
+        filters: &[&Expr],
+    ) -> DataFusionResult<Vec<TableProviderFilterPushDown>> {
+        // File names only narrow down the files; every row is still filtered
+        Ok(filters
+            .iter()
+            .map(|filter| match date_bounds(filter) {
+                Some(_) => TableProviderFilterPushDown::Inexact,
+                None => TableProviderFilterPushDown::Unsupported,
+            })
+            .collect())
+    }
+
+    async fn scan(
+        &self,
+        _state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        _limit: Option<usize>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let mut start = None::<NaiveDate>;
+        let mut end = None::<NaiveDate>;
+        for (low, high) in filters.iter().filter_map(date_bounds) {
+            start = start.max(low);
+            end = match (end, high) {
+                (Some(end), Some(high)) => Some(end.min(high)),
+                (end, high) => end.or(high),
+            };
+        }
+        let files: Vec<String> = self
+            .files()
+            .await
+            .map_err(|e| DataFusionError::External(Box::new(e)))?
+            .into_iter()
+            .filter(|(_, date)| match date {
+                Some(date) => {
+                    start.is_none_or(|start| *date >= start) && end.is_none_or(|end| *date <= end)
+                }
+                None => true,
+            })
+            .map(|(name, _)| name)
+            .collect();
+
+        let schema = match projection {
+            Some(projection) => Arc::new(self.schema().project(projection)?),
+            None => self.schema(),
+        };
+        if files.is_empty() {
+            return Ok(Arc::new(EmptyExec::new(schema)));
+        }
+        let properties = PlanProperties::new(
+            EquivalenceProperties::new(schema.clone()),
+            Partitioning::UnknownPartitioning(files.len()),
+            EmissionType::Incremental,
+            Boundedness::Bounded,
+        );
+        Ok(Arc::new(RawReportExec {
+            report_type: self.report_type,
+            format: self.format,
+            folder: self.folder.clone(),
+            files,
+            projection: projection.cloned(),
+            schema,
+            properties,
+        }))
+    }
+}
+
+/// Parses one report file, or reads one parser CSV, per partition.
+#[derive(Debug)]
+struct RawReportExec {
+    report_type: DeltaReportType,
+    format: ReportFormat,
+    folder: String,
+    /// File names, one per partition
+    files: Vec<String>,
+    projection: Option<Vec<usize>>,
+    schema: SchemaRef,
+    properties: PlanProperties,
+}
+
+impl DisplayAs for RawReportExec {
+    fn fmt_as(&self, _format: DisplayFormatType, f: &mut fmt::Formatter) -> fmt::Result {
+        let stems: Vec<&str> = self
+            .files
+            .iter()
+            .map(|name| {
+                name.rsplit_once('.')
+                    .map_or(name.as_str(), |(stem, _)| stem)
+            })
+            .collect();
+        write!(
+            f,
+            "{}: report_type={:?}, files=[{}]",
+            self.name(),
+            self.report_type,
+            stems.join(", ")
+        )
+    }
+}
+
+impl ExecutionPlan for RawReportExec {
+    fn name(&self) -> &str {
+        match self.format {
+            ReportFormat::Txt => "RawReportExec",
+            ReportFormat::Csv => "ReportCsvExec",
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn properties(&self) -> &PlanProperties {
+        &self.properties
+    }
+
+    fn children(&self) -> Vec<&Arc<dyn ExecutionPlan>> {
+        vec![]
+    }
+
+    fn with_new_children(
+        self: Arc<Self>,
+        _children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        Ok(self)
+    }
+
+    fn execute(
+        &self,
+        partition: usize,
+        _context: Arc<TaskContext>,
+    ) -> DataFusionResult<SendableRecordBatchStream> {
+        let csv_report_type = self.report_type;
+        let report_type = match self.report_type {
+            DeltaReportType::St1 => ReportType::St1,
+            DeltaReportType::St49 => ReportType::St49,
+        };
+        let name = self.files[partition].clone();
+        let format = self.format;
+        let folder = self.folder.clone();
+        let projection = self.projection.clone();
+        let schema = self.schema.clone();
+        let batch = async move {
+            let parsed = async {
+                let input = FetchedDir::new(&folder)?;
+                let path = input.fetch(&name).await?;
+                if format == ReportFormat::Csv {
+                    let (batch, _) = csv_report_type.read_csv_batch(&path)?;
+                    return Ok(batch);
+                }
+                let stem = path.file_stem().and_then(|stem| stem.to_str());
+                parse_file_to_batch(
+                    report_type,
+                    stem.unwrap_or_default(),
+                    input.path(),
+                    None,
+                    None,
+                    None,
+                    false,
+                )
+                .await
+            };
+            let batch = match parsed.await {
+                Ok(batch) => batch,
+                Err(e) => {
+                    warn!("Could not read {folder}/{name}, skipping: {e}");
+                    return Ok(RecordBatch::new_empty(schema));
+                }
+            };
+            match projection {
+                Some(projection) => Ok(batch.project(&projection)?),
+                None => Ok(batch),
+            }
+        };
+        Ok(Box::pin(RecordBatchStreamAdapter::new(
+            self.schema.clone(),
+            futures::stream::once(batch),
+        )))
+    }
+}
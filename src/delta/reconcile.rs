@@ -0,0 +1,148 @@
+//! Comparing a folder of parser CSVs with a delta table
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use chrono::NaiveDate;
+use datafusion::prelude::SessionContext;
+use deltalake::arrow::array::{Array, AsArray, RecordBatch};
+use deltalake::arrow::datatypes::{Date32Type, Int64Type};
+use log::info;
+
+use super::{ensure_typed_schema, table_uri, DeltaReportType};
+use crate::AppError;
+
+/// Rows that differ between the CSVs and the table for one report date.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DateDifference {
+    /// Rows in the CSVs that the table does not have
+    pub missing: usize,
+    /// Rows in the table that no CSV has
+    pub extra: usize,
+}
+
+/// Outcome of [`reconcile_delta`].
+#[derive(Debug, Default)]
+pub struct ReconcileReport {
+    /// CSV files compared
+    pub csv_files: Vec<PathBuf>,
+    /// CSV files that could not be read, with the reason; they are not compared
+    pub unreadable_files: Vec<(PathBuf, String)>,
+    /// Differences by report date; rows without a date are under `None`
+    pub differences: BTreeMap<Option<NaiveDate>, DateDifference>,
+}
+
+impl ReconcileReport {
+    /// Whether every CSV was read and the CSVs and the table hold the same rows.
+    pub fn is_match(&self) -> bool {
+        self.differences.is_empty() && self.unreadable_files.is_empty()
+    }
+
+    /// Rows in the CSVs that the table does not have, over all dates.
+    pub fn missing(&self) -> usize {
+        self.differences.values().map(|d| d.missing).sum()
+    }
+
+    /// Rows in the table that no CSV has, over all dates.
+    pub fn extra(&self) -> usize {
+        self.differences.values().map(|d| d.extra).sum()
+    }
+}
+
+/// Row counts per report date of a `date, count` query result.
+fn counts_by_date(batches: &[RecordBatch]) -> Vec<(Option<NaiveDate>, usize)> {
+    let mut counts = Vec::new();
+    for batch in batches {
+        let dates = batch.column(0).as_primitive::<Date32Type>();
+        let rows = batch.column(1).as_primitive::<Int64Type>();
+        for row in 0..batch.num_rows() {
+            let date = dates.value_as_date(row).filter(|_| dates.is_valid(row));
+            counts.push((date, rows.value(row) as usize));
+        }
+    }
+    counts
+}
+
+/// Compare the CSVs of a report type in a folder with a delta table.
+///
+/// The CSVs are parsed into the typed schema and compared on the report's
+/// columns, ignoring partition and lineage columns, like the
+/// `queries/*_csv_delta_comparison.sql` queries: a CSV row is missing when
+/// no identical row is in the table, and a table row is extra when no
+/// identical row is in any CSV. Duplicate rows count once. CSVs that cannot
+/// be read are reported separately and never match, since the table rows
+/// loaded from them are counted as extra.
+///
+/// # Arguments
+/// * `report_type` - Report type of the CSVs and the table
+/// * `csv_folder` - Folder of `*WELLS*.csv` or `*SPUD*.csv` files
+/// * `table_path` - Directory of the delta table
+///
+/// # Returns
+/// The CSV files compared or unreadable, and the missing and extra rows per report date
+///
+/// # Example
+/// ```rust,ignore
+/// let report = reconcile_delta(DeltaReportType::St1, Path::new("data/csv"), Path::new("data/deltalake/st1")).await?;
+/// if !report.is_match() {
+///     println!("{} missing, {} extra", report.missing(), report.extra());
+/// }
+/// ```
+pub async fn reconcile_delta(
+    report_type: DeltaReportType,
+    csv_folder: &Path,
+    table_path: &Path,
+) -> Result<ReconcileReport, AppError> {
+    let table = deltalake::open_table(table_uri(table_path)?).await?;
+    ensure_typed_schema(&table, report_type)?;
+
+    let csvs = report_type.read_csv_folder(csv_folder)?;
+    info!(
+        "Comparing {} CSV files in {csv_folder:?} with {table_path:?}",
+        csvs.read.len()
+    );
+
+    let ctx = SessionContext::new();
+    ctx.register_table("csv", ctx.read_batches(csvs.batches)?.into_view())?;
+    ctx.register_table("delta_table", Arc::new(table))?;
+    let columns = report_type
+        .arrow_schema()
+        .fields()
+        .iter()
+        .map(|field| format!("\"{}\"", field.name()))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let difference = |from: &str, except: &str| {
+        format!(
+            "SELECT date, count(*) FROM (
+                SELECT {columns} FROM {from}
+                EXCEPT
+                SELECT {columns} FROM {except}
+            ) GROUP BY date"
+        )
+    };
+
+    let mut report = ReconcileReport {
+        csv_files: csvs.read,
+        unreadable_files: csvs.unreadable,
+        ..Default::default()
+    };
+    let missing = ctx
+        .sql(&difference("csv", "delta_table"))
+        .await?
+        .collect()
+        .await?;
+    for (date, rows) in counts_by_date(&missing) {
+        report.differences.entry(date).or_default().missing += rows;
+    }
+    let extra = ctx
+        .sql(&difference("delta_table", "csv"))
+        .await?
+        .collect()
+        .await?;
+    for (date, rows) in counts_by_date(&extra) {
+        report.differences.entry(date).or_default().extra += rows;
+    }
+    Ok(report)
+}
@@ -0,0 +1,240 @@
+//! The `reload` workflow: replace a report date range with freshly parsed CSVs
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::NaiveDate;
+use deltalake::DeltaTable;
+use log::info;
+
+use super::stream::StreamingOptions;
+use super::write::replace_source_batches;
+use super::{
+    ensure_typed_schema, has_lineage, log_loaded_csv, maintain_delta_table, remove_logged_csvs,
+    table_uri, update_licence_history, DeltaReportType, LicenceHistorySummary, LoadSummary,
+    MaintenanceOptions, MaintenanceReport,
+};
+use crate::parsers::lineage::SourceFile;
+use crate::{storage, AppError};
+
+/// Options for [`reload_delta`], one field per `reload` command line flag.
+#[derive(Debug, Clone)]
+pub struct ReloadOptions {
+    /// Report type stored in the table
+    pub report_type: DeltaReportType,
+    /// Directory of the delta table
+    pub table_path: PathBuf,
+    /// First report date to replace
+    pub start_date: NaiveDate,
+    /// Last report date to replace
+    pub end_date: NaiveDate,
+    /// A single CSV file to load
+    pub csv_path: Option<PathBuf>,
+    /// Folder whose CSVs for the report type and date range are loaded
+    pub csv_folder: Option<PathBuf>,
+    /// Audit log location, defaults to `delta_load_log.json` inside the table
+    pub log_path: Option<PathBuf>,
+    /// Memory budget and row group size of the files written
+    pub streaming: StreamingOptions,
+    /// Optimize and vacuum steps run after the reload
+    pub maintenance: MaintenanceOptions,
+    /// `licence_history` table to update after an ST-1 reload
+    pub licence_history: Option<PathBuf>,
+}
+
+impl ReloadOptions {
+    /// Options with no CSV inputs and the command line defaults for everything else.
+    pub fn new(
+        report_type: DeltaReportType,
+        table_path: impl Into<PathBuf>,
+        start_date: NaiveDate,
+        end_date: NaiveDate,
+    ) -> Self {
+        Self {
+            report_type,
+            table_path: table_path.into(),
+            start_date,
+            end_date,
+            csv_path: None,
+            csv_folder: None,
+            log_path: None,
+            streaming: StreamingOptions::default(),
+            maintenance: MaintenanceOptions::default(),
+            licence_history: None,
+        }
+    }
+
+    /// Location of the load log for these options.
+    pub fn log_path(&self) -> PathBuf {
+        self.log_path
+            .clone()
+            .unwrap_or_else(|| self.table_path.join("delta_load_log.json"))
+    }
+
+    /// Whether a report date is inside the range being reloaded.
+    fn contains(&self, date: NaiveDate) -> bool {
+        self.start_date <= date && date <= self.end_date
+    }
+}
+
+/// Outcome of a [`reload_delta`] run.
+#[derive(Debug)]
+pub struct ReloadReport {
+    /// The table after reloading and maintenance
+    pub table: DeltaTable,
+    /// Rows written and deleted by the reload
+    pub summary: LoadSummary,
+    /// CSV files loaded and recorded in the load log
+    pub loaded_files: Vec<PathBuf>,
+    /// Load log entries removed for CSVs in the date range
+    pub unlogged_files: usize,
+    /// Versions changed in the licence history, if it was updated
+    pub licence_history: Option<LicenceHistorySummary>,
+    /// Files optimized and vacuumed after the reload
+    pub maintenance: MaintenanceReport,
+}
+
+/// Report date of a CSV written by the parsers, from its `YYYYMMDD_` file name prefix.
+fn csv_report_date(path: &Path) -> Option<NaiveDate> {
+    let name = path.file_name()?.to_str()?;
+    NaiveDate::parse_from_str(name.get(..8)?, "%Y%m%d").ok()
+}
+
+/// Replace the rows of a report date range with freshly parsed CSVs.
+///
+/// This is the `reload` command, for when the AER republishes corrected
+/// reports. Every row with a report date in the range is deleted and the
+/// CSVs' rows are written in the same commit, so the table never shows the
+/// range half reloaded. The CSVs are read one at a time as they are written,
+/// within the memory budget of `options.streaming`. CSVs are loaded whether or not their contents were
+/// loaded before, and every CSV row must fall inside the range. From
+/// `options.csv_folder` only CSVs whose file name date is in the range are
+/// used.
+///
+/// The load log entries of CSVs dated in the range are replaced by entries
+/// for the reloaded files. The licence history and maintenance then run as
+/// for `load-delta`.
+///
+/// # Arguments
+/// * `options` - Date range, inputs, table location and follow-up steps
+///
+/// # Returns
+/// The updated table, the rows replaced and the files loaded
+///
+/// # Example
+/// ```rust,ignore
+/// let day = NaiveDate::from_ymd_opt(2025, 1, 6).unwrap();
+/// let mut options = ReloadOptions::new(DeltaReportType::St1, "data/deltalake/st1", day, day);
+/// options.csv_folder = Some("data/csv".into());
+/// let report = reload_delta(&options).await?;
+/// ```
+pub async fn reload_delta(options: &ReloadOptions) -> Result<ReloadReport, AppError> {
+    let table_path = options.table_path.as_path();
+    if options.start_date > options.end_date {
+        return Err(AppError::Cli(format!(
+            "Start date {} is after end date {}",
+            options.start_date, options.end_date
+        )));
+    }
+    if options.licence_history.is_some() && options.report_type != DeltaReportType::St1 {
+        return Err(AppError::Cli(
+            "Licence history can only be derived from ST1 loads".to_string(),
+        ));
+    }
+    if !storage::delta_table_exists(table_uri(table_path)?).await? {
+        return Err(AppError::DeltaTable(format!(
+            "No delta table at {table_path:?}; create it with `load-delta` first"
+        )));
+    }
+    let mut table = deltalake::open_table(table_uri(table_path)?).await?;
+    ensure_typed_schema(&table, options.report_type)?;
+
+    let mut csv_files = Vec::new();
+    if let Some(folder) = &options.csv_folder {
+        info!("Searching for CSV files in folder: {folder:?}");
+        let prefix = options.report_type.csv_prefix();
+        for entry in fs::read_dir(folder)? {
+            let path = entry?.path();
+            if let Some(filename) = path.file_name().and_then(|n| n.to_str()) {
+                if filename.contains(prefix)
+                    && filename.ends_with(".csv")
+                    && csv_report_date(&path).is_some_and(|date| options.contains(date))
+                {
+                    info!("Found CSV file: {path:?}");
+                    csv_files.push(path);
+                }
+            }
+        }
+    }
+    if let Some(path) = &options.csv_path {
+        csv_files.push(path.clone());
+    }
+    csv_files.sort();
+    if csv_files.is_empty() {
+        return Err(AppError::Cli(format!(
+            "No {} CSV files to reload from {} to {}",
+            options.report_type.csv_prefix(),
+            options.start_date,
+            options.end_date
+        )));
+    }
+
+    let lineage = has_lineage(&table);
+    let source_files = csv_files
+        .iter()
+        .map(|csv_path| SourceFile::from_path(csv_path))
+        .collect::<Result<Vec<_>, _>>()?;
+    let report_type = options.report_type;
+    // Each CSV is only read when the write reaches it
+    let read_csv = move |(path, source): (PathBuf, SourceFile)| {
+        report_type.csv_to_record_batch(&path, lineage.then_some(&source))
+    };
+    let batches = csv_files
+        .clone()
+        .into_iter()
+        .zip(source_files.clone())
+        .map(read_csv);
+
+    info!(
+        "Reloading {} to {} from {} CSV files in a single commit with a {} MiB memory budget",
+        options.start_date,
+        options.end_date,
+        csv_files.len(),
+        options.streaming.memory_budget / (1024 * 1024)
+    );
+    let summary = replace_source_batches(
+        &mut table,
+        batches,
+        (options.start_date, options.end_date),
+        &source_files,
+        options.streaming,
+    )
+    .await?;
+
+    let log_path = options.log_path();
+    let unlogged_files = remove_logged_csvs(&log_path, |path| {
+        csv_report_date(path).is_some_and(|date| options.contains(date))
+    })
+    .await?;
+    for csv_path in &csv_files {
+        log_loaded_csv(&log_path, csv_path).await?;
+    }
+
+    let licence_history = match &options.licence_history {
+        Some(history_path) => Some(update_licence_history(&table, history_path).await?),
+        None => None,
+    };
+
+    info!("Running maintenance on delta table at {table_path:?}");
+    let maintenance =
+        maintain_delta_table(&mut table, &options.maintenance, &summary.partitions).await?;
+
+    Ok(ReloadReport {
+        table,
+        summary,
+        loaded_files: csv_files,
+        unlogged_files,
+        licence_history,
+        maintenance,
+    })
+}
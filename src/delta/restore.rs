@@ -0,0 +1,177 @@
+//! The `restore` workflow: roll a table back to an earlier version
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use deltalake::{DeltaOps, DeltaTable};
+use log::info;
+
+use super::tracking::rolled_back_commit_properties;
+use super::{
+    loaded_source_files, open_delta_table_at, remove_logged_csvs, sha256_file, table_uri,
+    update_licence_history, LicenceHistorySummary, TableVersion,
+};
+use crate::parsers::lineage::SourceFile;
+use crate::AppError;
+
+/// Options for [`restore_delta`], one field per `restore` command line flag.
+#[derive(Debug, Clone)]
+pub struct RestoreOptions {
+    /// Directory of the delta table
+    pub table_path: PathBuf,
+    /// Version, or time, to roll the table back to
+    pub target: TableVersion,
+    /// Audit log location, defaults to `delta_load_log.json` inside the table
+    pub log_path: Option<PathBuf>,
+    /// `licence_history` table to re-derive from the restored ST-1 table
+    pub licence_history: Option<PathBuf>,
+}
+
+impl RestoreOptions {
+    /// Options restoring the table to `target` with the default load log.
+    pub fn new(table_path: impl Into<PathBuf>, target: TableVersion) -> Self {
+        Self {
+            table_path: table_path.into(),
+            target,
+            log_path: None,
+            licence_history: None,
+        }
+    }
+
+    /// Location of the load log for these options.
+    pub fn log_path(&self) -> PathBuf {
+        self.log_path
+            .clone()
+            .unwrap_or_else(|| self.table_path.join("delta_load_log.json"))
+    }
+}
+
+/// Outcome of a [`restore_delta`] run.
+#[derive(Debug)]
+pub struct RestoreReport {
+    /// The table after the restore commit
+    pub table: DeltaTable,
+    /// Version the table was at before the restore
+    pub from_version: i64,
+    /// Version whose contents were restored
+    pub restored_version: i64,
+    /// Data files removed by the restore
+    pub removed_files: usize,
+    /// Data files added back by the restore
+    pub restored_files: usize,
+    /// Source files whose rows were rolled back, and may be loaded again
+    pub rolled_back_files: Vec<SourceFile>,
+    /// Load log entries removed for the rolled back files
+    pub unlogged_files: usize,
+    /// Versions changed in the licence history, if it was updated
+    pub licence_history: Option<LicenceHistorySummary>,
+}
+
+/// Roll a delta table back to an earlier version or time.
+///
+/// This is the `restore` command. A RESTORE commit makes the table's files
+/// those of the target version again; later versions stay in the history.
+/// Source files loaded after the target version are marked as rolled back in
+/// the restore commit (see [`LoadedFiles`](super::LoadedFiles)), and their
+/// entries are removed from the load log, so `load-delta` loads them again.
+/// Logged CSVs that still exist are matched on their SHA-256, missing ones on
+/// their file name.
+///
+/// A time is resolved to the latest version committed at or before it. Data
+/// files removed by vacuum since the target version make the restore fail.
+///
+/// # Arguments
+/// * `options` - Table, target version and load log
+///
+/// # Returns
+/// The restored table, the files it changed and the source files rolled back
+///
+/// # Example
+/// ```rust,ignore
+/// let options = RestoreOptions::new("data/deltalake/st1", TableVersion::Version(12));
+/// let report = restore_delta(&options).await?;
+/// ```
+pub async fn restore_delta(options: &RestoreOptions) -> Result<RestoreReport, AppError> {
+    let table_path = options.table_path.as_path();
+    if options.target == TableVersion::Latest {
+        return Err(AppError::Cli(
+            "A version or time to restore to is required".to_string(),
+        ));
+    }
+
+    let table = deltalake::open_table(table_uri(table_path)?).await?;
+    let from_version = table.version().unwrap_or_default();
+    let restored_version = open_delta_table_at(table_path, options.target)
+        .await?
+        .version()
+        .unwrap_or_default();
+    if restored_version >= from_version {
+        return Err(AppError::Cli(format!(
+            "Version {restored_version} is not earlier than the current version {from_version}"
+        )));
+    }
+
+    let loaded_before = loaded_source_files(&table).await?;
+    let loaded_after = loaded_source_files(
+        &open_delta_table_at(table_path, TableVersion::Version(restored_version)).await?,
+    )
+    .await?;
+    let kept_hashes: HashSet<&str> = loaded_after.iter().map(|f| f.sha256.as_str()).collect();
+    let kept_names: HashSet<&str> = loaded_after.iter().map(|f| f.file_name.as_str()).collect();
+    let mut rolled_back_files: Vec<SourceFile> = Vec::new();
+    for file in loaded_before {
+        if !kept_hashes.contains(file.sha256.as_str()) && !rolled_back_files.contains(&file) {
+            rolled_back_files.push(file);
+        }
+    }
+
+    info!("Restoring delta table at {table_path:?} from version {from_version} to version {restored_version}");
+    let (table, metrics) = DeltaOps::from(table)
+        .restore()
+        .with_version_to_restore(restored_version)
+        .with_commit_properties(rolled_back_commit_properties(
+            &rolled_back_files,
+            from_version + 1,
+        ))
+        .await?;
+
+    let unlogged_files = remove_logged_csvs(&options.log_path(), |path| {
+        rolled_back(path, &rolled_back_files, &kept_names)
+    })
+    .await?;
+    info!(
+        "Rolled back {} source files; removed {unlogged_files} load log entries",
+        rolled_back_files.len()
+    );
+
+    let licence_history = match &options.licence_history {
+        Some(history_path) => Some(update_licence_history(&table, history_path).await?),
+        None => None,
+    };
+
+    Ok(RestoreReport {
+        table,
+        from_version,
+        restored_version,
+        removed_files: metrics.num_removed_file,
+        restored_files: metrics.num_restored_file,
+        rolled_back_files,
+        unlogged_files,
+        licence_history,
+    })
+}
+
+/// Whether a logged CSV is one of the rolled back source files.
+fn rolled_back(path: &Path, rolled_back_files: &[SourceFile], kept_names: &HashSet<&str>) -> bool {
+    match sha256_file(path) {
+        Ok(hash) => rolled_back_files.iter().any(|file| file.sha256 == hash),
+        Err(_) => {
+            let name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or_default();
+            !kept_names.contains(name)
+                && rolled_back_files.iter().any(|file| file.file_name == name)
+        }
+    }
+}
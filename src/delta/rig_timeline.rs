@@ -0,0 +1,264 @@
+//! The derived `rig_timeline` table
+//!
+//! ST-49 reports which drilling contractor rig started each activity on a
+//! well. Ordered by activity time, the records of one rig (contractor BA ID
+//! and rig number) trace its moves from well to well. The `rig_timeline`
+//! table holds that sequence for every rig: the previous well, the days since
+//! the previous move and the days inferred on each well, i.e. until the rig's
+//! next activity. The latest activity of a rig has no days on well yet.
+//!
+//! The rows are recomputed from the ST-49 table and merged into the
+//! `rig_timeline` table (see [`super::derived`]).
+
+use std::path::Path;
+use std::sync::Arc;
+
+use datafusion::prelude::{col, lit, lower, SessionContext};
+use deltalake::kernel::{DataType, PrimitiveType, StructField};
+use deltalake::DeltaTable;
+use log::info;
+
+use super::derived::{create_or_open_derived_table, merge_derived_rows, row_counts};
+use super::{
+    ensure_typed_schema, get_schema, open_delta_table_at, DeltaReportType, QueryResult,
+    TableVersion,
+};
+use crate::AppError;
+
+/// ST-49 columns carried into each row.
+const ACTIVITY_COLUMNS: [&str; 10] = [
+    "contractor_ba_id",
+    "contractor_name",
+    "rig_number",
+    "activity_date",
+    "well_id",
+    "well_name",
+    "licence",
+    "licensee",
+    "activity_type",
+    "date",
+];
+
+/// Columns computed from the rig's other activities.
+const TIMELINE_COLUMNS: [&str; 4] = [
+    "sequence",
+    "previous_well_id",
+    "days_since_previous",
+    "days_on_well",
+];
+
+/// Columns identifying an activity of a rig.
+const KEY_COLUMNS: [&str; 5] = [
+    "contractor_ba_id",
+    "rig_number",
+    "activity_date",
+    "well_id",
+    "activity_type",
+];
+
+/// Rows changed in the `rig_timeline` table by [`update_rig_timeline`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RigTimelineSummary {
+    /// New rig activities
+    pub inserted: usize,
+    /// Activities whose details or place in the timeline changed
+    pub updated: usize,
+    /// Activities no longer in the ST-49 table
+    pub deleted: usize,
+    /// Rigs in the table after the update
+    pub rigs: usize,
+    /// Activities in the table after the update
+    pub activities: usize,
+}
+
+/// Create the `rig_timeline` table, or open it if it exists.
+async fn create_or_open_rig_timeline_table(table_path: &Path) -> Result<DeltaTable, AppError> {
+    let st49_fields = get_schema(DeltaReportType::St49)?;
+    let field = |name: &str| {
+        st49_fields
+            .iter()
+            .find(|field| field.name() == name)
+            .cloned()
+            .ok_or_else(|| AppError::DeltaTable(format!("ST-49 schema has no column `{name}`")))
+    };
+    let double = DataType::Primitive(PrimitiveType::Double);
+    let mut columns = vec![
+        field("contractor_ba_id")?,
+        field("contractor_name")?,
+        field("rig_number")?,
+        StructField::new(
+            "sequence",
+            DataType::Primitive(PrimitiveType::Integer),
+            false,
+        ),
+    ];
+    for name in &ACTIVITY_COLUMNS[3..] {
+        columns.push(field(name)?);
+    }
+    columns.extend([
+        StructField::new(
+            "previous_well_id",
+            DataType::Primitive(PrimitiveType::String),
+            true,
+        ),
+        StructField::new("days_since_previous", double.clone(), true),
+        StructField::new("days_on_well", double, true),
+    ]);
+    create_or_open_derived_table(table_path, columns).await
+}
+
+/// SQL ordering the activities of every rig in the `st49` table.
+///
+/// Records without a contractor, rig number or activity time cannot be
+/// placed and are left out. An activity listed on several report dates
+/// counts once, with the earliest report date.
+fn rig_timeline_sql() -> String {
+    let columns = ACTIVITY_COLUMNS.join(", ");
+    let keys = KEY_COLUMNS.join(", ");
+    let rig =
+        "PARTITION BY contractor_ba_id, rig_number ORDER BY activity_date, well_id, activity_type";
+    let days = |from: &str, to: &str| {
+        format!("(date_part('epoch', {to}) - date_part('epoch', {from})) / 86400")
+    };
+
+    format!(
+        "WITH activities AS (
+            SELECT {columns},
+                ROW_NUMBER() OVER (
+                    PARTITION BY {keys}
+                    ORDER BY date, contractor_name, well_name, licence, licensee
+                ) AS report_rank
+            FROM st49
+            WHERE contractor_ba_id IS NOT NULL AND trim(contractor_ba_id) <> ''
+                AND rig_number IS NOT NULL AND activity_date IS NOT NULL
+        ),
+        timeline AS (
+            SELECT {columns},
+                ROW_NUMBER() OVER ({rig}) AS sequence,
+                LAG(well_id) OVER ({rig}) AS previous_well_id,
+                LAG(activity_date) OVER ({rig}) AS previous_activity_date,
+                LEAD(activity_date) OVER ({rig}) AS next_activity_date
+            FROM activities
+            WHERE report_rank = 1
+        )
+        SELECT {columns},
+            CAST(sequence AS INT) AS sequence,
+            previous_well_id,
+            {} AS days_since_previous,
+            {} AS days_on_well
+        FROM timeline",
+        days("previous_activity_date", "activity_date"),
+        days("activity_date", "next_activity_date"),
+    )
+}
+
+/// Bring the `rig_timeline` table in line with an ST-49 table.
+///
+/// Creates the table (with Change Data Feed enabled) on first use. The
+/// timeline of every rig is recomputed and merged on the rig, activity time,
+/// well and activity type: new activities are inserted, activities whose
+/// details or neighbours changed (e.g. the rig's next move was loaded) are
+/// updated, and activities no longer in the ST-49 table are deleted. Nothing
+/// is committed when the table is up to date.
+///
+/// # Arguments
+/// * `st49_table` - The typed ST-49 table to derive the timeline from
+/// * `table_path` - Directory or object store URI of the `rig_timeline` table
+///
+/// # Returns
+/// Number of activities inserted, updated and deleted, and the size of the timeline
+///
+/// # Example
+/// ```rust,ignore
+/// let st49 = deltalake::open_table("data/deltalake/st49").await?;
+/// let summary = update_rig_timeline(&st49, Path::new("data/deltalake/rig_timeline")).await?;
+/// println!("{} activities of {} rigs", summary.activities, summary.rigs);
+/// ```
+pub async fn update_rig_timeline(
+    st49_table: &DeltaTable,
+    table_path: &Path,
+) -> Result<RigTimelineSummary, AppError> {
+    ensure_typed_schema(st49_table, DeltaReportType::St49)?;
+    let rig_timeline = create_or_open_rig_timeline_table(table_path).await?;
+
+    let ctx = SessionContext::new();
+    ctx.register_table("st49", Arc::new(st49_table.clone()))?;
+    let rows = ctx.sql(&rig_timeline_sql()).await?;
+    ctx.register_table("rig_timeline", rows.clone().into_view())?;
+    let counts = ctx
+        .sql(
+            "SELECT count(*) AS rigs, coalesce(sum(activities), 0) AS activities
+            FROM (
+                SELECT count(*) AS activities FROM rig_timeline
+                GROUP BY contractor_ba_id, rig_number
+            )",
+        )
+        .await?
+        .collect()
+        .await?;
+
+    let columns: Vec<&str> = ACTIVITY_COLUMNS
+        .iter()
+        .chain(&TIMELINE_COLUMNS)
+        .copied()
+        .collect();
+    let metrics = merge_derived_rows(rig_timeline, rows, &KEY_COLUMNS, &columns).await?;
+
+    let [rigs, activities] = row_counts(&counts);
+    let summary = RigTimelineSummary {
+        inserted: metrics.num_target_rows_inserted,
+        updated: metrics.num_target_rows_updated,
+        deleted: metrics.num_target_rows_deleted,
+        rigs,
+        activities,
+    };
+    info!(
+        "Updated rig_timeline at {table_path:?}: {} activities inserted, {} updated, {} deleted; {} activities of {} rigs",
+        summary.inserted, summary.updated, summary.deleted, summary.activities, summary.rigs
+    );
+    Ok(summary)
+}
+
+/// Read the timeline of some or all rigs, ordered by rig and sequence.
+///
+/// # Arguments
+/// * `table_path` - Directory or object store URI of the `rig_timeline` table
+/// * `contractor` - Only rigs of this contractor, by BA ID or name (case-insensitive)
+/// * `rig_number` - Only rigs with this number
+///
+/// # Returns
+/// The timeline rows, with every column of the table
+pub async fn read_rig_timeline(
+    table_path: &Path,
+    contractor: Option<&str>,
+    rig_number: Option<i32>,
+) -> Result<QueryResult, AppError> {
+    let table = open_delta_table_at(table_path, TableVersion::Latest).await?;
+    let ctx = SessionContext::new();
+    let mut df = ctx.read_table(Arc::new(table))?;
+    if let Some(contractor) = contractor {
+        df = df.filter(
+            col("contractor_ba_id")
+                .eq(lit(contractor))
+                .or(lower(col("contractor_name")).eq(lit(contractor.to_lowercase()))),
+        )?;
+    }
+    if let Some(rig_number) = rig_number {
+        df = df.filter(col("rig_number").eq(lit(rig_number)))?;
+    }
+    let columns: Vec<&str> = ACTIVITY_COLUMNS[..3]
+        .iter()
+        .chain(&TIMELINE_COLUMNS[..1])
+        .chain(&ACTIVITY_COLUMNS[3..])
+        .chain(&TIMELINE_COLUMNS[1..])
+        .copied()
+        .collect();
+    let df = df.select_columns(&columns)?.sort(vec![
+        col("contractor_ba_id").sort(true, false),
+        col("rig_number").sort(true, false),
+        col("sequence").sort(true, false),
+    ])?;
+    let schema = df.schema().inner().clone();
+    let batches = df.collect().await?;
+    Ok(QueryResult { schema, batches })
+}
@@ -0,0 +1,204 @@
+//! Standard summaries of the ST-1 and ST-49 records
+//!
+//! The `stats` command computes the counts every team otherwise writes by
+//! hand: licences per day, week or month, licences by substance, well type,
+//! field centre, licensee or mineral rights, and spuds by drilling
+//! contractor or field centre. Each [`Statistic`] is a SQL query run with
+//! [`run_query`] over a Delta table or a folder of parser CSVs.
+//!
+//! Licences are counted once per licence number and spuds once per well, so
+//! a licence listed again (e.g. after an amendment) is not counted twice.
+
+use std::path::PathBuf;
+
+use chrono::NaiveDate;
+use clap::ValueEnum;
+
+use super::{run_query, DeltaReportType, QueryResult, QuerySource};
+use crate::AppError;
+
+/// A summary computed by [`compute_stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Statistic {
+    /// ST-1 licences per report date
+    LicencesPerDay,
+    /// ST-1 licences per week, starting on Monday
+    LicencesPerWeek,
+    /// ST-1 licences per calendar month
+    LicencesPerMonth,
+    /// ST-1 licences by substance
+    LicencesBySubstance,
+    /// ST-1 licences by well type
+    LicencesByWellType,
+    /// ST-1 licences by AER field centre
+    LicencesByFieldCentre,
+    /// ST-1 licences by licensee
+    LicencesByLicensee,
+    /// ST-1 licences by mineral rights
+    LicencesByMineralRights,
+    /// ST-49 spuds by drilling contractor BA ID
+    SpudsByContractor,
+    /// ST-49 spuds by field centre
+    SpudsByFieldCentre,
+}
+
+impl Statistic {
+    /// Type of report the statistic summarises.
+    pub fn report_type(self) -> DeltaReportType {
+        match self {
+            Statistic::SpudsByContractor | Statistic::SpudsByFieldCentre => DeltaReportType::St49,
+            _ => DeltaReportType::St1,
+        }
+    }
+
+    /// SQL computing the statistic over the `st1` or `st49` table.
+    fn sql(self, filter: &str) -> String {
+        match self {
+            Statistic::LicencesPerDay => per_period("day", "date", filter),
+            Statistic::LicencesPerWeek => {
+                per_period("week", "CAST(date_trunc('week', date) AS DATE)", filter)
+            }
+            Statistic::LicencesPerMonth => {
+                per_period("month", "CAST(date_trunc('month', date) AS DATE)", filter)
+            }
+            Statistic::LicencesBySubstance => licences_by("substance", filter),
+            Statistic::LicencesByWellType => licences_by("well_type", filter),
+            Statistic::LicencesByFieldCentre => licences_by("aer_field_centre", filter),
+            Statistic::LicencesByLicensee => licences_by("licensee", filter),
+            Statistic::LicencesByMineralRights => licences_by("mineral_rights", filter),
+            Statistic::SpudsByContractor => breakdown(
+                "st49",
+                "contractor_ba_id, max(contractor_name) AS contractor_name",
+                "contractor_ba_id",
+                ("spuds", "well_id"),
+                filter,
+            ),
+            Statistic::SpudsByFieldCentre => breakdown(
+                "st49",
+                "field_centre",
+                "field_centre",
+                ("spuds", "well_id"),
+                filter,
+            ),
+        }
+    }
+}
+
+/// Licences per period, oldest first.
+fn per_period(period: &str, start: &str, filter: &str) -> String {
+    format!(
+        "SELECT {start} AS {period}, count(DISTINCT licence_number) AS licences
+        FROM st1 {filter}
+        GROUP BY 1
+        ORDER BY 1"
+    )
+}
+
+/// Licences by a column of the `st1` table.
+fn licences_by(column: &str, filter: &str) -> String {
+    breakdown(
+        "st1",
+        column,
+        column,
+        ("licences", "licence_number"),
+        filter,
+    )
+}
+
+/// Distinct `key` values counted as `measure` per group, with their
+/// percentage of the total, largest first.
+fn breakdown(
+    table: &str,
+    columns: &str,
+    group: &str,
+    (measure, key): (&str, &str),
+    filter: &str,
+) -> String {
+    format!(
+        "WITH counts AS (
+            SELECT {columns}, count(DISTINCT {key}) AS {measure}
+            FROM {table} {filter}
+            GROUP BY {group}
+        )
+        SELECT *, round(100.0 * {measure} / (SELECT sum({measure}) FROM counts), 1) AS percent
+        FROM counts
+        ORDER BY {measure} DESC, {group}"
+    )
+}
+
+/// Options for [`compute_stats`], one field per `stats` command line flag.
+#[derive(Debug, Clone)]
+pub struct StatsOptions {
+    /// The summary to compute
+    pub statistic: Statistic,
+    /// Records to summarise: a Delta table or a folder of parser CSVs
+    pub source: QuerySource,
+    /// Only records with a report `date` on or after this date
+    pub start_date: Option<NaiveDate>,
+    /// Only records with a report `date` on or before this date
+    pub end_date: Option<NaiveDate>,
+    /// Only the first rows, e.g. the ten largest licensees
+    pub limit: Option<usize>,
+}
+
+impl StatsOptions {
+    /// Options summarising every record of a Delta table.
+    pub fn new(statistic: Statistic, table_path: impl Into<PathBuf>) -> Self {
+        Self {
+            statistic,
+            source: QuerySource::Delta(table_path.into()),
+            start_date: None,
+            end_date: None,
+            limit: None,
+        }
+    }
+}
+
+/// Compute a standard summary of ST-1 or ST-49 records.
+///
+/// # Arguments
+/// * `options` - The statistic, its source, the report dates and a row limit
+///
+/// # Returns
+/// The summary rows: a period or group, the count and, for groups, its
+/// percentage of the total
+///
+/// # Example
+/// ```rust,ignore
+/// let mut options = StatsOptions::new(Statistic::LicencesByLicensee, "data/deltalake/st1");
+/// options.limit = Some(10);
+/// let result = compute_stats(&options).await?;
+/// print!("{}", format_query_result(&result, QueryFormat::Table)?);
+/// ```
+pub async fn compute_stats(options: &StatsOptions) -> Result<QueryResult, AppError> {
+    let table = match options.statistic.report_type() {
+        DeltaReportType::St1 => "st1",
+        DeltaReportType::St49 => "st49",
+    };
+    let conditions: Vec<String> = [
+        options
+            .start_date
+            .map(|date| format!("date >= DATE '{date}'")),
+        options
+            .end_date
+            .map(|date| format!("date <= DATE '{date}'")),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    let filter = if conditions.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {}", conditions.join(" AND "))
+    };
+    let mut sql = options.statistic.sql(&filter);
+    if let Some(limit) = options.limit {
+        sql.push_str(&format!("\nLIMIT {limit}"));
+    }
+
+    let tables = vec![(table.to_string(), options.source.clone())];
+    run_query(&tables, &sql)
+        .await?
+        .pop()
+        .ok_or_else(|| AppError::DeltaTable("The statistic query returned no result".to_string()))
+}
@@ -0,0 +1,221 @@
+//! Bounded-memory appends into delta tables
+//!
+//! Batches are written to parquet as they arrive instead of being
+//! concatenated first. Output files are buffered in memory until the memory
+//! budget is reached, then uploaded; every file is added in one commit at the
+//! end, so a load is still atomic. Every batch is checked against the table
+//! constraints before it is written, since commits made here bypass the
+//! constraint checks of the Delta writer.
+
+use std::collections::BTreeMap;
+
+use delta_kernel::expressions::Scalar;
+use deltalake::arrow::array::{Array, AsArray, RecordBatch, UInt32Array};
+use deltalake::arrow::compute::take_record_batch;
+use deltalake::arrow::datatypes::Int32Type;
+use deltalake::kernel::transaction::CommitBuilder;
+use deltalake::kernel::Action;
+use deltalake::kernel::DataType;
+use deltalake::parquet::file::properties::WriterProperties;
+use deltalake::protocol::{DeltaOperation, SaveMode};
+use deltalake::writer::{DeltaWriter, RecordBatchWriter, WriteMode};
+use deltalake::DeltaTable;
+use indexmap::IndexMap;
+use log::{debug, info};
+
+use super::tracking::source_files_commit_properties;
+use super::{validate_constraints, TableConstraints};
+use crate::parsers::lineage::SourceFile;
+use crate::AppError;
+
+/// Partition values in the form the delta writer takes them.
+type PartitionScalars = IndexMap<String, Scalar>;
+
+/// Memory limits for writing rows to a delta table.
+///
+/// Appends and reloads buffer encoded parquet up to the memory budget;
+/// merges merge their rows in groups of up to that many bytes of Arrow data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamingOptions {
+    /// Bytes of encoded parquet held in memory before files are uploaded.
+    /// This is also roughly the largest output file size.
+    pub memory_budget: usize,
+    /// Maximum rows per parquet row group, which bounds the unencoded rows
+    /// held for the row group being written
+    pub row_group_size: usize,
+}
+
+impl Default for StreamingOptions {
+    fn default() -> Self {
+        Self {
+            memory_budget: 1024 * 1024 * 1024,
+            row_group_size: 128 * 1024,
+        }
+    }
+}
+
+/// Appends batches to a table one at a time and commits them together.
+pub(super) struct StreamingAppend {
+    writer: RecordBatchWriter,
+    constraints: TableConstraints,
+    partition_columns: Vec<String>,
+    options: StreamingOptions,
+    actions: Vec<Action>,
+}
+
+impl StreamingAppend {
+    /// Creates a writer for the table's current schema and partitioning.
+    pub(super) fn for_table(
+        table: &DeltaTable,
+        options: StreamingOptions,
+    ) -> Result<Self, AppError> {
+        let properties = WriterProperties::builder()
+            .set_max_row_group_size(options.row_group_size.max(1))
+            .build();
+        Ok(Self {
+            writer: RecordBatchWriter::for_table(table)?.with_writer_properties(properties),
+            constraints: TableConstraints::for_table(table)?,
+            partition_columns: table.metadata()?.partition_columns().clone(),
+            options,
+            actions: Vec::new(),
+        })
+    }
+
+    /// Checks a batch against the table constraints and buffers it, uploading
+    /// the buffered files once the memory budget is reached.
+    ///
+    /// The batch must already carry the table's partition and lineage columns.
+    /// `source_name` and `source_lines` locate its rows in constraint errors.
+    pub(super) async fn write(
+        &mut self,
+        batch: RecordBatch,
+        source_name: &str,
+        source_lines: &[Option<u32>],
+    ) -> Result<(), AppError> {
+        validate_constraints(&self.constraints, &batch, source_name, source_lines)?;
+
+        // Match the table's field order and nullability exactly
+        let schema = self.writer.arrow_schema();
+        let columns = schema
+            .fields()
+            .iter()
+            .map(|field| {
+                batch.column_by_name(field.name()).cloned().ok_or_else(|| {
+                    AppError::DeltaTable(format!("Batch has no column `{}`", field.name()))
+                })
+            })
+            .collect::<Result<Vec<_>, AppError>>()?;
+        let batch = RecordBatch::try_new(schema, columns)?;
+
+        // `RecordBatchWriter::write` forgets the partition columns after its
+        // first batch, so rows are split by partition here instead
+        for (values, rows) in self.partition_rows(&batch)? {
+            let part = take_record_batch(&batch, &UInt32Array::from(rows))?;
+            self.writer
+                .write_partition(part, &values, WriteMode::Default)
+                .await?;
+        }
+
+        if self.writer.buffer_len() >= self.options.memory_budget {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Groups the rows of a batch by their partition values.
+    fn partition_rows(
+        &self,
+        batch: &RecordBatch,
+    ) -> Result<Vec<(PartitionScalars, Vec<u32>)>, AppError> {
+        let columns = self
+            .partition_columns
+            .iter()
+            .map(|name| {
+                batch
+                    .column_by_name(name)
+                    .map(|column| column.as_primitive::<Int32Type>())
+                    .ok_or_else(|| {
+                        AppError::DeltaTable(format!("Batch has no partition column `{name}`"))
+                    })
+            })
+            .collect::<Result<Vec<_>, AppError>>()?;
+
+        let mut partitions: BTreeMap<Vec<Option<i32>>, Vec<u32>> = BTreeMap::new();
+        for row in 0..batch.num_rows() {
+            let key = columns
+                .iter()
+                .map(|column| column.is_valid(row).then(|| column.value(row)))
+                .collect();
+            partitions.entry(key).or_default().push(row as u32);
+        }
+
+        Ok(partitions
+            .into_iter()
+            .map(|(key, rows)| {
+                let values = self
+                    .partition_columns
+                    .iter()
+                    .zip(key)
+                    .map(|(name, value)| {
+                        let value = value
+                            .map(Scalar::Integer)
+                            .unwrap_or(Scalar::Null(DataType::INTEGER));
+                        (name.clone(), value)
+                    })
+                    .collect();
+                (values, rows)
+            })
+            .collect())
+    }
+
+    async fn flush(&mut self) -> Result<(), AppError> {
+        let adds = self.writer.flush().await?;
+        debug!("Uploaded {} buffered parquet files", adds.len());
+        self.actions.extend(adds.into_iter().map(Action::Add));
+        Ok(())
+    }
+
+    /// Uploads the remaining files and commits every file written, recording
+    /// the source files in the commit metadata.
+    ///
+    /// Nothing is committed if neither rows nor source files were written.
+    pub(super) async fn commit(
+        mut self,
+        table: &mut DeltaTable,
+        source_files: &[SourceFile],
+    ) -> Result<(), AppError> {
+        self.flush().await?;
+        commit_append(table, self.actions, source_files).await
+    }
+}
+
+/// Commits the added files of an append, recording the source files in the
+/// commit metadata.
+///
+/// Source files without rows are committed with no files, so they are known
+/// as loaded. Nothing is committed if there are neither files nor source files.
+pub(super) async fn commit_append(
+    table: &mut DeltaTable,
+    actions: Vec<Action>,
+    source_files: &[SourceFile],
+) -> Result<(), AppError> {
+    if actions.is_empty() && source_files.is_empty() {
+        return Ok(());
+    }
+
+    let partition_columns = table.metadata()?.partition_columns().clone();
+    let operation = DeltaOperation::Write {
+        mode: SaveMode::Append,
+        partition_by: (!partition_columns.is_empty()).then_some(partition_columns),
+        predicate: None,
+    };
+    let files = actions.len();
+    let version = CommitBuilder::from(source_files_commit_properties(source_files))
+        .with_actions(actions)
+        .build(Some(table.snapshot()?), table.log_store(), operation)
+        .await?
+        .version();
+    table.update().await?;
+    info!("Committed {files} files as version {version}");
+    Ok(())
+}
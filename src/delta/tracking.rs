@@ -0,0 +1,356 @@
+//! Content-hash load tracking stored in the Delta transaction log
+//!
+//! Every load commit records one application transaction per source file
+//! hash, and the file names and hashes in its commitInfo under
+//! [`SOURCE_FILES_KEY`]. Whether a file has been loaded is then answered by
+//! the table itself (see [`LoadedFiles`]), so the answer survives moving the
+//! data directory and is committed atomically with the rows. Application
+//! transactions are part of the table state, so unlike the commitInfo they
+//! survive checkpoints and log cleanup. Two writers loading the same file at
+//! once conflict on the shared application transaction ID, so only one of
+//! them commits.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use deltalake::kernel::transaction::CommitProperties;
+use deltalake::kernel::{Action, ActionType, CommitInfo, EagerSnapshot, Transaction};
+use deltalake::logstore::get_actions;
+use deltalake::{DeltaOps, DeltaTable};
+use log::{info, warn};
+
+use super::read_load_log;
+use crate::parsers::lineage::SourceFile;
+use crate::AppError;
+
+/// commitInfo key holding the source files of a load.
+pub const SOURCE_FILES_KEY: &str = "aer_parser.sourceFiles";
+
+/// Table property set once the CSVs of the legacy load log are recorded in
+/// the table. New tables are created with it.
+pub(super) const LOAD_LOG_SEEDED_PROPERTY: &str = "aer_parser.loadLogSeeded";
+
+/// Prefix of the application transaction ID recorded for each loaded file hash.
+const APP_ID_PREFIX: &str = "aer_parser:sha256:";
+
+/// Application transaction ID whose version is that of the last restore made
+/// by [`restore_delta`](super::restore_delta).
+const RESTORE_APP_ID: &str = "aer_parser:restore";
+
+/// Application transaction version of a file whose rows are in the table.
+const LOADED: i64 = 0;
+
+/// Application transaction version of a file whose rows a restore removed.
+const ROLLED_BACK: i64 = -1;
+
+/// Commit properties with an application transaction of `version` for each hash.
+fn hash_transactions<'a>(
+    hashes: impl IntoIterator<Item = &'a str>,
+    version: i64,
+) -> CommitProperties {
+    let mut hashes: Vec<&str> = hashes.into_iter().collect();
+    hashes.sort_unstable();
+    hashes.dedup();
+    hashes
+        .into_iter()
+        .fold(CommitProperties::default(), |properties, hash| {
+            properties.with_application_transaction(Transaction::new(
+                format!("{APP_ID_PREFIX}{hash}"),
+                version,
+            ))
+        })
+}
+
+/// Commit properties recording the given source files.
+pub fn source_files_commit_properties(source_files: &[SourceFile]) -> CommitProperties {
+    if source_files.is_empty() {
+        return CommitProperties::default();
+    }
+    hash_transactions(source_files.iter().map(|f| f.sha256.as_str()), LOADED).with_metadata([(
+        SOURCE_FILES_KEY.to_string(),
+        serde_json::to_value(source_files).unwrap_or_default(),
+    )])
+}
+
+/// Commit properties of a restore committed as `version`, marking the source
+/// files whose rows it removes as no longer loaded.
+pub(super) fn rolled_back_commit_properties(
+    source_files: &[SourceFile],
+    version: i64,
+) -> CommitProperties {
+    hash_transactions(source_files.iter().map(|f| f.sha256.as_str()), ROLLED_BACK)
+        .with_application_transaction(Transaction::new(RESTORE_APP_ID, version))
+}
+
+/// Source files whose rows are in a table, for deciding what to load.
+///
+/// Answered from the application transaction of each file hash in the
+/// table's current version, so loads are recognised however old they are and
+/// no commit has to be read. A file is not loaded any more once
+/// [`restore_delta`](super::restore_delta) rolled it back. RESTOREs made by
+/// other tools only count with [`with_external_restores`](Self::with_external_restores).
+///
+/// # Example
+/// ```rust,ignore
+/// let loaded = LoadedFiles::for_table(&table).await?;
+/// if !loaded.contains(&sha256_file(csv_path)?).await? {
+///     // load the file
+/// }
+/// ```
+pub struct LoadedFiles {
+    snapshot: EagerSnapshot,
+    /// Hashes whose loading commit a RESTORE by another tool skipped
+    rolled_back: HashSet<String>,
+}
+
+impl LoadedFiles {
+    /// Reads the application transactions of the table's current version.
+    pub async fn for_table(table: &DeltaTable) -> Result<Self, AppError> {
+        let snapshot = EagerSnapshot::try_new_with_visitor(
+            table.log_store().as_ref(),
+            table.config.clone(),
+            table.version(),
+            HashSet::from([ActionType::Txn]),
+        )
+        .await?;
+        Ok(Self {
+            snapshot,
+            rolled_back: HashSet::new(),
+        })
+    }
+
+    /// Like [`for_table`](Self::for_table), but files whose loading commit a
+    /// RESTORE made by another tool skipped are not loaded either.
+    ///
+    /// Only the commits after the last restore made by
+    /// [`restore_delta`](super::restore_delta) are read, or the whole history
+    /// still in the log if there was none.
+    pub async fn with_external_restores(table: &DeltaTable) -> Result<Self, AppError> {
+        let mut loaded = Self::for_table(table).await?;
+        let last_restore = loaded
+            .snapshot
+            .transaction_version(RESTORE_APP_ID)
+            .await?
+            .unwrap_or(-1);
+        loaded.rolled_back = replay_history(table, last_restore).await?.skipped;
+        Ok(loaded)
+    }
+
+    /// Whether the rows of the file with this SHA-256 are in the table.
+    pub async fn contains(&self, sha256: &str) -> Result<bool, AppError> {
+        if self.rolled_back.contains(sha256) {
+            return Ok(false);
+        }
+        let version = self
+            .snapshot
+            .transaction_version(format!("{APP_ID_PREFIX}{sha256}"))
+            .await?;
+        Ok(version.is_some_and(|version| version >= LOADED))
+    }
+}
+
+/// Records the CSVs listed in the legacy load log as loaded, once per table.
+///
+/// Tables loaded before loads were tracked in the table only list them in the
+/// load log. The logged CSVs that still exist are hashed and recorded as
+/// application transactions in one commit, which also sets
+/// [`LOAD_LOG_SEEDED_PROPERTY`] so the log is not read again.
+///
+/// # Returns
+/// The number of logged CSVs recorded, 0 if the table was already seeded
+pub(super) async fn seed_from_load_log(
+    table: &mut DeltaTable,
+    log_path: &Path,
+) -> Result<usize, AppError> {
+    if table
+        .metadata()?
+        .configuration()
+        .contains_key(LOAD_LOG_SEEDED_PROPERTY)
+    {
+        return Ok(0);
+    }
+
+    let logged: Vec<SourceFile> = read_load_log(log_path)
+        .await?
+        .iter()
+        .filter_map(|csv_file| SourceFile::from_path(Path::new(csv_file)).ok())
+        .collect();
+    *table = DeltaOps::from(table.clone())
+        .set_tbl_properties()
+        .with_properties(HashMap::from([(
+            LOAD_LOG_SEEDED_PROPERTY.to_string(),
+            "true".to_string(),
+        )]))
+        .with_raise_if_not_exists(false)
+        .with_commit_properties(hash_transactions(
+            logged.iter().map(|file| file.sha256.as_str()),
+            LOADED,
+        ))
+        .await?;
+    info!(
+        "Recorded {} CSVs of the load log at {log_path:?} as loaded",
+        logged.len()
+    );
+    Ok(logged.len())
+}
+
+/// Source files recorded in a single commit.
+fn commit_source_files(commit: &CommitInfo) -> Vec<SourceFile> {
+    commit
+        .info
+        .get(SOURCE_FILES_KEY)
+        .and_then(|value| serde_json::from_value(value.clone()).ok())
+        .unwrap_or_default()
+}
+
+/// Whether a commit is a RESTORE, and the version it restored if it names one.
+///
+/// Restores by timestamp give `Some(None)`; see [`CommitLog::restored_version`].
+fn restore_target(commit: &CommitInfo) -> Option<Option<i64>> {
+    if commit.operation.as_deref() != Some("RESTORE") {
+        return None;
+    }
+    let parameters = commit.operation_parameters.as_ref();
+    Some(
+        parameters
+            .and_then(|parameters| parameters.get("version"))
+            .and_then(|value| {
+                value
+                    .as_i64()
+                    .or_else(|| value.as_str().and_then(|s| s.parse().ok()))
+            }),
+    )
+}
+
+/// Time a RESTORE by timestamp restored the table to, in milliseconds.
+fn restore_datetime(commit: &CommitInfo) -> Option<i64> {
+    let value = commit.operation_parameters.as_ref()?.get("datetime")?;
+    value
+        .as_i64()
+        .or_else(|| value.as_str().and_then(|s| s.parse().ok()))
+}
+
+/// Commits of a table, read by version from its log.
+struct CommitLog<'a> {
+    table: &'a DeltaTable,
+}
+
+impl CommitLog<'_> {
+    /// The commitInfo of a version, empty for a commit without one, or `None`
+    /// if the commit is no longer in the log.
+    async fn commit(&self, version: i64) -> Result<Option<CommitInfo>, AppError> {
+        let Some(bytes) = self.table.log_store().read_commit_entry(version).await? else {
+            return Ok(None);
+        };
+        let commit_info = get_actions(version, bytes)
+            .await?
+            .into_iter()
+            .find_map(|action| match action {
+                Action::CommitInfo(commit_info) => Some(commit_info),
+                _ => None,
+            });
+        Ok(Some(commit_info.unwrap_or_default()))
+    }
+
+    /// Version the RESTORE committed as `version` returned the table to, and
+    /// the commits it skipped, read on the way for a restore by timestamp.
+    ///
+    /// A timestamp is resolved to the latest earlier commit at or before it.
+    async fn restored_version(
+        &self,
+        version: i64,
+        commit: &CommitInfo,
+    ) -> Result<(i64, Vec<CommitInfo>), AppError> {
+        let mut skipped = Vec::new();
+        let target = match (restore_target(commit).flatten(), restore_datetime(commit)) {
+            (Some(target), _) => {
+                for skipped_version in (target + 1..version).rev() {
+                    skipped.extend(self.commit(skipped_version).await?);
+                }
+                target
+            }
+            (None, Some(datetime)) => {
+                let mut target = version - 1;
+                while let Some(candidate) = self.commit(target).await? {
+                    if candidate.timestamp.is_some_and(|t| t <= datetime) {
+                        break;
+                    }
+                    skipped.push(candidate);
+                    target -= 1;
+                }
+                target
+            }
+            (None, None) => version,
+        };
+        Ok((target, skipped))
+    }
+}
+
+/// SHA-256 hashes of the source files whose rows are in the current table version.
+///
+/// See [`loaded_source_files`] for how the history is read. To check whether
+/// a file is loaded, use [`LoadedFiles`], which also sees loads whose commits
+/// were cleaned up and reads no commits.
+pub async fn loaded_file_hashes(table: &DeltaTable) -> Result<HashSet<String>, AppError> {
+    Ok(loaded_source_files(table)
+        .await?
+        .into_iter()
+        .map(|file| file.sha256)
+        .collect())
+}
+
+/// Source files whose rows are in the current table version, newest load first.
+///
+/// The table history is replayed from the latest commit backwards. A RESTORE
+/// jumps to the version it restored, so files loaded after that version are
+/// no longer counted. Commits removed by log cleanup (older than the table's
+/// `delta.logRetentionDuration`) cannot be seen, so their files are not
+/// listed; [`LoadedFiles`] still recognises them.
+pub async fn loaded_source_files(table: &DeltaTable) -> Result<Vec<SourceFile>, AppError> {
+    Ok(replay_history(table, -1).await?.current)
+}
+
+/// Source files seen replaying a table's history.
+struct Replay {
+    /// Source files of the commits the current version is made of
+    current: Vec<SourceFile>,
+    /// Hashes loaded only by commits that a RESTORE skipped
+    skipped: HashSet<String>,
+}
+
+/// Replays the table history from its version back to the commit after `since`.
+///
+/// Each commit is read from the log by its version, so commits without a
+/// commitInfo do not shift the versions of the others. The commits a RESTORE
+/// skipped are read too, even those at or before `since`.
+async fn replay_history(table: &DeltaTable, since: i64) -> Result<Replay, AppError> {
+    let log = CommitLog { table };
+    let mut current = Vec::new();
+    let mut skipped = HashSet::new();
+    let mut version = table.version().unwrap_or(-1);
+    while version > since {
+        let Some(commit) = log.commit(version).await? else {
+            warn!(
+                "Table history up to version {version} has been cleaned up; files loaded in those commits are not listed"
+            );
+            break;
+        };
+        if restore_target(&commit).is_none() {
+            current.extend(commit_source_files(&commit));
+            version -= 1;
+            continue;
+        }
+        let (target, skipped_commits) = log.restored_version(version, &commit).await?;
+        skipped.extend(
+            skipped_commits
+                .iter()
+                .flat_map(commit_source_files)
+                .map(|file| file.sha256),
+        );
+        version = target.min(version - 1);
+    }
+
+    let current_hashes: HashSet<&str> = current.iter().map(|f| f.sha256.as_str()).collect();
+    skipped.retain(|hash| !current_hashes.contains(hash.as_str()));
+    Ok(Replay { current, skipped })
+}
@@ -0,0 +1,925 @@
+//! Writing parsed records into delta tables
+//!
+//! Records are either appended, merged on the report's natural key, or
+//! replace a report date range. Every path derives the table's partition
+//! columns from each row's report date.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use chrono::{Datelike, NaiveDate, Utc};
+use datafusion::catalog::streaming::StreamingTable;
+use datafusion::error::DataFusionError;
+use datafusion::execution::{SendableRecordBatchStream, TaskContext};
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::physical_plan::streaming::PartitionStream;
+use datafusion::prelude::{col, lit, SessionContext};
+use deltalake::arrow::array::{
+    new_null_array, Array, ArrayRef, AsArray, Int32Array, RecordBatch, TimestampMicrosecondArray,
+    UInt32Array,
+};
+use deltalake::arrow::compute::{cast, concat_batches, take_record_batch};
+use deltalake::arrow::datatypes::{
+    DataType as ArrowDataType, Date32Type, Field, Int32Type, Schema, SchemaRef,
+};
+use deltalake::delta_datafusion::DataFusionMixins;
+use deltalake::parquet::file::properties::WriterProperties;
+use deltalake::protocol::SaveMode;
+use deltalake::{DeltaOps, DeltaTable};
+use log::{info, warn};
+use serde::de::DeserializeOwned;
+
+use super::stream::{commit_append, StreamingAppend, StreamingOptions};
+use super::tracking::source_files_commit_properties;
+use super::{
+    date_scalar, has_lineage, validate_constraints, DeltaReportType, LoadMode, LoadSummary,
+    PartitionValues, TableConstraints,
+};
+use crate::parsers::lineage::{lineage_fields, SourceFile, LINEAGE_COLUMNS};
+use crate::AppError;
+
+/// Reads every record from a pipe-delimited CSV file written by the parsers,
+/// with the 1-based line each record is on.
+pub(super) fn read_csv_records<T: DeserializeOwned>(
+    csv_path: &Path,
+) -> Result<(Vec<T>, Vec<Option<u32>>), AppError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(b'|')
+        .has_headers(true)
+        .from_path(csv_path)?;
+
+    let mut records = Vec::new();
+    let mut lines = Vec::new();
+    let mut row = csv::StringRecord::new();
+    let headers = reader.headers()?.clone();
+    while reader.read_record(&mut row)? {
+        lines.push(row.position().map(|position| position.line() as u32));
+        records.push(row.deserialize(Some(&headers))?);
+    }
+    Ok((records, lines))
+}
+
+/// Parsed records to write, with the file and lines they came from.
+///
+/// Every writer checks its batches against the table constraints, since the
+/// commits made here bypass the constraint checks of the Delta writer; the
+/// source locates offending rows in the error.
+pub(super) struct SourceBatch {
+    pub(super) batch: RecordBatch,
+    pub(super) source_name: String,
+    pub(super) source_lines: Vec<Option<u32>>,
+}
+
+impl SourceBatch {
+    /// Locates the rows of a batch by its `source_file` and `source_line`
+    /// lineage columns, or by the batch's position when it has none.
+    fn from_lineage(batch: RecordBatch, index: usize) -> Self {
+        let source_name = batch
+            .column_by_name("source_file")
+            .and_then(|column| column.as_string_opt::<i32>())
+            .and_then(|files| files.iter().flatten().next().map(str::to_string))
+            .unwrap_or_else(|| format!("batch {}", index + 1));
+        let source_lines = batch
+            .column_by_name("source_line")
+            .and_then(|column| column.as_primitive_opt::<Int32Type>())
+            .map(|lines| {
+                lines
+                    .iter()
+                    .map(|line| line.map(|line| line as u32))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self {
+            batch,
+            source_name,
+            source_lines,
+        }
+    }
+
+    /// Wraps batches given without their sources.
+    fn from_batches(batches: Vec<RecordBatch>) -> Vec<Self> {
+        batches
+            .into_iter()
+            .enumerate()
+            .map(|(index, batch)| Self::from_lineage(batch, index))
+            .collect()
+    }
+
+    /// Fails if a row breaks the constraints of the target table.
+    fn validate(&self, constraints: &TableConstraints) -> Result<(), AppError> {
+        validate_constraints(
+            constraints,
+            &self.batch,
+            &self.source_name,
+            &self.source_lines,
+        )
+    }
+}
+
+/// Writes parsed reports into a delta table as they arrive.
+///
+/// Appends stream each report into parquet within the memory budget, so only
+/// the report being written is held in memory, and add every report in one
+/// commit. Merges hold reports until they would exceed the memory budget and
+/// then merge them in one commit, so a large merge takes several commits,
+/// each recording the source files of its reports. Every report is checked
+/// against the table constraints as it is written.
+///
+/// # Example
+/// ```rust,ignore
+/// let mut writer = ReportWriter::for_table(&table, DeltaReportType::St1, LoadMode::Append, StreamingOptions::default())?;
+/// let report = st1::parse_file("WELLS0102", "data/txt").await?;
+/// writer.write(License::to_record_batch(&report.records)?, report.source, report.source_lines).await?;
+/// let summary = writer.commit(&mut table).await?;
+/// ```
+pub struct ReportWriter {
+    report_type: DeltaReportType,
+    constraints: TableConstraints,
+    partition_columns: Vec<String>,
+    lineage: bool,
+    ingested_at: i64,
+    /// Streaming writer of an append; merges collect their batches in groups instead
+    append: Option<StreamingAppend>,
+    merge: Option<MergeGroups>,
+    summary: LoadSummary,
+}
+
+/// Reports of a merge, merged into the table a group at a time.
+///
+/// Duplicate keys are resolved within a group by [`dedupe_by_key`] and across
+/// groups by later groups updating the rows of earlier ones, so the table ends
+/// up as after one merge of every report.
+struct MergeGroups {
+    /// The table as of the last group merged
+    table: DeltaTable,
+    memory_budget: usize,
+    pending: Vec<SourceBatch>,
+    pending_bytes: usize,
+    /// Number of source files of the summary recorded by earlier groups
+    merged_files: usize,
+    merged_groups: usize,
+}
+
+impl MergeGroups {
+    /// Adds a batch to the pending group, merging the group first if the batch
+    /// would take it over the memory budget.
+    async fn push(
+        &mut self,
+        report_type: DeltaReportType,
+        source: SourceBatch,
+        summary: &mut LoadSummary,
+    ) -> Result<(), AppError> {
+        let bytes = source.batch.get_array_memory_size();
+        if !self.pending.is_empty() && self.pending_bytes + bytes > self.memory_budget {
+            self.merge(report_type, summary).await?;
+        }
+        self.pending_bytes += bytes;
+        self.pending.push(source);
+        Ok(())
+    }
+
+    /// Merges the pending group in one commit, recording the source files
+    /// added to `summary` since the last group, and adds its counts to `summary`.
+    async fn merge(
+        &mut self,
+        report_type: DeltaReportType,
+        summary: &mut LoadSummary,
+    ) -> Result<(), AppError> {
+        let batches = std::mem::take(&mut self.pending);
+        self.pending_bytes = 0;
+        let source_files = &summary.source_files[self.merged_files..];
+        let merged =
+            merge_source_batches(&mut self.table, report_type, batches, source_files).await?;
+        self.merged_files = summary.source_files.len();
+        self.merged_groups += 1;
+        summary.rows += merged.rows;
+        summary.inserted += merged.inserted;
+        summary.updated += merged.updated;
+        summary.partitions.extend(merged.partitions);
+        Ok(())
+    }
+}
+
+impl ReportWriter {
+    /// Creates a writer for the table's current schema, partitioning and constraints.
+    ///
+    /// # Arguments
+    /// * `table` - Table the reports are written to
+    /// * `report_type` - Report type stored in the table, whose key merges match on
+    /// * `mode` - Append the rows or merge them on the natural key
+    /// * `streaming` - Memory budget of appends and merge groups, and row group size of appends
+    pub fn for_table(
+        table: &DeltaTable,
+        report_type: DeltaReportType,
+        mode: LoadMode,
+        streaming: StreamingOptions,
+    ) -> Result<Self, AppError> {
+        let (append, merge) = match mode {
+            LoadMode::Append => (
+                Some(StreamingAppend::for_table(table, streaming)?),
+                None,
+            ),
+            LoadMode::Merge => (
+                None,
+                Some(MergeGroups {
+                    table: table.clone(),
+                    memory_budget: streaming.memory_budget,
+                    pending: Vec::new(),
+                    pending_bytes: 0,
+                    merged_files: 0,
+                    merged_groups: 0,
+                }),
+            ),
+        };
+        Ok(Self {
+            report_type,
+            constraints: TableConstraints::for_table(table)?,
+            partition_columns: table.metadata()?.partition_columns().clone(),
+            lineage: has_lineage(table),
+            ingested_at: Utc::now().timestamp_micros(),
+            append,
+            merge,
+            summary: LoadSummary::default(),
+        })
+    }
+
+    /// Writes the records parsed from one source file.
+    ///
+    /// # Arguments
+    /// * `batch` - Typed records, with or without lineage columns
+    /// * `source` - File the records came from, recorded in the commit
+    /// * `source_lines` - 1-based line of each row in that file, for constraint errors
+    ///
+    /// # Returns
+    /// `AppError::ConstraintViolation` if a row breaks the table constraints,
+    /// in which case nothing of the batch is written and the writer can go on.
+    /// A merge may commit the reports written before, and fail if that does.
+    pub async fn write(
+        &mut self,
+        batch: RecordBatch,
+        source: SourceFile,
+        source_lines: Vec<Option<u32>>,
+    ) -> Result<(), AppError> {
+        let batch = SourceBatch {
+            batch,
+            source_name: source.file_name.clone(),
+            source_lines,
+        };
+        self.write_batch(batch).await?;
+        self.summary.source_files.push(source);
+        Ok(())
+    }
+
+    async fn write_batch(&mut self, source: SourceBatch) -> Result<(), AppError> {
+        if source.batch.num_rows() == 0 {
+            return Ok(());
+        }
+        if let Some(groups) = &mut self.merge {
+            source.validate(&self.constraints)?;
+            return groups
+                .push(self.report_type, source, &mut self.summary)
+                .await;
+        }
+        let Some(writer) = &mut self.append else {
+            return Ok(());
+        };
+
+        let batch = add_partition_columns(&source.batch, &self.partition_columns)?;
+        let batch = align_lineage(&batch, self.lineage, self.ingested_at)?;
+        writer
+            .write(batch.clone(), &source.source_name, &source.source_lines)
+            .await?;
+        self.summary.rows += batch.num_rows();
+        self.summary.inserted += batch.num_rows();
+        self.summary
+            .partitions
+            .extend(batch_partitions(&batch, &self.partition_columns)?);
+        Ok(())
+    }
+
+    /// Commits everything written, recording the source files.
+    ///
+    /// Source files without rows are still recorded, in a commit without data
+    /// files, so they are not loaded again. `table` is updated to the groups a
+    /// merge committed even if a later group fails.
+    ///
+    /// # Returns
+    /// Rows written and the partitions touched
+    pub async fn commit(self, table: &mut DeltaTable) -> Result<LoadSummary, AppError> {
+        let mut summary = self.summary;
+        match (self.append, self.merge) {
+            (Some(writer), _) => {
+                writer.commit(table, &summary.source_files).await?;
+                Ok(summary)
+            }
+            (None, Some(mut groups)) if !groups.pending.is_empty() || groups.merged_groups > 0 => {
+                let merged = groups.merge(self.report_type, &mut summary).await;
+                *table = groups.table;
+                merged?;
+                if groups.merged_groups > 1 {
+                    info!("Merged the reports in {} commits", groups.merged_groups);
+                }
+                Ok(summary)
+            }
+            (None, _) => {
+                commit_append(table, Vec::new(), &summary.source_files).await?;
+                Ok(summary)
+            }
+        }
+    }
+}
+
+/// Load multiple CSV files into the delta table.
+///
+/// CSVs are read one at a time into a [`ReportWriter`], so memory use is
+/// bounded by `streaming` rather than by the number of files. Appends add
+/// every file in a single commit, with output files up to the memory budget
+/// in size; merges commit once per group of files that fits the budget.
+/// CSVs that cannot be read are skipped with a warning; rows breaking the
+/// table constraints fail the whole load.
+pub async fn load_csvs_to_delta(
+    table: &mut DeltaTable,
+    report_type: DeltaReportType,
+    csv_paths: &[&Path],
+    mode: LoadMode,
+    streaming: StreamingOptions,
+) -> Result<LoadSummary, AppError> {
+    let lineage = has_lineage(table);
+    let mut writer = ReportWriter::for_table(table, report_type, mode, streaming)?;
+    for csv_path in csv_paths {
+        let source = SourceFile::from_path(csv_path)?;
+        match report_type.csv_to_record_batch(csv_path, lineage.then_some(&source)) {
+            Ok(batch) => {
+                writer.write_batch(batch).await?;
+                writer.summary.source_files.push(source);
+            }
+            Err(e) => warn!("Could not read CSV file {csv_path:?}, skipping: {e}"),
+        }
+    }
+    writer.commit(table).await
+}
+
+/// Write parsed record batches using the given load mode.
+///
+/// `source_files` are recorded in the commit metadata so later loads can tell
+/// they are already in the table (see [`loaded_file_hashes`](super::loaded_file_hashes)).
+pub async fn write_records(
+    table: &mut DeltaTable,
+    report_type: DeltaReportType,
+    batches: Vec<RecordBatch>,
+    mode: LoadMode,
+    source_files: &[SourceFile],
+) -> Result<LoadSummary, AppError> {
+    match mode {
+        LoadMode::Append => write_batches_to_delta(table, report_type, batches, source_files).await,
+        LoadMode::Merge => {
+            merge_batches_into_delta(table, report_type, batches, source_files).await
+        }
+    }
+}
+
+/// Appends the table's partition columns to a batch, derived from its `date` column.
+pub(super) fn add_partition_columns(
+    batch: &RecordBatch,
+    partition_columns: &[String],
+) -> Result<RecordBatch, AppError> {
+    if partition_columns.is_empty() {
+        return Ok(batch.clone());
+    }
+
+    let dates = batch
+        .column_by_name("date")
+        .ok_or_else(|| {
+            AppError::DeltaTable("Batch has no `date` column to partition by".to_string())
+        })?
+        .as_primitive::<Date32Type>();
+
+    let mut fields: Vec<Field> = batch
+        .schema()
+        .fields()
+        .iter()
+        .map(|field| field.as_ref().clone())
+        .collect();
+    let mut columns: Vec<ArrayRef> = batch.columns().to_vec();
+
+    for name in partition_columns {
+        let values: Int32Array = (0..dates.len())
+            .map(|i| {
+                let date = dates.value_as_date(i).filter(|_| dates.is_valid(i))?;
+                match name.as_str() {
+                    "report_year" => Some(date.year()),
+                    "report_month" => Some(date.month() as i32),
+                    _ => None,
+                }
+            })
+            .collect();
+        fields.push(Field::new(name, ArrowDataType::Int32, true));
+        columns.push(Arc::new(values));
+    }
+
+    Ok(RecordBatch::try_new(
+        Arc::new(Schema::new(fields)),
+        columns,
+    )?)
+}
+
+/// Collects the distinct partition values present in a partitioned batch.
+fn batch_partitions(
+    batch: &RecordBatch,
+    partition_columns: &[String],
+) -> Result<BTreeSet<PartitionValues>, AppError> {
+    let mut partitions = BTreeSet::new();
+    if partition_columns.is_empty() {
+        return Ok(partitions);
+    }
+
+    let columns = partition_columns
+        .iter()
+        .map(|name| {
+            batch
+                .column_by_name(name)
+                .map(|column| column.as_primitive::<Int32Type>())
+                .ok_or_else(|| {
+                    AppError::DeltaTable(format!("Batch has no partition column `{name}`"))
+                })
+        })
+        .collect::<Result<Vec<_>, AppError>>()?;
+
+    for row in 0..batch.num_rows() {
+        let values = partition_columns
+            .iter()
+            .zip(&columns)
+            .filter(|(_, column)| column.is_valid(row))
+            .map(|(name, column)| (name.clone(), column.value(row).to_string()))
+            .collect::<PartitionValues>();
+        if values.len() == partition_columns.len() {
+            partitions.insert(values);
+        }
+    }
+    Ok(partitions)
+}
+
+/// Matches a batch's lineage columns to the table.
+///
+/// For a table with lineage, missing source lineage columns are filled with
+/// nulls and `ingested_at` is set to the load time. For a table without
+/// lineage, any lineage columns of the batch are dropped.
+fn align_lineage(
+    batch: &RecordBatch,
+    table_lineage: bool,
+    ingested_at: i64,
+) -> Result<RecordBatch, AppError> {
+    let schema = batch.schema();
+    let mut fields = Vec::new();
+    let mut columns: Vec<ArrayRef> = Vec::new();
+    for (field, column) in schema.fields().iter().zip(batch.columns()) {
+        if !LINEAGE_COLUMNS.contains(&field.name().as_str()) {
+            fields.push(field.as_ref().clone());
+            columns.push(column.clone());
+        }
+    }
+
+    if table_lineage {
+        for field in lineage_fields() {
+            let column = match (field.name().as_str(), batch.column_by_name(field.name())) {
+                ("ingested_at", _) => Arc::new(
+                    TimestampMicrosecondArray::from(vec![ingested_at; batch.num_rows()])
+                        .with_timezone("UTC"),
+                ) as ArrayRef,
+                (_, Some(column)) => column.clone(),
+                (_, None) => new_null_array(field.data_type(), batch.num_rows()),
+            };
+            fields.push(field);
+            columns.push(column);
+        }
+    }
+
+    Ok(RecordBatch::try_new(
+        Arc::new(Schema::new(fields)),
+        columns,
+    )?)
+}
+
+/// Write parsed record batches into the delta table as a single append.
+/// This is the append mode of [`write_records`], for batches already in memory.
+///
+/// Partition columns of the table are derived from each row's report date,
+/// so rows land in the matching `report_year`/`report_month` partitions.
+/// Rows breaking the constraints the table declares fail the write,
+/// located by their lineage columns if the batches have them.
+pub async fn write_batches_to_delta(
+    table: &mut DeltaTable,
+    report_type: DeltaReportType,
+    batches: Vec<RecordBatch>,
+    source_files: &[SourceFile],
+) -> Result<LoadSummary, AppError> {
+    let mut writer = ReportWriter::for_table(
+        table,
+        report_type,
+        LoadMode::Append,
+        StreamingOptions::default(),
+    )?;
+    for batch in SourceBatch::from_batches(batches) {
+        writer.write_batch(batch).await?;
+    }
+    writer.summary.source_files = source_files.to_vec();
+    writer.commit(table).await
+}
+
+/// Replace every row with a report date in `start..=end` by the given batches, in one commit.
+///
+/// Rows in the range are deleted and the batches written in the same commit
+/// (a `replaceWhere` overwrite), so readers see either the old or the new
+/// rows for the range, never both or neither. Every batch row must have a
+/// report date inside the range and meet the table constraints.
+///
+/// # Arguments
+/// * `table` - Table to rewrite, updated to the new version
+/// * `batches` - Parsed records replacing the range
+/// * `start` - First report date to replace
+/// * `end` - Last report date to replace
+/// * `source_files` - Source files recorded in the commit metadata
+///
+/// # Returns
+/// Rows written and deleted, and the partitions touched
+pub async fn replace_date_range(
+    table: &mut DeltaTable,
+    batches: Vec<RecordBatch>,
+    start: NaiveDate,
+    end: NaiveDate,
+    source_files: &[SourceFile],
+) -> Result<LoadSummary, AppError> {
+    replace_source_batches(
+        table,
+        SourceBatch::from_batches(batches).into_iter().map(Ok),
+        (start, end),
+        source_files,
+        StreamingOptions::default(),
+    )
+    .await
+}
+
+/// [`replace_date_range`] for batches whose sources are known, read one at a time.
+///
+/// The batches are pulled from `batches` as the write consumes them, so only
+/// the batch being written and the output files up to `streaming.memory_budget`
+/// are held in memory, however many files replace the range.
+pub(super) async fn replace_source_batches(
+    table: &mut DeltaTable,
+    batches: impl Iterator<Item = Result<SourceBatch, AppError>> + Send + 'static,
+    (start, end): (NaiveDate, NaiveDate),
+    source_files: &[SourceFile],
+    streaming: StreamingOptions,
+) -> Result<LoadSummary, AppError> {
+    let partition_columns = table.metadata()?.partition_columns().clone();
+    let schema = table.snapshot()?.arrow_schema()?;
+    let replacement = Arc::new(Replacement {
+        constraints: TableConstraints::for_table(table)?,
+        range: (start, end),
+        lineage: has_lineage(table),
+        ingested_at: Utc::now().timestamp_micros(),
+        schema: schema.clone(),
+        partition_columns: partition_columns.clone(),
+        summary: Mutex::default(),
+        error: Mutex::default(),
+    });
+    let rows = StreamingTable::try_new(
+        schema.clone(),
+        vec![Arc::new(ReplacementRows {
+            replacement: replacement.clone(),
+            batches: Mutex::new(Some(Box::new(batches))),
+        })],
+    )?;
+
+    let mut predicate = col("date").between(lit(date_scalar(start)), lit(date_scalar(end)));
+    // Also bound the year partition so only files of those years are scanned
+    if partition_columns.iter().any(|column| column == "report_year") {
+        predicate = predicate.and(col("report_year").between(lit(start.year()), lit(end.year())));
+    }
+    let ctx = SessionContext::new();
+    let deleted = ctx
+        .read_table(Arc::new(table.clone()))?
+        .filter(predicate.clone())?
+        .count()
+        .await?;
+
+    let properties = WriterProperties::builder()
+        .set_max_row_group_size(streaming.row_group_size.max(1))
+        .build();
+    let written = DeltaOps::from(table.clone())
+        .write(Vec::new())
+        .with_input_execution_plan(Arc::new(
+            ctx.read_table(Arc::new(rows))?.into_unoptimized_plan(),
+        ))
+        .with_target_file_size(streaming.memory_budget.max(1))
+        .with_writer_properties(properties)
+        .with_save_mode(SaveMode::Overwrite)
+        .with_replace_where(predicate)
+        .with_commit_properties(source_files_commit_properties(source_files))
+        .await;
+    if let Some(error) = lock(&replacement.error).take() {
+        return Err(error);
+    }
+    *table = written?;
+
+    let mut summary = std::mem::take(&mut *lock(&replacement.summary));
+    summary.deleted = deleted;
+    summary.source_files = source_files.to_vec();
+    info!(
+        "Replaced {} rows dated {start} to {end} with {} rows",
+        summary.deleted, summary.rows
+    );
+    Ok(summary)
+}
+
+/// Locks a mutex, also after a panic while it was held.
+pub(super) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// How the batches of a [`replace_source_batches`] write are prepared.
+#[derive(Debug)]
+struct Replacement {
+    constraints: TableConstraints,
+    range: (NaiveDate, NaiveDate),
+    lineage: bool,
+    ingested_at: i64,
+    schema: SchemaRef,
+    partition_columns: Vec<String>,
+    /// Rows and partitions of the batches prepared so far
+    summary: Mutex<LoadSummary>,
+    /// Why preparing a batch failed; the write only passes on its message
+    error: Mutex<Option<AppError>>,
+}
+
+impl Replacement {
+    /// Checks a batch and gives it the table's partition and lineage columns.
+    fn prepare(&self, source: SourceBatch) -> Result<RecordBatch, AppError> {
+        let (start, end) = self.range;
+        source.validate(&self.constraints)?;
+        let outside = date_rows_outside(&source.batch, start, end)?;
+        if outside > 0 {
+            return Err(AppError::DeltaTable(format!(
+                "{outside} rows have a report date outside {start} to {end}"
+            )));
+        }
+        let batch = add_partition_columns(&source.batch, &self.partition_columns)?;
+        let batch = align_lineage(&batch, self.lineage, self.ingested_at)?;
+        let columns = self
+            .schema
+            .fields()
+            .iter()
+            .map(|field| {
+                batch.column_by_name(field.name()).cloned().ok_or_else(|| {
+                    AppError::DeltaTable(format!("Batch has no column `{}`", field.name()))
+                })
+            })
+            .collect::<Result<Vec<_>, AppError>>()?;
+
+        let mut summary = lock(&self.summary);
+        summary.rows += batch.num_rows();
+        summary.inserted += batch.num_rows();
+        summary
+            .partitions
+            .extend(batch_partitions(&batch, &self.partition_columns)?);
+        Ok(RecordBatch::try_new(self.schema.clone(), columns)?)
+    }
+}
+
+/// Batches still to be read for a replacement
+type SourceBatches = Box<dyn Iterator<Item = Result<SourceBatch, AppError>> + Send>;
+
+/// The rows replacing a date range, as a stream the delta writer reads.
+struct ReplacementRows {
+    replacement: Arc<Replacement>,
+    batches: Mutex<Option<SourceBatches>>,
+}
+
+impl fmt::Debug for ReplacementRows {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReplacementRows")
+            .field("replacement", &self.replacement)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PartitionStream for ReplacementRows {
+    fn schema(&self) -> &SchemaRef {
+        &self.replacement.schema
+    }
+
+    /// Streams the batches once; the stream is empty when executed again.
+    fn execute(&self, _ctx: Arc<TaskContext>) -> SendableRecordBatchStream {
+        let replacement = self.replacement.clone();
+        let batches = lock(&self.batches).take().into_iter().flatten();
+        let prepared = batches.map(move |source| {
+            source
+                .and_then(|source| replacement.prepare(source))
+                .map_err(|error| {
+                    let message = error.to_string();
+                    lock(&replacement.error).get_or_insert(error);
+                    DataFusionError::Execution(message)
+                })
+        });
+        Box::pin(RecordBatchStreamAdapter::new(
+            self.replacement.schema.clone(),
+            futures::stream::iter(prepared),
+        ))
+    }
+}
+
+/// Number of rows whose report date is null or outside `start..=end`.
+fn date_rows_outside(
+    batch: &RecordBatch,
+    start: NaiveDate,
+    end: NaiveDate,
+) -> Result<usize, AppError> {
+    let dates = batch
+        .column_by_name("date")
+        .ok_or_else(|| AppError::DeltaTable("Batch has no `date` column".to_string()))?
+        .as_primitive::<Date32Type>();
+    Ok((0..dates.len())
+        .filter(|&row| {
+            !dates
+                .value_as_date(row)
+                .filter(|_| dates.is_valid(row))
+                .is_some_and(|date| start <= date && date <= end)
+        })
+        .count())
+}
+
+/// Keeps only the last row for each key, so a source never matches a target row twice.
+fn dedupe_by_key(batch: &RecordBatch, keys: &[&str]) -> Result<RecordBatch, AppError> {
+    let columns = keys
+        .iter()
+        .map(|key| {
+            let column = batch
+                .column_by_name(key)
+                .ok_or_else(|| AppError::DeltaTable(format!("Batch has no key column `{key}`")))?;
+            Ok(cast(column, &ArrowDataType::Utf8)?)
+        })
+        .collect::<Result<Vec<ArrayRef>, AppError>>()?;
+
+    let mut last_row = HashMap::new();
+    for row in 0..batch.num_rows() {
+        let key: Vec<Option<&str>> = columns
+            .iter()
+            .map(|column| {
+                let strings = column.as_string::<i32>();
+                strings.is_valid(row).then(|| strings.value(row))
+            })
+            .collect();
+        last_row.insert(key, row as u32);
+    }
+
+    if last_row.len() == batch.num_rows() {
+        return Ok(batch.clone());
+    }
+    warn!(
+        "Dropping {} rows with duplicate keys {keys:?} before merging",
+        batch.num_rows() - last_row.len()
+    );
+    let mut rows: Vec<u32> = last_row.into_values().collect();
+    rows.sort_unstable();
+    Ok(take_record_batch(batch, &UInt32Array::from(rows))?)
+}
+
+/// Upsert parsed record batches into the delta table with MERGE.
+///
+/// Rows are matched on the report's natural key (see [`DeltaReportType::merge_keys`]).
+/// Matching rows are only rewritten when a non-key column changed, so loading
+/// the same data again commits nothing, while corrected data updates rows in place.
+/// The batches are merged in groups within the default memory budget, one
+/// commit per group, and the source files are recorded with the last group.
+/// Rows breaking the table constraints fail the merge, located by their
+/// lineage columns if the batches have them.
+pub async fn merge_batches_into_delta(
+    table: &mut DeltaTable,
+    report_type: DeltaReportType,
+    batches: Vec<RecordBatch>,
+    source_files: &[SourceFile],
+) -> Result<LoadSummary, AppError> {
+    let mut writer = ReportWriter::for_table(
+        table,
+        report_type,
+        LoadMode::Merge,
+        StreamingOptions::default(),
+    )?;
+    for batch in SourceBatch::from_batches(batches) {
+        writer.write_batch(batch).await?;
+    }
+    writer.summary.source_files = source_files.to_vec();
+    writer.commit(table).await
+}
+
+/// Merges batches already checked against the constraints with a single MERGE.
+/// Without rows, the source files are recorded in a commit of their own.
+async fn merge_source_batches(
+    table: &mut DeltaTable,
+    report_type: DeltaReportType,
+    batches: Vec<SourceBatch>,
+    source_files: &[SourceFile],
+) -> Result<LoadSummary, AppError> {
+    let partition_columns = table.metadata()?.partition_columns().clone();
+    let lineage = has_lineage(table);
+    let ingested_at = Utc::now().timestamp_micros();
+
+    let mut partitioned = Vec::new();
+    for source in batches.iter().filter(|source| source.batch.num_rows() > 0) {
+        let batch = add_partition_columns(&source.batch, &partition_columns)?;
+        partitioned.push(align_lineage(&batch, lineage, ingested_at)?);
+    }
+    let Some(first) = partitioned.first() else {
+        commit_append(table, Vec::new(), source_files).await?;
+        return Ok(LoadSummary {
+            source_files: source_files.to_vec(),
+            ..LoadSummary::default()
+        });
+    };
+    let combined = concat_batches(&first.schema(), &partitioned)?;
+
+    let keys = report_type.merge_keys();
+    let source = dedupe_by_key(&combined, keys)?;
+    let partitions = batch_partitions(&source, &partition_columns)?;
+
+    // Partition columns are derived from the date key, so matching on them only prunes files
+    let predicate = keys
+        .iter()
+        .map(|key| key.to_string())
+        .chain(partition_columns.iter().cloned())
+        .map(|column| format!("target.{column} = source.{column}"))
+        .collect::<Vec<_>>()
+        .join(" AND ");
+    let value_columns: Vec<String> = source
+        .schema()
+        .fields()
+        .iter()
+        .map(|field| field.name().clone())
+        .filter(|name| !keys.contains(&name.as_str()) && !partition_columns.contains(name))
+        .collect();
+    // Lineage differs on every load, so it alone does not make a row changed
+    let changed = value_columns
+        .iter()
+        .filter(|column| !LINEAGE_COLUMNS.contains(&column.as_str()))
+        .map(|column| format!("(target.{column} IS DISTINCT FROM source.{column})"))
+        .collect::<Vec<_>>()
+        .join(" OR ");
+    let all_columns: Vec<String> = source
+        .schema()
+        .fields()
+        .iter()
+        .map(|field| field.name().clone())
+        .collect();
+
+    let source_df = SessionContext::new().read_batch(source)?;
+    let (merged, metrics) = DeltaOps::from(table.clone())
+        .merge(source_df, predicate)
+        .with_source_alias("source")
+        .with_target_alias("target")
+        .with_commit_properties(source_files_commit_properties(source_files))
+        .when_matched_update(|update| {
+            value_columns
+                .iter()
+                .fold(update.predicate(changed), |update, column| {
+                    update.update(column.as_str(), format!("source.{column}"))
+                })
+        })?
+        .when_not_matched_insert(|insert| {
+            all_columns.iter().fold(insert, |insert, column| {
+                insert.set(column.as_str(), format!("source.{column}"))
+            })
+        })?
+        .await?;
+    *table = merged;
+
+    info!(
+        "Merged {} source rows: {} inserted, {} updated",
+        metrics.num_source_rows, metrics.num_target_rows_inserted, metrics.num_target_rows_updated
+    );
+    Ok(LoadSummary {
+        rows: metrics.num_target_rows_inserted + metrics.num_target_rows_updated,
+        inserted: metrics.num_target_rows_inserted,
+        updated: metrics.num_target_rows_updated,
+        deleted: 0,
+        partitions,
+        source_files: source_files.to_vec(),
+    })
+}
+
+/// Legacy function for loading a single CSV file (kept for backward compatibility)
+pub async fn load_csv_to_delta(
+    table: &mut DeltaTable,
+    report_type: DeltaReportType,
+    csv_path: &Path,
+) -> Result<LoadSummary, AppError> {
+    load_csvs_to_delta(
+        table,
+        report_type,
+        &[csv_path],
+        LoadMode::Append,
+        StreamingOptions::default(),
+    )
+    .await
+}
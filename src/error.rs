@@ -20,6 +20,14 @@ pub enum AppError {
     Join(#[from] tokio::task::JoinError),
     #[error("Delta lake error: {0}")]
     Delta(#[from] deltalake::DeltaTableError),
+    #[error("Object store error: {0}")]
+    ObjectStore(#[from] deltalake::ObjectStoreError),
+    #[error("Delta table error: {0}")]
+    DeltaTable(String),
+    #[error("Constraint violation: {0}")]
+    ConstraintViolation(String),
+    #[error("DataFusion error: {0}")]
+    DataFusion(#[from] datafusion::error::DataFusionError),
     #[error("Arrow error: {0}")]
     Arrow(#[from] deltalake::arrow::error::ArrowError),
     #[error("Serde JSON error: {0}")]
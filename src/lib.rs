@@ -1,18 +1,28 @@
+pub mod delta;
 pub mod downloader;
 pub mod error;
 pub mod parsers;
+pub mod server;
 pub mod st1;
 pub mod st49;
+pub mod storage;
 pub mod utils;
 
-use chrono::{Datelike, NaiveDate};
+use chrono::NaiveDate;
 use clap::ValueEnum;
+use delta::{LoadSummary, LoadedFiles, ReportWriter};
+use deltalake::arrow::array::RecordBatch;
+use deltalake::DeltaTable;
 pub use error::AppError;
 use futures::stream::{self, StreamExt};
 use log::info;
+use parsers::common::write_report_csv;
+use parsers::lineage::{append_source_lineage, SourceFile, PARSER_VERSION};
+use parsers::record_batch::ArrowRecord;
 use std::fs;
 use std::io;
 use std::path::Path;
+use storage::{FetchedDir, StagedDir};
 use tempfile::NamedTempFile;
 use zip::ZipArchive;
 
@@ -34,6 +44,31 @@ impl std::str::FromStr for ReportType {
     }
 }
 
+/// Destinations that parsed report records can be written to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Sink {
+    /// Pipe-delimited CSV files in the CSV output directory
+    Csv,
+    /// Arrow record batches written straight into a Delta table
+    Delta,
+}
+
+fn check_date_in_range(
+    processed_date: NaiveDate,
+    start_date: Option<NaiveDate>,
+    end_date: Option<NaiveDate>,
+    filename_stem: &str,
+) -> Result<(), AppError> {
+    if let (Some(s_date), Some(e_date)) = (start_date, end_date) {
+        if processed_date < s_date || processed_date > e_date {
+            return Err(AppError::FileProcessing(format!(
+                "Date in file content ({processed_date}) is outside the specified range ({s_date} - {e_date}) for file: {filename_stem}"
+            )));
+        }
+    }
+    Ok(())
+}
+
 pub async fn process_file(
     report_type: ReportType,
     filename_stem: &str,
@@ -42,21 +77,92 @@ pub async fn process_file(
     start_date: Option<NaiveDate>,
     end_date: Option<NaiveDate>,
 ) -> Result<(), AppError> {
+    let csv_output = StagedDir::new(csv_output_dir)?;
     let processed_date = match report_type {
-        ReportType::St1 => st1::process_file(filename_stem, txt_input_dir, csv_output_dir).await?,
+        ReportType::St1 => {
+            st1::process_file(filename_stem, txt_input_dir, csv_output.path()).await?
+        }
         ReportType::St49 => {
-            st49::process_file(filename_stem, txt_input_dir, csv_output_dir).await?
+            st49::process_file(filename_stem, txt_input_dir, csv_output.path()).await?
         }
     };
+    csv_output.publish().await?;
 
-    if let (Some(s_date), Some(e_date)) = (start_date, end_date) {
-        if processed_date < s_date || processed_date > e_date {
-            return Err(AppError::FileProcessing(format!(
-                "Date in file content ({processed_date}) is outside the specified range ({s_date} - {e_date}) for file: {filename_stem}"
-            )));
+    check_date_in_range(processed_date, start_date, end_date, filename_stem)
+}
+
+/// Parse a single report file straight into an Arrow `RecordBatch`.
+///
+/// CSV output is optional: when `csv_output_dir` is `None` no CSV file is written.
+/// With `lineage` set, the source file, its hash, each row's source line and the
+/// parser version are appended as extra columns (see [`parsers::lineage`]).
+pub async fn parse_file_to_batch(
+    report_type: ReportType,
+    filename_stem: &str,
+    txt_input_dir: &str,
+    csv_output_dir: Option<&str>,
+    start_date: Option<NaiveDate>,
+    end_date: Option<NaiveDate>,
+    lineage: bool,
+) -> Result<RecordBatch, AppError> {
+    let parsed = parse_file_to_source_batch(
+        report_type,
+        filename_stem,
+        txt_input_dir,
+        csv_output_dir,
+        (start_date, end_date),
+        lineage,
+    )
+    .await?;
+    Ok(parsed.batch)
+}
+
+/// A report parsed into a record batch, with the file and line of its rows.
+struct ParsedBatch {
+    batch: RecordBatch,
+    source: SourceFile,
+    source_lines: Vec<Option<u32>>,
+}
+
+/// [`parse_file_to_batch`], keeping the source of the records.
+async fn parse_file_to_source_batch(
+    report_type: ReportType,
+    filename_stem: &str,
+    txt_input_dir: &str,
+    csv_output_dir: Option<&str>,
+    (start_date, end_date): (Option<NaiveDate>, Option<NaiveDate>),
+    lineage: bool,
+) -> Result<ParsedBatch, AppError> {
+    let (processed_date, batch, source_lines, source) = match report_type {
+        ReportType::St1 => {
+            let report = st1::parse_file(filename_stem, txt_input_dir).await?;
+            if let Some(dir) = csv_output_dir {
+                write_report_csv(&report, Path::new(dir), "WELLS")?;
+            }
+            let batch = st1::License::to_record_batch(&report.records)?;
+            (report.date, batch, report.source_lines, report.source)
         }
-    }
-    Ok(())
+        ReportType::St49 => {
+            let report = st49::parse_file(filename_stem, txt_input_dir).await?;
+            if let Some(dir) = csv_output_dir {
+                write_report_csv(&report, Path::new(dir), "SPUD")?;
+            }
+            let batch = st49::SpudData::to_record_batch(&report.records)?;
+            (report.date, batch, report.source_lines, report.source)
+        }
+    };
+    let batch = if lineage {
+        append_source_lineage(&batch, &source, &source_lines, Some(PARSER_VERSION))?
+    } else {
+        batch
+    };
+
+    check_date_in_range(processed_date, start_date, end_date, filename_stem)?;
+    Ok(ParsedBatch {
+        batch,
+        source,
+        source_lines,
+    })
 }
 
 pub async fn move_to_conversion_errors(
@@ -78,29 +184,34 @@ pub async fn process_folder(
     folder_path: &str,
     csv_output_dir: &str,
 ) -> Result<(), AppError> {
-    let entries = fs::read_dir(folder_path)?
-        .map(|res| res.map(|e| e.path()))
-        .collect::<Result<Vec<_>, io::Error>>()?;
+    // Object store inputs are downloaded one file at a time as they are processed
+    let txt_input = FetchedDir::new(folder_path)?;
+    let entries = storage::list_files(folder_path).await?;
 
     let futures = stream::iter(entries)
-        .map(|path| {
+        .map(|name| {
             let csv_output_dir_clone = csv_output_dir.to_string();
-            let folder_path_clone = folder_path.to_string();
+            let txt_input = &txt_input;
             async move {
-                if path.is_file() {
-                    if let Some(filename_stem) = path.file_stem().and_then(|s| s.to_str()) {
-                        if let Err(e) = process_file(
-                            report_type,
-                            filename_stem,
-                            &folder_path_clone,
-                            &csv_output_dir_clone,
-                            None,
-                            None,
-                        )
-                        .await
-                        {
-                            eprintln!("Failed to process file {filename_stem:?}: {e}");
-                        }
+                let path = match txt_input.fetch(&name).await {
+                    Ok(path) => path,
+                    Err(e) => {
+                        eprintln!("Failed to read file {name:?}: {e}");
+                        return;
+                    }
+                };
+                if let Some(filename_stem) = path.file_stem().and_then(|s| s.to_str()) {
+                    if let Err(e) = process_file(
+                        report_type,
+                        filename_stem,
+                        txt_input.path(),
+                        &csv_output_dir_clone,
+                        None,
+                        None,
+                    )
+                    .await
+                    {
+                        eprintln!("Failed to process file {filename_stem:?}: {e}");
                     }
                 }
             }
@@ -120,14 +231,98 @@ pub async fn process_date_range(
     txt_output_dir: &str,
     csv_output_dir: &str,
 ) -> Result<(), AppError> {
-    let downloaded_files =
-        downloader::download_files_by_date_range(report_type, start_date, end_date, txt_output_dir)
-            .await?;
+    date_range_reports(
+        report_type,
+        start_date,
+        end_date,
+        txt_output_dir,
+        Some(csv_output_dir),
+        None,
+    )
+    .await?;
+    Ok(())
+}
 
-    let futures = stream::iter(downloaded_files)
+/// Download and parse files within a date range straight into a Delta table.
+///
+/// Each report is written through `writer` as soon as it is parsed, so memory
+/// use does not grow with the length of the range, and the writer is then
+/// committed. CSV files are only written when `csv_output_dir` is given.
+/// Lineage columns are added when the table has them. A report whose records
+/// break the table constraints is moved to `data/conversion_errors` like any
+/// other report that fails to parse, and the other reports are still loaded.
+///
+/// # Arguments
+/// * `report_type` - Report type to download
+/// * `start_date` - First report date
+/// * `end_date` - Last report date
+/// * `txt_output_dir` - Directory or URI the TXT reports are archived in
+/// * `csv_output_dir` - Directory or URI for CSV files, if they are kept
+/// * `table` - Delta table written to, updated to the new version
+/// * `writer` - Writer created for `table` with the load mode and memory budget
+///
+/// # Returns
+/// Rows written and the source files recorded in the commit
+///
+/// # Example
+/// ```rust,ignore
+/// let writer = ReportWriter::for_table(&table, DeltaReportType::St1, LoadMode::Append, StreamingOptions::default())?;
+/// let summary = process_date_range_to_delta(ReportType::St1, start, end, "data/txt", None, &mut table, writer).await?;
+/// ```
+pub async fn process_date_range_to_delta(
+    report_type: ReportType,
+    start_date: NaiveDate,
+    end_date: NaiveDate,
+    txt_output_dir: &str,
+    csv_output_dir: Option<&str>,
+    table: &mut DeltaTable,
+    writer: ReportWriter,
+) -> Result<LoadSummary, AppError> {
+    let summary = date_range_reports(
+        report_type,
+        start_date,
+        end_date,
+        txt_output_dir,
+        csv_output_dir,
+        Some((table, writer)),
+    )
+    .await?;
+    Ok(summary.unwrap_or_default())
+}
+
+async fn date_range_reports(
+    report_type: ReportType,
+    start_date: NaiveDate,
+    end_date: NaiveDate,
+    txt_output_dir: &str,
+    csv_output_dir: Option<&str>,
+    // Table and writer the parsed reports go to; without them only CSV files are written
+    mut delta: Option<(&mut DeltaTable, ReportWriter)>,
+) -> Result<Option<LoadSummary>, AppError> {
+    // Object store outputs are written locally first and uploaded at the end
+    let txt_archive = StagedDir::new(txt_output_dir)?;
+    let csv_output = csv_output_dir.map(StagedDir::new).transpose()?;
+    let downloaded_files = downloader::download_files_by_date_range(
+        report_type,
+        start_date,
+        end_date,
+        txt_archive.path(),
+    )
+    .await?;
+    let lineage = delta
+        .as_ref()
+        .map(|(table, _)| delta::has_lineage(table));
+    // Reports already in the table are not parsed again
+    let loaded = match &delta {
+        Some((table, _)) => Some(LoadedFiles::for_table(table).await?),
+        None => None,
+    };
+    let loaded = loaded.as_ref();
+
+    let mut reports = stream::iter(downloaded_files)
         .map(|filename| {
-            let txt_output_dir_clone = txt_output_dir.to_string();
-            let csv_output_dir_clone = csv_output_dir.to_string();
+            let txt_output_dir_clone = txt_archive.path().to_string();
+            let csv_output_dir_clone = csv_output.as_ref().map(|dir| dir.path().to_string());
             async move {
                 let (prefix, extension) = match report_type {
                     ReportType::St1 => ("WELLS", "TXT"),
@@ -138,6 +333,16 @@ pub async fn process_date_range(
 
                 // Read contents and extract date first
                 let contents = fs::read_to_string(&original_full_filename)?;
+                if let Some(loaded) = loaded {
+                    let source = SourceFile::from_contents(
+                        Path::new(&original_full_filename),
+                        contents.as_bytes(),
+                    );
+                    if loaded.contains(&source.sha256).await? {
+                        info!("Skipping already loaded file {original_full_filename}");
+                        return Ok(None);
+                    }
+                }
                 let date_str = match report_type {
                     ReportType::St1 => contents.lines().nth(6).unwrap_or_default(),
                     ReportType::St49 => contents.lines().nth(1).unwrap_or_default(),
@@ -176,22 +381,42 @@ pub async fn process_date_range(
                         .unwrap_or_default();
                     // info!("Processing file: {filename_stem:?}");
 
-                    if let Err(e) = process_file(
-                        report_type,
-                        filename_stem,
-                        &txt_output_dir_clone,
-                        &csv_output_dir_clone,
-                        Some(start_date),
-                        Some(end_date),
-                    )
-                    .await
-                    {
-                        eprintln!("Failed to process file {original_full_filename:?}: {e}");
-                        move_to_conversion_errors(
-                            Path::new(&original_full_filename),
-                            &e.to_string(),
+                    let result = if let Some(lineage) = lineage {
+                        parse_file_to_source_batch(
+                            report_type,
+                            filename_stem,
+                            &txt_output_dir_clone,
+                            csv_output_dir_clone.as_deref(),
+                            (Some(start_date), Some(end_date)),
+                            lineage,
+                        )
+                        .await
+                        .map(Some)
+                    } else {
+                        process_file(
+                            report_type,
+                            filename_stem,
+                            &txt_output_dir_clone,
+                            csv_output_dir_clone.as_deref().unwrap_or_default(),
+                            Some(start_date),
+                            Some(end_date),
                         )
-                        .await?;
+                        .await
+                        .map(|_| None)
+                    };
+
+                    match result {
+                        Ok(parsed) => {
+                            return Ok(parsed.map(|parsed| (original_full_filename, parsed)))
+                        }
+                        Err(e) => {
+                            eprintln!("Failed to process file {original_full_filename:?}: {e}");
+                            move_to_conversion_errors(
+                                Path::new(&original_full_filename),
+                                &e.to_string(),
+                            )
+                            .await?;
+                        }
                     }
                 } else {
                     println!(
@@ -203,19 +428,44 @@ pub async fn process_date_range(
                     )
                     .await?;
                 }
-                Ok::<(), AppError>(())
+                Ok::<Option<(String, ParsedBatch)>, AppError>(None)
             }
         })
-        .buffer_unordered(10) // 10 concurrent tasks
-        .collect::<Vec<_>>();
+        .buffer_unordered(10); // 10 concurrent tasks
 
-    for future in futures.await {
-        if let Err(e) = future {
-            eprintln!("An error occurred: {e}");
+    // Each report is written as it arrives instead of being collected first
+    while let Some(result) = reports.next().await {
+        match result {
+            Ok(Some((txt_path, parsed))) => {
+                let Some((_, writer)) = delta.as_mut() else {
+                    continue;
+                };
+                match writer
+                    .write(parsed.batch, parsed.source, parsed.source_lines)
+                    .await
+                {
+                    Ok(()) => {}
+                    Err(e @ AppError::ConstraintViolation(_)) => {
+                        eprintln!("Failed to process file {txt_path:?}: {e}");
+                        move_to_conversion_errors(Path::new(&txt_path), &e.to_string()).await?;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(None) => {}
+            Err(e) => eprintln!("An error occurred: {e}"),
         }
     }
+    drop(reports);
 
-    Ok(())
+    txt_archive.publish().await?;
+    if let Some(csv_output) = csv_output {
+        csv_output.publish().await?;
+    }
+    match delta {
+        Some((table, writer)) => Ok(Some(writer.commit(table).await?)),
+        None => Ok(None),
+    }
 }
 
 pub async fn process_single_zip_file(
@@ -252,6 +502,9 @@ pub async fn process_single_zip_file(
             })?
     };
 
+    // Nested zips are passed the staging directory, which is already local
+    let txt_archive = StagedDir::new(txt_output_dir)?;
+    let txt_output_dir = txt_archive.path();
     let txt_output_dir_path = Path::new(txt_output_dir);
     if !txt_output_dir_path.exists() {
         fs::create_dir_all(txt_output_dir_path)?;
@@ -316,6 +569,7 @@ pub async fn process_single_zip_file(
             info!("Skipping unknown file type: {outpath:?}");
         }
     }
+    txt_archive.publish().await?;
     Ok(())
 }
 
@@ -325,12 +579,10 @@ pub async fn process_zip_folder(
     txt_output_dir: &str,
     csv_output_dir: &str,
 ) -> Result<(), AppError> {
-    let entries = fs::read_dir(folder_path)?
-        .map(|res| res.map(|e| e.path()))
-        .collect::<Result<Vec<_>, io::Error>>()?;
-
-    for path in entries {
-        if path.is_file() && path.extension().and_then(|s| s.to_str()) == Some("zip") {
+    let zip_input = FetchedDir::new(folder_path)?;
+    for name in storage::list_files(folder_path).await? {
+        if Path::new(&name).extension().and_then(|s| s.to_str()) == Some("zip") {
+            let path = zip_input.fetch(&name).await?;
             process_single_zip_file(&path, report_type, txt_output_dir, csv_output_dir, None)
                 .await?;
         }
@@ -1,11 +1,24 @@
 use aer_st1::{
-    process_date_range, process_file, process_folder, process_zip_folder, AppError, ReportType,
+    process_date_range, process_date_range_to_delta, process_file, process_folder,
+    process_zip_folder, AppError, ReportType, Sink,
 };
-use chrono::NaiveDate;
-use clap::{Parser, Subcommand};
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use clap::{Args, Parser, Subcommand};
 use log::info;
-use std::path::Path;
-mod delta;
+use aer_st1::delta::{
+    create_or_open_delta_table, default_query_tables, export_delta_table, format_query_result,
+    load_delta, maintain_delta_table, migrate_delta_table, open_delta_table_at, reconcile_delta,
+    reload_delta, restore_delta, run_query, ExportFormat, ExportOptions, ReportWriter,
+    LoadDeltaOptions, LoadMode, MaintenanceOptions, MaintenanceReport, Partitioning,
+    QueryFormat, QuerySource, ReloadOptions, RestoreOptions, StreamingOptions, TableVersion,
+    read_rig_timeline, update_licence_spud, update_rig_timeline, read_company_aliases,
+    resolve_st1_licensees, update_company_registry, diff_reports, DiffSide, DeltaReportType,
+    compute_stats, Statistic, StatsOptions,
+};
+use aer_st1::server::{serve, ServeOptions};
+use std::collections::BTreeSet;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -14,6 +27,86 @@ struct Cli {
     command: Commands,
 }
 
+/// Optimize and vacuum settings shared by `load-delta` and `maintain`
+#[derive(Args, Debug)]
+struct MaintenanceArgs {
+    /// Z-order data files by these columns when optimizing (e.g. licence_number,licensee)
+    #[arg(long, value_delimiter = ',')]
+    z_order: Vec<String>,
+    /// Keep unreferenced files this many hours before vacuum deletes them (default: table setting, 7 days)
+    #[arg(long)]
+    vacuum_retention_hours: Option<u64>,
+    /// List the files vacuum would delete without deleting them
+    #[arg(long)]
+    vacuum_dry_run: bool,
+}
+
+impl MaintenanceArgs {
+    fn options(&self, optimize: bool, vacuum: bool) -> MaintenanceOptions {
+        MaintenanceOptions {
+            optimize,
+            z_order: self.z_order.clone(),
+            vacuum,
+            retention_hours: self.vacuum_retention_hours,
+            dry_run: self.vacuum_dry_run,
+        }
+    }
+}
+
+/// Print the files a vacuum dry run would delete.
+fn print_vacuum_dry_run(options: &MaintenanceOptions, report: &MaintenanceReport) {
+    if options.vacuum && options.dry_run {
+        println!("Vacuum would delete {} files:", report.vacuumed_files.len());
+        for file in &report.vacuumed_files {
+            println!("{file}");
+        }
+    }
+}
+
+/// Streaming options from `--memory-budget-mb` and `--row-group-size`.
+fn streaming_options(
+    memory_budget_mb: usize,
+    row_group_size: usize,
+) -> Result<StreamingOptions, AppError> {
+    let memory_budget = memory_budget_mb.checked_mul(1024 * 1024).ok_or_else(|| {
+        AppError::Cli(format!(
+            "--memory-budget-mb {memory_budget_mb} is too large"
+        ))
+    })?;
+    Ok(StreamingOptions {
+        memory_budget,
+        row_group_size,
+    })
+}
+
+/// Parses `--as-of` as an RFC 3339 timestamp, or a date meaning the end of that day in UTC.
+fn parse_as_of(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Ok(timestamp.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(|date| {
+            let end_of_day = NaiveTime::from_hms_milli_opt(23, 59, 59, 999).unwrap_or_default();
+            date.and_time(end_of_day).and_utc()
+        })
+        .map_err(|_| format!("expected an RFC 3339 timestamp or YYYY-MM-DD date, got `{value}`"))
+}
+
+/// Parses a `NAME=PATH` table registration for `query`.
+fn parse_named_path(value: &str) -> Result<(String, PathBuf), String> {
+    match value.split_once('=') {
+        Some((name, path)) if !name.is_empty() && !path.is_empty() => {
+            Ok((name.to_string(), PathBuf::from(path)))
+        }
+        _ => Err(format!("expected NAME=PATH, got `{value}`")),
+    }
+}
+
+/// Parses a `diff` side as a report date, a `START..END` date range or a file path.
+fn parse_diff_side(value: &str) -> Result<DiffSide, String> {
+    DiffSide::parse(value).map_err(|e| e.to_string())
+}
+
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Process a single file
@@ -23,7 +116,7 @@ enum Commands {
         report_type: ReportType,
         /// The path to the file to process
         filename: String,
-        /// Optional: Output directory for CSV files
+        /// Optional: Output directory or URI for CSV files
         #[arg(long, default_value = "data/csv")]
         csv_output_dir: String,
     },
@@ -34,7 +127,7 @@ enum Commands {
         report_type: ReportType,
         /// The path to the folder to process
         folder_path: String,
-        /// Optional: Output directory for CSV files
+        /// Optional: Output directory or URI for CSV files
         #[arg(long, default_value = "data/csv")]
         csv_output_dir: String,
     },
@@ -49,12 +142,33 @@ enum Commands {
         /// The end date (YYYY-MM-DD)
         #[arg(long)]
         end_date: NaiveDate,
-        /// Optional: Output directory for TXT files
+        /// Optional: Output directory or URI for TXT files
         #[arg(long, default_value = "data/txt")]
         txt_output_dir: String,
-        /// Optional: Output directory for CSV files
+        /// Optional: Output directory or URI for CSV files
         #[arg(long, default_value = "data/csv")]
         csv_output_dir: String,
+        /// Where to write parsed records (csv, delta, or both as csv,delta)
+        #[arg(long, value_enum, value_delimiter = ',', default_value = "csv")]
+        sink: Vec<Sink>,
+        /// Path or URI of the Delta table (required when sink includes delta)
+        #[arg(long)]
+        table_path: Option<String>,
+        /// Partition columns derived from the report date, used when the table is created
+        #[arg(long, value_enum, default_value = "none")]
+        partition_by: Partitioning,
+        /// Append rows, or merge them on the report's natural key so reloads are idempotent
+        #[arg(long, value_enum, default_value = "append")]
+        load_mode: LoadMode,
+        /// Store the source file, line, parser version and ingestion time with every row
+        #[arg(long)]
+        lineage: bool,
+        /// MiB of parquet buffered in memory before files are written; also the largest file size
+        #[arg(long, default_value_t = 1024)]
+        memory_budget_mb: usize,
+        /// Maximum rows per parquet row group
+        #[arg(long, default_value_t = 131_072)]
+        row_group_size: usize,
     },
     /// Process all files in a zip folder
     Zip {
@@ -63,10 +177,10 @@ enum Commands {
         report_type: ReportType,
         /// The path to the folder to process
         folder_path: String,
-        /// Optional: Output directory for TXT files
+        /// Optional: Output directory or URI for TXT files
         #[arg(long, default_value = "data/txt")]
         txt_output_dir: String,
-        /// Optional: Output directory for CSV files
+        /// Optional: Output directory or URI for CSV files
         #[arg(long, default_value = "data/csv")]
         csv_output_dir: String,
     },
@@ -81,7 +195,7 @@ enum Commands {
         /// Path to a folder containing CSV files (optional if csv_path is used)
         #[arg(long)]
         csv_folder: Option<String>,
-        /// Path to the Delta table
+        /// Path or URI (e.g. s3://bucket/st1) of the Delta table
         #[arg(long)]
         table_path: String,
         /// Optional: Path to the log file. Defaults to delta_load_log.json inside the table_path.
@@ -90,6 +204,289 @@ enum Commands {
         /// Recreate the table if it already exists
         #[arg(long)]
         recreate_table: bool,
+        /// Partition columns derived from the report date, used when the table is created
+        #[arg(long, value_enum, default_value = "none")]
+        partition_by: Partitioning,
+        /// Append rows, or merge them on the report's natural key so reloads are idempotent
+        #[arg(long, value_enum, default_value = "append")]
+        load_mode: LoadMode,
+        /// Store the source file, line, parser version and ingestion time with every row
+        #[arg(long)]
+        lineage: bool,
+        /// MiB of parquet buffered in memory before files are written; also the largest file size
+        #[arg(long, default_value_t = 1024)]
+        memory_budget_mb: usize,
+        /// Maximum rows per parquet row group
+        #[arg(long, default_value_t = 131_072)]
+        row_group_size: usize,
+        /// Update the licence_history table at this path after an ST1 load
+        #[arg(long)]
+        licence_history_path: Option<String>,
+        /// Also load files again whose rows a RESTORE made by another tool removed
+        #[arg(long)]
+        external_restores: bool,
+        /// Skip optimize and vacuum after loading, e.g. for frequent small loads
+        #[arg(long)]
+        skip_maintenance: bool,
+        #[command(flatten)]
+        maintenance: MaintenanceArgs,
+    },
+    /// Replace the rows of a report date range with freshly parsed CSVs in one commit
+    Reload {
+        /// The type of report to reload (st1 or st49)
+        #[arg(long, value_enum)]
+        report_type: ReportType,
+        /// The first report date to replace (YYYY-MM-DD)
+        #[arg(long)]
+        start_date: NaiveDate,
+        /// The last report date to replace (YYYY-MM-DD)
+        #[arg(long)]
+        end_date: NaiveDate,
+        /// Path to a single CSV file (optional if csv_folder is used)
+        #[arg(long)]
+        csv_path: Option<String>,
+        /// Path to a folder containing CSV files; only files dated in the range are used
+        #[arg(long)]
+        csv_folder: Option<String>,
+        /// Path or URI (e.g. s3://bucket/st1) of the Delta table
+        #[arg(long)]
+        table_path: String,
+        /// Optional: Path to the log file. Defaults to delta_load_log.json inside the table_path.
+        #[arg(long)]
+        log_path: Option<String>,
+        /// MiB of parquet buffered in memory before files are written; also the largest file size
+        #[arg(long, default_value_t = 1024)]
+        memory_budget_mb: usize,
+        /// Maximum rows per parquet row group
+        #[arg(long, default_value_t = 131_072)]
+        row_group_size: usize,
+        /// Update the licence_history table at this path after an ST1 reload
+        #[arg(long)]
+        licence_history_path: Option<String>,
+        /// Skip optimize and vacuum after reloading
+        #[arg(long)]
+        skip_maintenance: bool,
+        #[command(flatten)]
+        maintenance: MaintenanceArgs,
+    },
+    /// Roll a Delta table back to an earlier version or time
+    Restore {
+        /// Path or URI (e.g. s3://bucket/st1) of the Delta table
+        #[arg(long)]
+        table_path: String,
+        /// The version to restore
+        #[arg(long, conflicts_with = "to_timestamp", required_unless_present = "to_timestamp")]
+        to_version: Option<i64>,
+        /// Restore the table as it was at this time (RFC 3339, or YYYY-MM-DD for the end of that day UTC)
+        #[arg(long, value_parser = parse_as_of)]
+        to_timestamp: Option<DateTime<Utc>>,
+        /// Optional: Path to the log file. Defaults to delta_load_log.json inside the table_path.
+        #[arg(long)]
+        log_path: Option<String>,
+        /// Re-derive the licence_history table at this path from the restored ST1 table
+        #[arg(long)]
+        licence_history_path: Option<String>,
+    },
+    /// Compare a folder of CSVs with a Delta table; exits non-zero if they differ
+    Reconcile {
+        /// The type of report to compare (st1 or st49)
+        #[arg(long, value_enum)]
+        report_type: ReportType,
+        /// Path to the folder containing the CSV files
+        #[arg(long, default_value = "data/csv")]
+        csv_folder: String,
+        /// Path or URI (e.g. s3://bucket/st1) of the Delta table
+        #[arg(long)]
+        table_path: String,
+    },
+    /// Link ST49 spuds to their ST1 licences in the derived licence_spud table
+    LicenceSpud {
+        /// Path or URI (e.g. s3://bucket/st1) of the ST1 Delta table
+        #[arg(long, default_value = "data/deltalake/st1")]
+        st1_table_path: String,
+        /// Path or URI (e.g. s3://bucket/st49) of the ST49 Delta table
+        #[arg(long, default_value = "data/deltalake/st49")]
+        st49_table_path: String,
+        /// Path or URI of the licence_spud table, created if it does not exist
+        #[arg(long, default_value = "data/deltalake/licence_spud")]
+        table_path: String,
+    },
+    /// Update the derived rig_timeline table from ST49 and print the timeline of each rig
+    RigTimeline {
+        /// Path or URI (e.g. s3://bucket/st49) of the ST49 Delta table
+        #[arg(long, default_value = "data/deltalake/st49")]
+        st49_table_path: String,
+        /// Path or URI of the rig_timeline table, created if it does not exist
+        #[arg(long, default_value = "data/deltalake/rig_timeline")]
+        table_path: String,
+        /// Only print rigs of this contractor, by BA ID or name
+        #[arg(long)]
+        contractor: Option<String>,
+        /// Only print rigs with this number
+        #[arg(long)]
+        rig: Option<i32>,
+        /// How to print the timeline
+        #[arg(long, value_enum, default_value = "table")]
+        format: QueryFormat,
+    },
+    /// Build the company registry from ST49 BA IDs and resolve ST1 licensees to BA IDs
+    Companies {
+        /// Path or URI (e.g. s3://bucket/st1) of the ST1 Delta table
+        #[arg(long, default_value = "data/deltalake/st1")]
+        st1_table_path: String,
+        /// Path or URI (e.g. s3://bucket/st49) of the ST49 Delta table
+        #[arg(long, default_value = "data/deltalake/st49")]
+        st49_table_path: String,
+        /// CSV file with a name,ba_id header mapping more names to BA IDs
+        #[arg(long)]
+        aliases: Option<PathBuf>,
+        /// Path or URI of the company_registry table, created if it does not exist
+        #[arg(long, default_value = "data/deltalake/company_registry")]
+        registry_path: String,
+        /// Path or URI of the st1_licensees table, created if it does not exist
+        #[arg(long, default_value = "data/deltalake/st1_licensees")]
+        licensees_path: String,
+    },
+    /// Print the records added, removed and changed between two reports or report dates
+    Diff {
+        /// The type of report to compare (st1 or st49)
+        #[arg(long, value_enum)]
+        report_type: ReportType,
+        /// Older side: a WELLS or SPUD TXT file, a report date (YYYY-MM-DD) or a date range (START..END)
+        #[arg(value_parser = parse_diff_side)]
+        old: DiffSide,
+        /// Newer side, in the same forms
+        #[arg(value_parser = parse_diff_side)]
+        new: DiffSide,
+        /// ST1 Delta table report dates are read from
+        #[arg(long, default_value = "data/deltalake/st1")]
+        st1_table_path: String,
+        /// ST49 Delta table report dates are read from
+        #[arg(long, default_value = "data/deltalake/st49")]
+        st49_table_path: String,
+    },
+    /// Optimize and vacuum a Delta table without loading anything
+    Maintain {
+        /// Path or URI (e.g. s3://bucket/st1) of the Delta table
+        #[arg(long)]
+        table_path: String,
+        /// Do not optimize the table
+        #[arg(long)]
+        skip_optimize: bool,
+        /// Do not vacuum the table
+        #[arg(long)]
+        skip_vacuum: bool,
+        #[command(flatten)]
+        maintenance: MaintenanceArgs,
+    },
+    /// Export a Delta table, optionally as of a past version or time, to CSV, Parquet or JSON
+    Export {
+        /// Path or URI (e.g. s3://bucket/st1) of the Delta table
+        #[arg(long)]
+        table_path: String,
+        /// The file or URI to write
+        #[arg(long)]
+        output: PathBuf,
+        /// Format of the output file
+        #[arg(long, value_enum, default_value = "csv")]
+        format: ExportFormat,
+        /// Export this table version instead of the latest
+        #[arg(long, conflicts_with = "as_of")]
+        as_of_version: Option<i64>,
+        /// Export the table as it was at this time (RFC 3339, or YYYY-MM-DD for the end of that day UTC)
+        #[arg(long, value_parser = parse_as_of)]
+        as_of: Option<DateTime<Utc>>,
+        /// Optional: Only rows with a report date on or after this date (YYYY-MM-DD)
+        #[arg(long)]
+        start_date: Option<NaiveDate>,
+        /// Optional: Only rows with a report date on or before this date (YYYY-MM-DD)
+        #[arg(long)]
+        end_date: Option<NaiveDate>,
+        /// Optional: Columns to export, in order (e.g. date,licence_number,licensee)
+        #[arg(long, value_delimiter = ',')]
+        columns: Vec<String>,
+    },
+    /// Run SQL over the Delta tables and parser CSVs, plus any other tables given
+    Query {
+        /// SQL to run; several statements may be separated by `;`. Raw TXT
+        /// reports can be read with st1_raw('<folder>') and st49_raw('<folder>')
+        #[arg(required_unless_present = "file", conflicts_with = "file")]
+        sql: Option<String>,
+        /// Read the SQL from this file instead (e.g. queries/read_delta_st1.sql)
+        #[arg(long)]
+        file: Option<PathBuf>,
+        /// How to print the results
+        #[arg(long, value_enum, default_value = "table")]
+        format: QueryFormat,
+        /// ST1 Delta table registered as `st1`, if it exists
+        #[arg(long, default_value = "data/deltalake/st1")]
+        st1_table_path: String,
+        /// ST49 Delta table registered as `st49`, if it exists
+        #[arg(long, default_value = "data/deltalake/st49")]
+        st49_table_path: String,
+        /// Folder of parser CSVs registered as `st1_csv` and `st49_csv`, if it exists
+        #[arg(long, default_value = "data/csv")]
+        csv_folder: String,
+        /// Register another Delta table as NAME=PATH
+        #[arg(long, value_parser = parse_named_path)]
+        delta: Vec<(String, PathBuf)>,
+        /// Register a pipe-delimited CSV file or folder as NAME=PATH
+        #[arg(long, value_parser = parse_named_path)]
+        csv: Vec<(String, PathBuf)>,
+        /// Register a Parquet file or folder as NAME=PATH
+        #[arg(long, value_parser = parse_named_path)]
+        parquet: Vec<(String, PathBuf)>,
+    },
+    /// Print a standard summary of the licences or spuds
+    Stats {
+        /// The summary to compute
+        #[arg(value_enum)]
+        statistic: Statistic,
+        /// How to print the summary
+        #[arg(long, value_enum, default_value = "table")]
+        format: QueryFormat,
+        /// ST1 Delta table summarised by the licences statistics
+        #[arg(long, default_value = "data/deltalake/st1")]
+        st1_table_path: String,
+        /// ST49 Delta table summarised by the spuds statistics
+        #[arg(long, default_value = "data/deltalake/st49")]
+        st49_table_path: String,
+        /// Optional: Summarise the parser CSVs in this folder instead of the Delta table
+        #[arg(long)]
+        csv_folder: Option<PathBuf>,
+        /// Optional: Only records with a report date on or after this date (YYYY-MM-DD)
+        #[arg(long)]
+        start_date: Option<NaiveDate>,
+        /// Optional: Only records with a report date on or before this date (YYYY-MM-DD)
+        #[arg(long)]
+        end_date: Option<NaiveDate>,
+        /// Optional: Only print the first rows, e.g. the 10 largest licensees
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+    /// Serve the Delta tables as a JSON REST API, described at /openapi.json
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        address: SocketAddr,
+        /// ST1 Delta table served at /licences
+        #[arg(long, default_value = "data/deltalake/st1")]
+        st1_table_path: String,
+        /// ST49 Delta table served at /spuds
+        #[arg(long, default_value = "data/deltalake/st49")]
+        st49_table_path: String,
+        /// rig_timeline table served at /rigs, updated by `rig-timeline`
+        #[arg(long, default_value = "data/deltalake/rig_timeline")]
+        rig_timeline_path: String,
+    },
+    /// Rewrite an all-string Delta table into the typed schema as a new version
+    MigrateDelta {
+        /// The type of report stored in the table (st1 or st49)
+        #[arg(long, value_enum)]
+        report_type: ReportType,
+        /// Path or URI (e.g. s3://bucket/st1) of the Delta table
+        #[arg(long)]
+        table_path: String,
     },
 }
 
@@ -132,16 +529,56 @@ async fn main() -> Result<(), AppError> {
             end_date,
             txt_output_dir,
             csv_output_dir,
+            sink,
+            table_path,
+            partition_by,
+            load_mode,
+            lineage,
+            memory_budget_mb,
+            row_group_size,
         } => {
             info!("Downloading and processing from {start_date} to {end_date}");
-            process_date_range(
-                *report_type,
-                *start_date,
-                *end_date,
-                txt_output_dir,
-                csv_output_dir,
-            )
-            .await?;
+            if sink.contains(&Sink::Delta) {
+                let table_path = table_path.as_deref().ok_or_else(|| {
+                    AppError::Cli("--table-path is required when writing to delta".to_string())
+                })?;
+                let mut table = create_or_open_delta_table(
+                    Path::new(table_path),
+                    (*report_type).into(),
+                    *partition_by,
+                    *lineage,
+                )
+                .await?;
+
+                let streaming = streaming_options(*memory_budget_mb, *row_group_size)?;
+                let writer =
+                    ReportWriter::for_table(&table, (*report_type).into(), *load_mode, streaming)?;
+
+                let csv_output_dir = sink.contains(&Sink::Csv).then_some(csv_output_dir.as_str());
+                let summary = process_date_range_to_delta(
+                    *report_type,
+                    *start_date,
+                    *end_date,
+                    txt_output_dir,
+                    csv_output_dir,
+                    &mut table,
+                    writer,
+                )
+                .await?;
+                info!(
+                    "Wrote {} rows ({} inserted, {} updated) directly into delta table at {table_path}",
+                    summary.rows, summary.inserted, summary.updated
+                );
+            } else {
+                process_date_range(
+                    *report_type,
+                    *start_date,
+                    *end_date,
+                    txt_output_dir,
+                    csv_output_dir,
+                )
+                .await?;
+            }
         }
         Commands::Zip {
             report_type,
@@ -159,115 +596,378 @@ async fn main() -> Result<(), AppError> {
             table_path,
             log_path,
             recreate_table,
+            partition_by,
+            load_mode,
+            lineage,
+            memory_budget_mb,
+            row_group_size,
+            licence_history_path,
+            external_restores,
+            skip_maintenance,
+            maintenance,
         } => {
-            use crate::delta::{
-                create_or_open_delta_table, load_csvs_to_delta, log_loaded_csv, read_load_log,
-                DeltaReportType,
-            };
-            use deltalake::DeltaOps;
-            use std::fs;
-            use std::path::Path;
-
-            use std::path::PathBuf;
-
-            let log_path = if let Some(lp) = log_path {
-                PathBuf::from(lp)
-            } else {
-                PathBuf::from(table_path).join("delta_load_log.json")
-            };
+            let mut options = LoadDeltaOptions::new((*report_type).into(), table_path);
+            options.csv_path = csv_path.as_ref().map(PathBuf::from);
+            options.csv_folder = csv_folder.as_ref().map(PathBuf::from);
+            options.log_path = log_path.as_ref().map(PathBuf::from);
+            options.recreate_table = *recreate_table;
+            options.partitioning = *partition_by;
+            options.load_mode = *load_mode;
+            options.lineage = *lineage;
+            options.streaming = streaming_options(*memory_budget_mb, *row_group_size)?;
+            options.licence_history = licence_history_path.as_ref().map(PathBuf::from);
+            options.external_restores = *external_restores;
+            options.maintenance = maintenance.options(!skip_maintenance, !skip_maintenance);
 
-            if *recreate_table {
-                let table_path_obj = Path::new(table_path);
-                if table_path_obj.exists() {
-                    info!("Recreating delta table at {table_path}");
-                    std::fs::remove_dir_all(table_path_obj)?;
-                }
-                if log_path.exists() {
-                    info!("Removing log file at {log_path:?}");
-                    std::fs::remove_file(&log_path)?;
-                }
+            let report = load_delta(&options).await?;
+            info!(
+                "Loaded {} rows from {} CSV files into {table_path} ({} skipped, {} failed)",
+                report.summary.rows,
+                report.loaded_files.len(),
+                report.skipped_files.len(),
+                report.failed_files.len()
+            );
+            if let Some(history) = report.licence_history {
+                info!(
+                    "Licence history: {} versions inserted, {} updated, {} deleted",
+                    history.inserted, history.updated, history.deleted
+                );
             }
+            print_vacuum_dry_run(&options.maintenance, &report.maintenance);
+        }
+        Commands::Reload {
+            report_type,
+            start_date,
+            end_date,
+            csv_path,
+            csv_folder,
+            table_path,
+            log_path,
+            memory_budget_mb,
+            row_group_size,
+            licence_history_path,
+            skip_maintenance,
+            maintenance,
+        } => {
+            let mut options =
+                ReloadOptions::new((*report_type).into(), table_path, *start_date, *end_date);
+            options.csv_path = csv_path.as_ref().map(PathBuf::from);
+            options.csv_folder = csv_folder.as_ref().map(PathBuf::from);
+            options.log_path = log_path.as_ref().map(PathBuf::from);
+            options.streaming = streaming_options(*memory_budget_mb, *row_group_size)?;
+            options.licence_history = licence_history_path.as_ref().map(PathBuf::from);
+            options.maintenance = maintenance.options(!skip_maintenance, !skip_maintenance);
 
-            let delta_type = match report_type {
-                ReportType::St1 => DeltaReportType::St1,
-                ReportType::St49 => DeltaReportType::St49,
+            let report = reload_delta(&options).await?;
+            info!(
+                "Replaced {} rows from {start_date} to {end_date} with {} rows from {} CSV files in {table_path}",
+                report.summary.deleted,
+                report.summary.rows,
+                report.loaded_files.len()
+            );
+            if let Some(history) = report.licence_history {
+                info!(
+                    "Licence history: {} versions inserted, {} updated, {} deleted",
+                    history.inserted, history.updated, history.deleted
+                );
+            }
+            print_vacuum_dry_run(&options.maintenance, &report.maintenance);
+        }
+        Commands::Restore {
+            table_path,
+            to_version,
+            to_timestamp,
+            log_path,
+            licence_history_path,
+        } => {
+            let target = match (to_version, to_timestamp) {
+                (Some(version), _) => TableVersion::Version(*version),
+                (None, Some(timestamp)) => TableVersion::AsOf(*timestamp),
+                (None, None) => TableVersion::Latest,
             };
+            let mut options = RestoreOptions::new(table_path, target);
+            options.log_path = log_path.as_ref().map(PathBuf::from);
+            options.licence_history = licence_history_path.as_ref().map(PathBuf::from);
 
-            let mut table = create_or_open_delta_table(Path::new(table_path), delta_type).await?;
-
-            let processed_files = read_load_log(&log_path)?;
-
-            let mut csv_files = Vec::new();
-            if let Some(folder) = csv_folder {
-                info!("Searching for CSV files in folder: {folder}");
-                let prefix = match report_type {
-                    ReportType::St1 => "WELLS",
-                    ReportType::St49 => "SPUD",
-                };
-
-                for entry in fs::read_dir(folder)? {
-                    let entry = entry?;
-                    let path = entry.path();
-                    if let Some(filename) = path.file_name().and_then(|n| n.to_str()) {
-                        if filename.contains(prefix) && filename.ends_with(".csv") {
-                            let canonical_path = path.canonicalize()?;
-                            if !processed_files
-                                .contains(&canonical_path.to_string_lossy().to_string())
-                            {
-                                info!("Found CSV file: {path:?}");
-                                csv_files.push(path);
-                            } else {
-                                info!("Skipping already processed file: {path:?}");
-                            }
-                        }
-                    }
-                }
+            let report = restore_delta(&options).await?;
+            info!(
+                "Restored {table_path} from version {} to version {} ({} files removed, {} restored)",
+                report.from_version,
+                report.restored_version,
+                report.removed_files,
+                report.restored_files
+            );
+            for file in &report.rolled_back_files {
+                println!("Rolled back {} ({})", file.file_name, file.sha256);
+            }
+            if let Some(history) = report.licence_history {
+                info!(
+                    "Licence history: {} versions inserted, {} updated, {} deleted",
+                    history.inserted, history.updated, history.deleted
+                );
             }
-            if let Some(file) = csv_path {
-                let path = Path::new(file).to_path_buf();
-                let canonical_path = path.canonicalize()?;
-                if !processed_files.contains(&canonical_path.to_string_lossy().to_string()) {
-                    csv_files.push(path);
-                } else {
-                    info!("Skipping already processed file: {path:?}");
+        }
+        Commands::Reconcile {
+            report_type,
+            csv_folder,
+            table_path,
+        } => {
+            let report =
+                reconcile_delta((*report_type).into(), Path::new(csv_folder), Path::new(table_path))
+                    .await?;
+            println!("date|missing|extra");
+            for (date, difference) in &report.differences {
+                let date = date.map(|date| date.to_string()).unwrap_or_default();
+                println!("{date}|{}|{}", difference.missing, difference.extra);
+            }
+            if !report.is_match() {
+                for (csv_file, reason) in &report.unreadable_files {
+                    eprintln!("Could not read {csv_file:?}, not compared: {reason}");
                 }
+                eprintln!(
+                    "{} rows of {} CSV files are missing from {table_path}, {} rows in it are not in any CSV",
+                    report.missing(),
+                    report.csv_files.len(),
+                    report.extra()
+                );
+                std::process::exit(1);
             }
+            info!(
+                "{table_path} matches the {} CSV files in {csv_folder}",
+                report.csv_files.len()
+            );
+        }
+        Commands::LicenceSpud {
+            st1_table_path,
+            st49_table_path,
+            table_path,
+        } => {
+            let st1 = open_delta_table_at(Path::new(st1_table_path), TableVersion::Latest).await?;
+            let st49 = open_delta_table_at(Path::new(st49_table_path), TableVersion::Latest).await?;
+            let summary = update_licence_spud(&st1, &st49, Path::new(table_path)).await?;
+            println!(
+                "{} spuds linked, {} without an ST1 licence ({} rows inserted, {} updated, {} deleted)",
+                summary.spuds - summary.unmatched,
+                summary.unmatched,
+                summary.inserted,
+                summary.updated,
+                summary.deleted
+            );
+        }
+        Commands::RigTimeline {
+            st49_table_path,
+            table_path,
+            contractor,
+            rig,
+            format,
+        } => {
+            let st49 = open_delta_table_at(Path::new(st49_table_path), TableVersion::Latest).await?;
+            let summary = update_rig_timeline(&st49, Path::new(table_path)).await?;
+            info!(
+                "Rig timeline: {} activities inserted, {} updated, {} deleted",
+                summary.inserted, summary.updated, summary.deleted
+            );
+            let timeline = read_rig_timeline(Path::new(table_path), contractor.as_deref(), *rig).await?;
+            print!("{}", format_query_result(&timeline, *format)?);
+        }
+        Commands::Companies {
+            st1_table_path,
+            st49_table_path,
+            aliases,
+            registry_path,
+            licensees_path,
+        } => {
+            let aliases = match aliases {
+                Some(path) => read_company_aliases(path)?,
+                None => Vec::new(),
+            };
+            let st49 = open_delta_table_at(Path::new(st49_table_path), TableVersion::Latest).await?;
+            let registry =
+                update_company_registry(&st49, &aliases, Path::new(registry_path)).await?;
+            println!(
+                "{} companies under {} names in the registry ({} rows inserted, {} updated, {} deleted)",
+                registry.companies,
+                registry.names,
+                registry.inserted,
+                registry.updated,
+                registry.deleted
+            );
+            let st1 = open_delta_table_at(Path::new(st1_table_path), TableVersion::Latest).await?;
+            let registry_table =
+                open_delta_table_at(Path::new(registry_path), TableVersion::Latest).await?;
+            let licensees =
+                resolve_st1_licensees(&st1, &registry_table, Path::new(licensees_path)).await?;
+            println!(
+                "{} of {} ST1 licensees resolved to a BA ID ({} rows inserted, {} updated, {} deleted)",
+                licensees.resolved,
+                licensees.licensees,
+                licensees.inserted,
+                licensees.updated,
+                licensees.deleted
+            );
+        }
+        Commands::Diff {
+            report_type,
+            old,
+            new,
+            st1_table_path,
+            st49_table_path,
+        } => {
+            let report_type = DeltaReportType::from(*report_type);
+            let table_path = match report_type {
+                DeltaReportType::St1 => st1_table_path,
+                DeltaReportType::St49 => st49_table_path,
+            };
+            let diff = diff_reports(report_type, old, new, Path::new(table_path)).await?;
+            println!("{old} -> {new}");
+            print!("{diff}");
+        }
+        Commands::Maintain {
+            table_path,
+            skip_optimize,
+            skip_vacuum,
+            maintenance,
+        } => {
+            let options = maintenance.options(!skip_optimize, !skip_vacuum);
+            let mut table =
+                open_delta_table_at(Path::new(table_path), TableVersion::Latest).await?;
+            let report = maintain_delta_table(&mut table, &options, &BTreeSet::new()).await?;
+            info!(
+                "Maintained delta table at {table_path}: {} files optimized into {}, {} files vacuumed",
+                report.files_removed,
+                report.files_added,
+                if options.dry_run { 0 } else { report.vacuumed_files.len() }
+            );
+            print_vacuum_dry_run(&options, &report);
+        }
+        Commands::Export {
+            table_path,
+            output,
+            format,
+            as_of_version,
+            as_of,
+            start_date,
+            end_date,
+            columns,
+        } => {
+            let version = match (as_of_version, as_of) {
+                (Some(version), _) => TableVersion::Version(*version),
+                (None, Some(timestamp)) => TableVersion::AsOf(*timestamp),
+                (None, None) => TableVersion::Latest,
+            };
+            let options = ExportOptions {
+                table_path: PathBuf::from(table_path),
+                output: output.clone(),
+                format: *format,
+                version,
+                start_date: *start_date,
+                end_date: *end_date,
+                columns: columns.clone(),
+            };
+            let summary = export_delta_table(&options).await?;
+            info!(
+                "Exported {} rows from version {} of {table_path} to {output:?}",
+                summary.rows, summary.version
+            );
+        }
+        Commands::Query {
+            sql,
+            file,
+            format,
+            st1_table_path,
+            st49_table_path,
+            csv_folder,
+            delta,
+            csv,
+            parquet,
+        } => {
+            let sql = match (sql, file) {
+                (Some(sql), _) => sql.clone(),
+                (None, Some(file)) => std::fs::read_to_string(file)?,
+                (None, None) => {
+                    return Err(AppError::Cli("Give the SQL to run or --file".to_string()))
+                }
+            };
+            let mut tables = default_query_tables(
+                Path::new(st1_table_path),
+                Path::new(st49_table_path),
+                Path::new(csv_folder),
+            )
+            .await?;
+            let named = |paths: &[(String, PathBuf)], source: fn(PathBuf) -> QuerySource| {
+                paths
+                    .iter()
+                    .map(move |(name, path)| (name.clone(), source(path.clone())))
+                    .collect::<Vec<_>>()
+            };
+            tables.extend(named(delta, QuerySource::Delta));
+            tables.extend(named(csv, QuerySource::Csv));
+            tables.extend(named(parquet, QuerySource::Parquet));
 
-            if !csv_files.is_empty() {
-                info!("Loading {} CSV files as a single batch operation with 1GB target file size", csv_files.len());
-                
-                // Convert Vec<PathBuf> to Vec<&Path>
-                let csv_paths: Vec<&Path> = csv_files.iter().map(|p| p.as_path()).collect();
-                
-                match load_csvs_to_delta(&mut table, &csv_paths).await {
-                    Ok(loaded_rows) => {
-                        info!("Successfully loaded {loaded_rows} total rows from {} CSV files", csv_files.len());
-                        
-                        // Log all processed files
-                        for csv_path in &csv_files {
-                            log_loaded_csv(&log_path, csv_path)?;
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("Failed to load CSV files as batch: {e}");
-                        // Move individual files to conversion_errors if batch fails
-                        for csv in &csv_files {
-                            if let Err(e) = aer_st1::move_to_conversion_errors(csv, &e.to_string()).await {
-                                eprintln!("Failed to move file to conversion_errors: {e}");
-                            }
-                        }
-                    }
+            let results = run_query(&tables, &sql).await?;
+            for (i, result) in results.iter().enumerate() {
+                if i > 0 {
+                    println!();
                 }
-            } else {
-                info!("No new CSV files to process");
+                print!("{}", format_query_result(result, *format)?);
+                info!("{} rows", result.num_rows());
+            }
+        }
+        Commands::Stats {
+            statistic,
+            format,
+            st1_table_path,
+            st49_table_path,
+            csv_folder,
+            start_date,
+            end_date,
+            limit,
+        } => {
+            let report_type = statistic.report_type();
+            let source = match csv_folder {
+                Some(folder) => QuerySource::ReportCsv(report_type, folder.clone()),
+                None => QuerySource::Delta(PathBuf::from(match report_type {
+                    DeltaReportType::St1 => st1_table_path,
+                    DeltaReportType::St49 => st49_table_path,
+                })),
+            };
+            let options = StatsOptions {
+                statistic: *statistic,
+                source,
+                start_date: *start_date,
+                end_date: *end_date,
+                limit: *limit,
+            };
+            let result = compute_stats(&options).await?;
+            print!("{}", format_query_result(&result, *format)?);
+        }
+        Commands::Serve {
+            address,
+            st1_table_path,
+            st49_table_path,
+            rig_timeline_path,
+        } => {
+            serve(ServeOptions {
+                address: *address,
+                st1_table_path: PathBuf::from(st1_table_path),
+                st49_table_path: PathBuf::from(st49_table_path),
+                rig_timeline_path: PathBuf::from(rig_timeline_path),
+            })
+            .await?;
+        }
+        Commands::MigrateDelta {
+            report_type,
+            table_path,
+        } => {
+            info!("Migrating delta table at {table_path} to the typed schema");
+            let summary = migrate_delta_table(Path::new(table_path), (*report_type).into()).await?;
+            match summary.to_version {
+                Some(version) => info!(
+                    "Migrated {} rows from version {:?} to version {version}",
+                    summary.rows, summary.from_version
+                ),
+                None => info!("Nothing to migrate"),
             }
-
-            info!("Optimizing delta table at {table_path}");
-            let ops = DeltaOps::from(table.clone());
-            ops.optimize().await?;
-            info!("Vacuuming delta table at {table_path}");
-            let ops = DeltaOps::from(table.clone());
-            ops.vacuum().with_dry_run(false).await?;
         }
     }
 
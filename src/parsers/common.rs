@@ -7,6 +7,7 @@
 //!
 //! - **File Operations**: Efficient file reading with buffered I/O
 //! - **Date Parsing**: Specialized date extraction for ST1/ST49 formats
+//! - **Field Parsing**: Typed values (dates, timestamps, metres) for Arrow output
 //! - **CSV Writing**: Streaming CSV output with proper formatting
 //! - **Text Processing**: Common text manipulation utilities
 //!
@@ -30,11 +31,12 @@
 //! ```
 
 use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use chrono::NaiveDate;
 use serde::Serialize;
 
+use crate::parsers::lineage::{CsvLineage, ParsedReport};
 use crate::AppError;
 
 /// Trim and remove empty lines from content
@@ -87,8 +89,7 @@ pub fn write_csv_records<T: Serialize>(
         return Ok(());
     }
 
-    let output_filename = format!("{}_{}.csv", report_date.format("%Y%m%d"), filename_prefix);
-    let full_path = output_path.join(output_filename);
+    let full_path = csv_path(output_path, filename_prefix, report_date);
 
     let mut wtr = csv::WriterBuilder::new()
         .delimiter(b'|')
@@ -102,6 +103,46 @@ pub fn write_csv_records<T: Serialize>(
     Ok(())
 }
 
+/// Path of the CSV written by [`write_csv_records`]
+fn csv_path(output_path: &Path, filename_prefix: &str, report_date: NaiveDate) -> PathBuf {
+    output_path.join(format!(
+        "{}_{}.csv",
+        report_date.format("%Y%m%d"),
+        filename_prefix
+    ))
+}
+
+/// Write the records of a parsed report to CSV, with a lineage sidecar
+///
+/// The records are written as by [`write_csv_records`], and the report
+/// file, its hash and the line of each record are written next to the CSV
+/// (see [`CsvLineage`]) so rows loaded from the CSV keep their lineage.
+///
+/// # Arguments
+/// * `report` - Parsed report to write
+/// * `output_path` - Directory path for output
+/// * `filename_prefix` - Prefix for filename (e.g., "WELLS", "SPUD")
+///
+/// # Returns
+/// Result indicating success or error
+pub fn write_report_csv<T: Serialize>(
+    report: &ParsedReport<T>,
+    output_path: &Path,
+    filename_prefix: &str,
+) -> Result<(), AppError> {
+    if report.records.is_empty() {
+        return Ok(());
+    }
+
+    write_csv_records(&report.records, output_path, filename_prefix, report.date)?;
+    CsvLineage::write(
+        &csv_path(output_path, filename_prefix, report.date),
+        &report.source,
+        &report.source_lines,
+    )?;
+    Ok(())
+}
+
 /// Common date extraction utilities
 pub mod date_utils {
     use super::*;
@@ -163,6 +204,135 @@ pub mod date_utils {
     }
 }
 
+/// Typed field parsing for report values
+///
+/// Report fields are kept as text in the parsed records; these helpers convert
+/// them to typed values for Arrow and Delta output. Blank or unrecognised
+/// values map to `None` so they are stored as nulls.
+pub mod field_parsing {
+    use chrono::NaiveDateTime;
+
+    use super::*;
+
+    /// Parse a report date written as `YYYY-MM-DD`
+    ///
+    /// # Arguments
+    /// * `value` - Date string from a parsed record
+    ///
+    /// # Returns
+    /// Parsed date, or `None` for blank/invalid values
+    pub fn parse_report_date(value: &str) -> Option<NaiveDate> {
+        NaiveDate::parse_from_str(value.trim(), "%Y-%m-%d").ok()
+    }
+
+    /// Parse an ST-49 activity timestamp such as `01 Jan 2025 12:30:00 PM`
+    ///
+    /// # Arguments
+    /// * `value` - Activity date string from a spud record
+    ///
+    /// # Returns
+    /// Parsed local date and time, or `None` for blank/invalid values
+    pub fn parse_activity_datetime(value: &str) -> Option<NaiveDateTime> {
+        let value = value.trim();
+        NaiveDateTime::parse_from_str(value, "%d %b %Y %I:%M:%S %p")
+            .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S"))
+            .ok()
+    }
+
+    /// Parse a measurement in metres such as `931.70M`
+    ///
+    /// # Arguments
+    /// * `value` - Elevation or depth string, with or without the `M` suffix
+    ///
+    /// # Returns
+    /// Value in metres, or `None` for blank/invalid values
+    ///
+    /// # Example
+    /// ```rust,ignore
+    /// assert_eq!(field_parsing::parse_metres("931.70M"), Some(931.7));
+    /// ```
+    pub fn parse_metres(value: &str) -> Option<f64> {
+        value
+            .trim()
+            .trim_end_matches(['M', 'm'])
+            .trim()
+            .parse()
+            .ok()
+    }
+
+    /// Parse an AER classification such as `DEV (NC)`
+    ///
+    /// # Arguments
+    /// * `value` - Classification string from a licence
+    ///
+    /// # Returns
+    /// The classification code (`DEV`) and whether the well is confidential
+    /// (`(C)`) rather than non-confidential (`(NC)`), or `None` for values
+    /// without a confidentiality suffix
+    ///
+    /// # Example
+    /// ```rust,ignore
+    /// assert_eq!(field_parsing::parse_classification("XPL (C)"), Some(("XPL", true)));
+    /// ```
+    pub fn parse_classification(value: &str) -> Option<(&str, bool)> {
+        let (code, suffix) = value.trim().split_once('(')?;
+        let confidential = match suffix.trim_end_matches(')').trim() {
+            "C" => true,
+            "NC" => false,
+            _ => return None,
+        };
+        Some((code.trim(), confidential))
+    }
+
+    /// Normalise a company name so spellings of the same name compare equal
+    ///
+    /// Upper-cases the name, spells `&` as `AND`, drops punctuation, collapses
+    /// whitespace and shortens the legal suffixes `CORPORATION`,
+    /// `INCORPORATED`, `LIMITED` and `COMPANY` to `CORP`, `INC`, `LTD` and `CO`.
+    ///
+    /// # Arguments
+    /// * `value` - Licensee or contractor name as listed in a report
+    ///
+    /// # Returns
+    /// The normalised name, or `None` for blank values
+    ///
+    /// # Example
+    /// ```rust,ignore
+    /// let key = field_parsing::normalize_company_name("Tourmaline Oil Corporation");
+    /// assert_eq!(key.as_deref(), Some("TOURMALINE OIL CORP"));
+    /// ```
+    pub fn normalize_company_name(value: &str) -> Option<String> {
+        let spaced: String = value
+            .to_uppercase()
+            .replace('&', " AND ")
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+            .collect();
+        let words: Vec<&str> = spaced
+            .split_whitespace()
+            .map(|word| match word {
+                "CORPORATION" => "CORP",
+                "INCORPORATED" => "INC",
+                "LIMITED" => "LTD",
+                "COMPANY" => "CO",
+                word => word,
+            })
+            .collect();
+        (!words.is_empty()).then(|| words.join(" "))
+    }
+
+    /// Parse a whole number such as a rig number
+    ///
+    /// # Arguments
+    /// * `value` - Integer string
+    ///
+    /// # Returns
+    /// Parsed integer, or `None` for blank/invalid values
+    pub fn parse_integer(value: &str) -> Option<i32> {
+        value.trim().parse().ok()
+    }
+}
+
 /// Common file operations
 pub mod file_ops {
     use std::fs::File;
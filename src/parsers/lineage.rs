@@ -0,0 +1,296 @@
+//! Row lineage for parsed records
+//!
+//! Lineage ties every parsed record back to the exact AER file and line it
+//! came from, so a disputed record can be traced to its source. The parsers
+//! report the source file, its SHA-256 and the line each record starts on;
+//! the Delta loader adds the ingestion timestamp.
+//!
+//! ## Lineage Columns
+//!
+//! - `source_file`: file name of the source report
+//! - `source_sha256`: hex SHA-256 of the source file contents
+//! - `source_line`: 1-based line in the source file where the record starts
+//! - `parser_version`: version of this crate that parsed the record
+//! - `ingested_at`: UTC time the record was written to Delta
+//!
+//! ## CSV Lineage
+//!
+//! A CSV written from a report gets a `<csv>.lineage.json` sidecar
+//! ([`CsvLineage`]) holding the report, its hash, the line of every CSV row
+//! and the parser version, so rows loaded from the CSV can still point at
+//! the report rather than at the CSV.
+//!
+//! ## Usage Example
+//!
+//! ```rust,ignore
+//! use aer_st1::parsers::lineage::append_source_lineage;
+//! use aer_st1::parsers::record_batch::ArrowRecord;
+//!
+//! let report = st1::parse_file("WELLS0102", "TXT").await?;
+//! let batch = License::to_record_batch(&report.records)?;
+//! let batch = append_source_lineage(&batch, &report.source, &report.source_lines, Some(PARSER_VERSION))?;
+//! ```
+
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use chrono::NaiveDate;
+use deltalake::arrow::array::{ArrayRef, Int32Array, RecordBatch, StringArray};
+use deltalake::arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use deltalake::arrow::error::ArrowError;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Version of the parser recorded in the `parser_version` column
+pub const PARSER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Names of the lineage columns in table order
+pub const LINEAGE_COLUMNS: [&str; 5] = [
+    "source_file",
+    "source_sha256",
+    "source_line",
+    "parser_version",
+    "ingested_at",
+];
+
+/// A source file identified by name and content hash
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceFile {
+    /// File name, for auditing only; dedupe uses the hash
+    pub file_name: String,
+    /// Hex-encoded SHA-256 of the file contents
+    pub sha256: String,
+}
+
+impl SourceFile {
+    /// Identify a file on disk by name and SHA-256
+    ///
+    /// # Arguments
+    /// * `path` - Path of the file to hash
+    ///
+    /// # Returns
+    /// The file name and hash, or an I/O error
+    pub fn from_path(path: &Path) -> Result<Self, std::io::Error> {
+        Ok(Self {
+            file_name: file_name(path),
+            sha256: sha256_file(path)?,
+        })
+    }
+
+    /// Identify a file whose contents have already been read
+    ///
+    /// # Arguments
+    /// * `path` - Path the contents were read from
+    /// * `contents` - Bytes of the file
+    pub fn from_contents(path: &Path, contents: &[u8]) -> Self {
+        Self {
+            file_name: file_name(path),
+            sha256: hex::encode(Sha256::digest(contents)),
+        }
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Hex-encoded SHA-256 of a file's contents
+///
+/// # Arguments
+/// * `path` - Path of the file to hash
+///
+/// # Returns
+/// Lowercase hex digest or I/O error
+pub fn sha256_file(path: &Path) -> Result<String, std::io::Error> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Records parsed from one report file, with where each record came from
+#[derive(Debug)]
+pub struct ParsedReport<T> {
+    /// Report date from the file header
+    pub date: NaiveDate,
+    /// Parsed records in file order
+    pub records: Vec<T>,
+    /// 1-based line where each record starts, parallel to `records`
+    pub source_lines: Vec<Option<u32>>,
+    /// The file the records were parsed from
+    pub source: SourceFile,
+}
+
+/// Lineage of a CSV written from a parsed report, stored next to the CSV
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CsvLineage {
+    /// Hex SHA-256 of the CSV the lineage was written for
+    pub csv_sha256: String,
+    /// The report the CSV rows were parsed from
+    pub source: SourceFile,
+    /// 1-based line in the report of each CSV row, in CSV order
+    pub source_lines: Vec<Option<u32>>,
+    /// Version of the parser that wrote the CSV
+    pub parser_version: String,
+}
+
+impl CsvLineage {
+    /// Path of the sidecar file holding the lineage of a CSV
+    pub fn sidecar_path(csv_path: &Path) -> PathBuf {
+        let mut name = csv_path.as_os_str().to_owned();
+        name.push(".lineage.json");
+        PathBuf::from(name)
+    }
+
+    /// Write the lineage sidecar of a CSV written from `source`
+    ///
+    /// # Arguments
+    /// * `csv_path` - The CSV, already written
+    /// * `source` - Report the rows were parsed from
+    /// * `source_lines` - Line of each CSV row in the report
+    pub fn write(
+        csv_path: &Path,
+        source: &SourceFile,
+        source_lines: &[Option<u32>],
+    ) -> Result<(), std::io::Error> {
+        let lineage = Self {
+            csv_sha256: sha256_file(csv_path)?,
+            source: source.clone(),
+            source_lines: source_lines.to_vec(),
+            parser_version: PARSER_VERSION.to_string(),
+        };
+        std::fs::write(Self::sidecar_path(csv_path), serde_json::to_vec(&lineage)?)
+    }
+
+    /// Read the lineage sidecar of a CSV
+    ///
+    /// # Returns
+    /// The lineage, or `None` when the CSV has no sidecar, or the CSV was
+    /// changed after the sidecar was written
+    pub fn read(csv_path: &Path) -> Option<Self> {
+        let sidecar_path = Self::sidecar_path(csv_path);
+        let contents = std::fs::read(&sidecar_path).ok()?;
+        let lineage: Self = match serde_json::from_slice(&contents) {
+            Ok(lineage) => lineage,
+            Err(e) => {
+                warn!("Ignoring unreadable lineage file {sidecar_path:?}: {e}");
+                return None;
+            }
+        };
+        if sha256_file(csv_path).ok()? != lineage.csv_sha256 {
+            warn!("Ignoring lineage file {sidecar_path:?} written for other CSV contents");
+            return None;
+        }
+        Some(lineage)
+    }
+}
+
+/// Find the line numbers of parsed record lines in the original file
+///
+/// The parsers work on trimmed, non-empty lines, so line numbers are lost
+/// on the way. Record lines appear in file order, which lets each one be
+/// matched to the next original line with the same trimmed text.
+///
+/// # Arguments
+/// * `content` - Original file contents
+/// * `record_lines` - Trimmed first line of each record, in file order
+///
+/// # Returns
+/// 1-based line number of each record line, `None` if it was not found
+pub fn source_line_numbers<S: AsRef<str>>(content: &str, record_lines: &[S]) -> Vec<Option<u32>> {
+    let mut original = content.lines().enumerate();
+    record_lines
+        .iter()
+        .map(|record_line| {
+            let record_line = record_line.as_ref().trim();
+            original
+                .by_ref()
+                .find(|(_, line)| line.trim() == record_line)
+                .map(|(index, _)| index as u32 + 1)
+        })
+        .collect()
+}
+
+/// Arrow fields of the lineage columns
+pub fn lineage_fields() -> Vec<Field> {
+    vec![
+        Field::new("source_file", DataType::Utf8, true),
+        Field::new("source_sha256", DataType::Utf8, true),
+        Field::new("source_line", DataType::Int32, true),
+        Field::new("parser_version", DataType::Utf8, true),
+        Field::new(
+            "ingested_at",
+            DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+            true,
+        ),
+    ]
+}
+
+/// Append the source lineage columns to a batch of parsed records
+///
+/// Adds `source_file`, `source_sha256`, `source_line` and `parser_version`;
+/// `ingested_at` is added by the Delta loader when the rows are written.
+///
+/// # Arguments
+/// * `batch` - Records parsed from `source`
+/// * `source` - File the records came from
+/// * `source_lines` - Line of each row in the batch
+/// * `parser_version` - Version that parsed the file, if known
+///
+/// # Returns
+/// The batch with the four source lineage columns appended
+pub fn append_source_lineage(
+    batch: &RecordBatch,
+    source: &SourceFile,
+    source_lines: &[Option<u32>],
+    parser_version: Option<&str>,
+) -> Result<RecordBatch, ArrowError> {
+    let rows = batch.num_rows();
+    if source_lines.len() != rows {
+        return Err(ArrowError::InvalidArgumentError(format!(
+            "{} source lines given for {rows} rows",
+            source_lines.len()
+        )));
+    }
+
+    let mut fields: Vec<Field> = batch
+        .schema()
+        .fields()
+        .iter()
+        .map(|field| field.as_ref().clone())
+        .collect();
+    fields.extend(lineage_fields().into_iter().take(4));
+
+    let mut columns: Vec<ArrayRef> = batch.columns().to_vec();
+    columns.push(Arc::new(StringArray::from(vec![
+        source.file_name.as_str();
+        rows
+    ])));
+    columns.push(Arc::new(StringArray::from(vec![
+        source.sha256.as_str();
+        rows
+    ])));
+    columns.push(Arc::new(
+        source_lines
+            .iter()
+            .map(|line| line.map(|line| line as i32))
+            .collect::<Int32Array>(),
+    ));
+    columns.push(Arc::new(StringArray::from(vec![parser_version; rows])));
+
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), columns)
+}
@@ -0,0 +1,125 @@
+//! Dominion Land Survey locations in unique well identifiers
+//!
+//! AER reports locate wells on the Dominion Land Survey (DLS) grid. The same
+//! legal subdivision, section, township, range and meridian appear in
+//! several forms:
+//!
+//! - ST-1 unique identifiers: `100/07-36-026-18W4/00`
+//! - ST-49 well IDs: `00/05-15-050-22W5/0`
+//! - Surface locations: `04-30-026-17W4`
+//!
+//! [`DlsLocation::parse`] reads any of them, ignoring the location
+//! exception and event sequence around the location and anything after
+//! the first whitespace (ST-1 unique identifiers may be followed by surface
+//! coordinates).
+//!
+//! ## Usage Example
+//!
+//! ```rust,ignore
+//! use aer_st1::parsers::location::DlsLocation;
+//!
+//! let location = DlsLocation::parse("100/07-36-026-18W4/00").unwrap();
+//! assert_eq!((location.township, location.range, location.meridian), (26, 18, 4));
+//! let (latitude, longitude) = location.approximate_lat_lon();
+//! ```
+
+/// Miles per township side and per range side
+const TOWNSHIP_MILES: f64 = 6.0;
+/// Kilometres per mile
+const KM_PER_MILE: f64 = 1.609_344;
+/// Kilometres per degree of latitude
+const KM_PER_DEGREE: f64 = 111.2;
+/// Latitude of the south boundary of township 1, the 49th parallel
+const FIRST_TOWNSHIP_LATITUDE: f64 = 49.0;
+/// Longitude west of Greenwich of the First (Principal) Meridian through the
+/// Seventh Meridian; the Second and later meridians lie every 4 degrees
+const MERIDIAN_LONGITUDES: [f64; 7] = [97.457_2, 102.0, 106.0, 110.0, 114.0, 118.0, 122.0];
+
+/// A legal subdivision on the Dominion Land Survey grid, west of a meridian
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DlsLocation {
+    /// Legal subdivision within the section, 1 to 16
+    pub lsd: u8,
+    /// Section within the township, 1 to 36
+    pub section: u8,
+    /// Township, numbered north from the 49th parallel
+    pub township: u8,
+    /// Range, numbered west from the meridian
+    pub range: u8,
+    /// Meridian the range is counted from, 1 (Principal) to 7
+    pub meridian: u8,
+}
+
+impl DlsLocation {
+    /// Parse the DLS location in a unique well identifier or surface location
+    ///
+    /// # Arguments
+    /// * `value` - UWI such as `100/07-36-026-18W4/00` or a location such as `04-30-026-17W4`
+    ///
+    /// # Returns
+    /// The location, or `None` if the value holds no valid DLS location
+    pub fn parse(value: &str) -> Option<Self> {
+        let token = value.split_whitespace().next()?;
+        let location = token.split('/').find(|part| part.contains('-'))?;
+        let [lsd, section, township, range_meridian] =
+            location.split('-').collect::<Vec<_>>().try_into().ok()?;
+        let (range, meridian) = range_meridian.split_once(['W', 'w'])?;
+
+        let location = DlsLocation {
+            lsd: lsd.parse().ok()?,
+            section: section.parse().ok()?,
+            township: township.parse().ok()?,
+            range: range.parse().ok()?,
+            meridian: meridian.parse().ok()?,
+        };
+        let valid = (1..=16).contains(&location.lsd)
+            && (1..=36).contains(&location.section)
+            && (1..=126).contains(&location.township)
+            && (1..=34).contains(&location.range)
+            && (1..=7).contains(&location.meridian);
+        valid.then_some(location)
+    }
+
+    /// Approximate latitude and longitude of the centre of the legal subdivision
+    ///
+    /// Computed from the nominal grid: 6-mile townships and ranges and
+    /// quarter-mile legal subdivisions. Road allowances, correction lines and
+    /// survey irregularities are ignored, so the result may be off by a few
+    /// kilometres in the north of the province; it is meant for maps and
+    /// rough distances, not for locating a wellhead.
+    ///
+    /// # Returns
+    /// Latitude and longitude in degrees; longitude is negative (west)
+    pub fn approximate_lat_lon(&self) -> (f64, f64) {
+        // Sections and legal subdivisions are numbered back and forth from
+        // the south-east corner: east to west in the first row, west to east
+        // in the next
+        let (section_row, section_col) = serpentine(self.section, 6);
+        let (lsd_row, lsd_col) = serpentine(self.lsd, 4);
+        let north_miles = f64::from(self.township - 1) * TOWNSHIP_MILES
+            + f64::from(section_row)
+            + (f64::from(lsd_row) + 0.5) / 4.0;
+        let west_miles = f64::from(self.range - 1) * TOWNSHIP_MILES
+            + f64::from(section_col)
+            + (f64::from(lsd_col) + 0.5) / 4.0;
+
+        let latitude = FIRST_TOWNSHIP_LATITUDE + north_miles * KM_PER_MILE / KM_PER_DEGREE;
+        let km_per_degree_longitude = KM_PER_DEGREE * latitude.to_radians().cos();
+        let longitude = -(MERIDIAN_LONGITUDES[usize::from(self.meridian - 1)]
+            + west_miles * KM_PER_MILE / km_per_degree_longitude);
+        (latitude, longitude)
+    }
+}
+
+/// Row from the south and column from the east of a cell numbered back and
+/// forth from the south-east corner of a `width`-wide grid.
+fn serpentine(number: u8, width: u8) -> (u8, u8) {
+    let row = (number - 1) / width;
+    let index = (number - 1) % width;
+    let col = if row.is_multiple_of(2) {
+        index
+    } else {
+        width - 1 - index
+    };
+    (row, col)
+}
@@ -10,6 +10,9 @@
 //! handle common operations across different report types:
 //!
 //! - **Common utilities**: File operations, date parsing, CSV writing
+//! - **Record batches**: Direct Arrow conversion of parsed records
+//! - **Lineage**: Source file, hash and line number of every parsed record
+//! - **Locations**: Dominion Land Survey locations in unique well identifiers
 //! - **Error handling**: Context-rich error messages with recovery strategies
 //! - **Memory optimization**: Buffered reading and streaming operations
 //!
@@ -27,6 +30,9 @@
 
 pub mod common;
 pub mod error;
+pub mod lineage;
+pub mod location;
+pub mod record_batch;
 pub mod traits;
 
 pub use common::*;
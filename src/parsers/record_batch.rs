@@ -0,0 +1,131 @@
+//! Arrow RecordBatch conversion for parsed records
+//!
+//! Parsed ST-1 and ST-49 records can be turned directly into Arrow
+//! `RecordBatch`es, so they can be written to Delta Lake without going
+//! through an intermediate CSV file. Dates, timestamps, depths and other
+//! numeric fields are converted to typed Arrow columns on the way.
+//!
+//! ## Usage Example
+//!
+//! ```rust,ignore
+//! use aer_st1::parsers::record_batch::ArrowRecord;
+//! use aer_st1::st1::License;
+//!
+//! let report = st1::parse_file("WELLS0102", "TXT").await?;
+//! let batch = License::to_record_batch(&report.records)?;
+//! ```
+
+use std::sync::Arc;
+
+use deltalake::arrow::array::{Array, AsArray, RecordBatch};
+use deltalake::arrow::compute::cast;
+use deltalake::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use deltalake::arrow::error::ArrowError;
+use deltalake::arrow::json::ReaderBuilder;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// A parsed report record that can be converted into an Arrow `RecordBatch`
+pub trait ArrowRecord: Serialize + Sized {
+    /// Arrow schema matching the serialized fields of the record
+    fn arrow_schema() -> SchemaRef;
+
+    /// Convert a slice of records into a single `RecordBatch`
+    fn to_record_batch(records: &[Self]) -> Result<RecordBatch, ArrowError> {
+        records_to_batch(records, Self::arrow_schema())
+    }
+}
+
+/// Build a schema of nullable columns, defaulting every column to a string
+///
+/// # Arguments
+/// * `names` - Column names in output order
+/// * `typed` - Columns that use a type other than `Utf8`
+///
+/// # Returns
+/// Shared Arrow schema
+///
+/// # Example
+/// ```rust,ignore
+/// let schema = typed_schema(&["date", "well_name"], &[("date", DataType::Date32)]);
+/// ```
+pub fn typed_schema(names: &[&str], typed: &[(&str, DataType)]) -> SchemaRef {
+    Arc::new(Schema::new(
+        names
+            .iter()
+            .map(|name| {
+                let data_type = typed
+                    .iter()
+                    .find(|(typed_name, _)| typed_name == name)
+                    .map(|(_, data_type)| data_type.clone())
+                    .unwrap_or(DataType::Utf8);
+                Field::new(*name, data_type, true)
+            })
+            .collect::<Vec<_>>(),
+    ))
+}
+
+/// Serialize records into a `RecordBatch` with the given schema
+///
+/// # Arguments
+/// * `records` - Slice of serializable records
+/// * `schema` - Target Arrow schema
+///
+/// # Returns
+/// A single `RecordBatch` containing every record
+pub fn records_to_batch<T: Serialize>(
+    records: &[T],
+    schema: SchemaRef,
+) -> Result<RecordBatch, ArrowError> {
+    if records.is_empty() {
+        return Ok(RecordBatch::new_empty(schema));
+    }
+
+    let mut decoder = ReaderBuilder::new(Arc::clone(&schema)).build_decoder()?;
+    decoder.serialize(records)?;
+    decoder
+        .flush()?
+        .ok_or_else(|| ArrowError::JsonError("No rows were serialized".to_string()))
+}
+
+/// Read a `RecordBatch` back into records, treating every column as a string
+///
+/// Columns of any type are cast to `Utf8` first and nulls become empty strings,
+/// which matches how the parsers represent missing values. This is used to
+/// upgrade batches read from the original all-string Delta tables.
+///
+/// # Arguments
+/// * `batch` - Batch whose column names match the record's field names
+///
+/// # Returns
+/// One record per row in the batch
+pub fn string_batch_to_records<T: DeserializeOwned>(
+    batch: &RecordBatch,
+) -> Result<Vec<T>, ArrowError> {
+    let schema = batch.schema();
+    let mut columns = Vec::with_capacity(batch.num_columns());
+    for column in batch.columns() {
+        columns.push(cast(column, &DataType::Utf8)?);
+    }
+
+    (0..batch.num_rows())
+        .map(|row| {
+            let values = schema
+                .fields()
+                .iter()
+                .zip(&columns)
+                .map(|(field, column)| {
+                    let strings = column.as_string::<i32>();
+                    let value = if strings.is_null(row) {
+                        String::new()
+                    } else {
+                        strings.value(row).to_string()
+                    };
+                    (field.name().clone(), serde_json::Value::String(value))
+                })
+                .collect::<serde_json::Map<_, _>>();
+            serde_json::from_value(serde_json::Value::Object(values))
+                .map_err(|e| ArrowError::JsonError(e.to_string()))
+        })
+        .collect()
+}
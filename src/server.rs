@@ -0,0 +1,636 @@
+//! Local REST API over the managed Delta tables
+//!
+//! The `serve` command answers JSON requests from other applications on the
+//! same machine, reading the latest version of each table on every request:
+//!
+//! - `GET /licences`: ST-1 licence listings, filtered by licensee, area and
+//!   report date
+//! - `GET /licences/{number}`: every listing of one licence
+//! - `GET /spuds`: ST-49 spud records, filtered by licence, well, licensee,
+//!   contractor and report date
+//! - `GET /rigs/{contractor}/{rig}`: the timeline of one rig from the
+//!   `rig_timeline` table (see [`crate::delta::update_rig_timeline`])
+//! - `GET /openapi.json`: the OpenAPI description of these endpoints
+//!
+//! List endpoints are paginated with `limit` (default 100, at most 1000) and
+//! `offset`; the response carries the `next_offset` to request, or null on the
+//! last page. The list endpoints match licensees and contractors by their
+//! normalised name (see [`normalize_company_name`]), so any spelling finds them.
+//!
+//! ## Usage Example
+//!
+//! ```rust,ignore
+//! use aer_st1::server::{serve, ServeOptions};
+//!
+//! serve(ServeOptions::new("127.0.0.1:8080".parse()?)).await?;
+//! ```
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use axum::extract::{Path as UrlPath, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use chrono::NaiveDate;
+use datafusion::execution::FunctionRegistry;
+use datafusion::logical_expr::{Expr, SortExpr};
+use datafusion::prelude::{col, lit, DataFrame, SessionContext};
+use datafusion::scalar::ScalarValue;
+use deltalake::arrow::array::RecordBatch;
+use deltalake::arrow::compute::concat_batches;
+use deltalake::arrow::datatypes::{DataType, Date32Type, Schema};
+use deltalake::arrow::json::writer::JsonArray;
+use deltalake::arrow::json::WriterBuilder;
+use log::info;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::delta::{
+    open_delta_table_at, read_rig_timeline, register_aer_functions, DeltaReportType, TableVersion,
+};
+use crate::parsers::common::field_parsing::normalize_company_name;
+use crate::{storage, AppError};
+
+/// Rows per page when a request gives no `limit`.
+const DEFAULT_PAGE_SIZE: usize = 100;
+/// Largest `limit` a request may give.
+const MAX_PAGE_SIZE: usize = 1000;
+
+/// Options for [`serve`], one field per `serve` command line flag.
+#[derive(Debug, Clone)]
+pub struct ServeOptions {
+    /// Address to listen on
+    pub address: SocketAddr,
+    /// ST-1 Delta table behind `/licences`
+    pub st1_table_path: PathBuf,
+    /// ST-49 Delta table behind `/spuds`
+    pub st49_table_path: PathBuf,
+    /// `rig_timeline` table behind `/rigs`
+    pub rig_timeline_path: PathBuf,
+}
+
+impl ServeOptions {
+    /// Options serving the tables at their default locations under `data/deltalake`.
+    pub fn new(address: SocketAddr) -> Self {
+        Self {
+            address,
+            st1_table_path: PathBuf::from("data/deltalake/st1"),
+            st49_table_path: PathBuf::from("data/deltalake/st49"),
+            rig_timeline_path: PathBuf::from("data/deltalake/rig_timeline"),
+        }
+    }
+}
+
+/// An error response: a status code and a JSON `{"error": ...}` body.
+#[derive(Debug)]
+struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl<E: Into<AppError>> From<E> for ApiError {
+    fn from(error: E) -> Self {
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, error.into().to_string())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(json!({ "error": self.message }))).into_response()
+    }
+}
+
+/// Query parameters of `/licences`.
+#[derive(Debug, Deserialize)]
+struct LicenceQuery {
+    licensee: Option<String>,
+    field_centre: Option<String>,
+    meridian: Option<i32>,
+    range: Option<i32>,
+    township: Option<i32>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    limit: Option<usize>,
+    offset: Option<usize>,
+}
+
+/// Query parameters of `/spuds`.
+#[derive(Debug, Deserialize)]
+struct SpudQuery {
+    licence: Option<String>,
+    well_id: Option<String>,
+    licensee: Option<String>,
+    contractor: Option<String>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    limit: Option<usize>,
+    offset: Option<usize>,
+}
+
+/// Query parameters of `/rigs/{contractor}/{rig}`.
+#[derive(Debug, Deserialize)]
+struct PageQuery {
+    limit: Option<usize>,
+    offset: Option<usize>,
+}
+
+/// A page of rows: `limit` rows starting at `offset`.
+#[derive(Debug, Clone, Copy)]
+struct Page {
+    limit: usize,
+    offset: usize,
+}
+
+impl Page {
+    fn new(limit: Option<usize>, offset: Option<usize>) -> Result<Self, ApiError> {
+        let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE);
+        if limit == 0 || limit > MAX_PAGE_SIZE {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                format!("limit must be between 1 and {MAX_PAGE_SIZE}"),
+            ));
+        }
+        Ok(Self {
+            limit,
+            offset: offset.unwrap_or(0),
+        })
+    }
+
+    /// The response body for the rows of the page, plus one more row if
+    /// there is a next page.
+    fn body(self, mut items: Vec<Value>) -> Value {
+        let more = items.len() > self.limit;
+        items.truncate(self.limit);
+        json!({
+            "items": items,
+            "limit": self.limit,
+            "offset": self.offset,
+            "next_offset": more.then_some(self.offset + self.limit),
+        })
+    }
+}
+
+/// Rows as JSON objects, with nulls written out.
+fn rows_json(batches: &[RecordBatch]) -> Result<Vec<Value>, AppError> {
+    let mut writer = WriterBuilder::new()
+        .with_explicit_nulls(true)
+        .build::<_, JsonArray>(Vec::new());
+    for batch in batches {
+        writer.write(batch)?;
+    }
+    writer.finish()?;
+    let json = writer.into_inner();
+    if json.is_empty() {
+        return Ok(Vec::new());
+    }
+    Ok(serde_json::from_slice(&json)?)
+}
+
+/// A session with the AER SQL functions and the latest version of a table.
+///
+/// Answers 404 if the table does not exist, naming the command that creates it.
+async fn read_table(
+    table_path: &Path,
+    created_by: &str,
+) -> Result<(SessionContext, DataFrame), ApiError> {
+    let uri = table_path.to_str().unwrap_or_default();
+    if !storage::delta_table_exists(uri).await? {
+        return Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            format!("No Delta table at {uri}; run `{created_by}` first"),
+        ));
+    }
+    let table = open_delta_table_at(table_path, TableVersion::Latest).await?;
+    let ctx = SessionContext::new();
+    register_aer_functions(&ctx);
+    let df = ctx.read_table(Arc::new(table))?;
+    Ok((ctx, df))
+}
+
+/// Rows of a report table matching every filter, in order, one page at a time.
+async fn report_rows(
+    report_type: DeltaReportType,
+    df: DataFrame,
+    filters: Vec<Expr>,
+    order: Vec<SortExpr>,
+    page: Option<Page>,
+) -> Result<Vec<Value>, ApiError> {
+    let schema = report_type.arrow_schema();
+    let columns: Vec<&str> = schema
+        .fields()
+        .iter()
+        .map(|field| field.name().as_str())
+        .collect();
+    let mut df = df;
+    for filter in filters {
+        df = df.filter(filter)?;
+    }
+    let mut df = df.select_columns(&columns)?.sort(order)?;
+    if let Some(page) = page {
+        df = df.limit(page.offset, Some(page.limit + 1))?;
+    }
+    Ok(rows_json(&df.collect().await?)?)
+}
+
+/// A `DATE` literal.
+fn date_literal(date: NaiveDate) -> Expr {
+    lit(ScalarValue::Date32(Some(Date32Type::from_naive_date(date))))
+}
+
+/// Filter on the report `date` for the `from` and `to` parameters.
+fn date_filters(from: Option<NaiveDate>, to: Option<NaiveDate>) -> Vec<Expr> {
+    let mut filters = Vec::new();
+    if let Some(from) = from {
+        filters.push(col("date").gt_eq(date_literal(from)));
+    }
+    if let Some(to) = to {
+        filters.push(col("date").lt_eq(date_literal(to)));
+    }
+    filters
+}
+
+/// Filter matching a company name column in any spelling.
+fn company_filter(ctx: &SessionContext, column: &str, name: &str) -> Result<Expr, AppError> {
+    let key = normalize_company_name(name).unwrap_or_default();
+    Ok(ctx
+        .udf("company_name_key")?
+        .call(vec![col(column)])
+        .eq(lit(key)))
+}
+
+async fn list_licences(
+    State(options): State<Arc<ServeOptions>>,
+    Query(query): Query<LicenceQuery>,
+) -> Result<Json<Value>, ApiError> {
+    let page = Page::new(query.limit, query.offset)?;
+    let (ctx, df) = read_table(&options.st1_table_path, "load-delta").await?;
+    let mut filters = date_filters(query.from, query.to);
+    if let Some(licensee) = &query.licensee {
+        filters.push(company_filter(&ctx, "licensee", licensee)?);
+    }
+    if let Some(field_centre) = &query.field_centre {
+        filters.push(col("aer_field_centre").eq(lit(field_centre.as_str())));
+    }
+    for (part, value) in [
+        ("uwi_meridian", query.meridian),
+        ("uwi_range", query.range),
+        ("uwi_township", query.township),
+    ] {
+        if let Some(value) = value {
+            let location = ctx.udf(part)?.call(vec![col("unique_identifier")]);
+            filters.push(location.eq(lit(value)));
+        }
+    }
+    let order = vec![
+        col("date").sort(false, false),
+        col("licence_number").sort(true, false),
+    ];
+    let rows = report_rows(DeltaReportType::St1, df, filters, order, Some(page)).await?;
+    Ok(Json(page.body(rows)))
+}
+
+async fn get_licence(
+    State(options): State<Arc<ServeOptions>>,
+    UrlPath(number): UrlPath<String>,
+) -> Result<Json<Value>, ApiError> {
+    let (_, df) = read_table(&options.st1_table_path, "load-delta").await?;
+    let filters = vec![col("licence_number").eq(lit(number.as_str()))];
+    let order = vec![col("date").sort(true, false)];
+    let listings = report_rows(DeltaReportType::St1, df, filters, order, None).await?;
+    if listings.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            format!("No licence {number}"),
+        ));
+    }
+    Ok(Json(
+        json!({ "licence_number": number, "listings": listings }),
+    ))
+}
+
+async fn list_spuds(
+    State(options): State<Arc<ServeOptions>>,
+    Query(query): Query<SpudQuery>,
+) -> Result<Json<Value>, ApiError> {
+    let page = Page::new(query.limit, query.offset)?;
+    let (ctx, df) = read_table(&options.st49_table_path, "load-delta").await?;
+    let mut filters = date_filters(query.from, query.to);
+    if let Some(licence) = &query.licence {
+        filters.push(col("licence").eq(lit(licence.as_str())));
+    }
+    if let Some(well_id) = &query.well_id {
+        filters.push(col("well_id").eq(lit(well_id.as_str())));
+    }
+    if let Some(licensee) = &query.licensee {
+        filters.push(company_filter(&ctx, "licensee", licensee)?);
+    }
+    if let Some(contractor) = &query.contractor {
+        filters.push(
+            col("contractor_ba_id")
+                .eq(lit(contractor.as_str()))
+                .or(company_filter(&ctx, "contractor_name", contractor)?),
+        );
+    }
+    let order = vec![
+        col("date").sort(false, false),
+        col("activity_date").sort(false, false),
+        col("well_id").sort(true, false),
+    ];
+    let rows = report_rows(DeltaReportType::St49, df, filters, order, Some(page)).await?;
+    Ok(Json(page.body(rows)))
+}
+
+async fn get_rig(
+    State(options): State<Arc<ServeOptions>>,
+    UrlPath((contractor, rig)): UrlPath<(String, i32)>,
+    Query(query): Query<PageQuery>,
+) -> Result<Json<Value>, ApiError> {
+    let page = Page::new(query.limit, query.offset)?;
+    read_table(&options.rig_timeline_path, "rig-timeline").await?;
+    let timeline =
+        read_rig_timeline(&options.rig_timeline_path, Some(&contractor), Some(rig)).await?;
+    if timeline.num_rows() == 0 {
+        return Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            format!("No activities of rig {rig} of {contractor}"),
+        ));
+    }
+    let rows = concat_batches(&timeline.schema, &timeline.batches)?;
+    let start = page.offset.min(rows.num_rows());
+    let length = (page.limit + 1).min(rows.num_rows() - start);
+    let items = rows_json(&[rows.slice(start, length)])?;
+    Ok(Json(page.body(items)))
+}
+
+/// OpenAPI type of an Arrow column.
+fn openapi_type(data_type: &DataType) -> Value {
+    match data_type {
+        DataType::Date32 => json!({ "type": "string", "format": "date", "nullable": true }),
+        DataType::Timestamp(_, _) => {
+            json!({ "type": "string", "format": "date-time", "nullable": true })
+        }
+        DataType::Float64 => json!({ "type": "number", "nullable": true }),
+        DataType::Int32 | DataType::Int64 => json!({ "type": "integer", "nullable": true }),
+        DataType::Boolean => json!({ "type": "boolean", "nullable": true }),
+        _ => json!({ "type": "string", "nullable": true }),
+    }
+}
+
+/// OpenAPI object schema with a property per column.
+fn openapi_object(schema: &Schema) -> Value {
+    let properties: serde_json::Map<String, Value> = schema
+        .fields()
+        .iter()
+        .map(|field| (field.name().clone(), openapi_type(field.data_type())))
+        .collect();
+    json!({ "type": "object", "properties": properties })
+}
+
+/// The OpenAPI 3.0 description of the API.
+///
+/// # Returns
+/// The description as JSON, as served at `/openapi.json`
+pub fn openapi() -> Value {
+    let parameter = |name: &str, location: &str, schema: Value, description: &str| {
+        json!({
+            "name": name,
+            "in": location,
+            "required": location == "path",
+            "schema": schema,
+            "description": description,
+        })
+    };
+    let string = json!({ "type": "string" });
+    let integer = json!({ "type": "integer" });
+    let date = json!({ "type": "string", "format": "date" });
+    let page_parameters = [
+        parameter(
+            "limit",
+            "query",
+            json!({ "type": "integer", "minimum": 1, "maximum": MAX_PAGE_SIZE, "default": DEFAULT_PAGE_SIZE }),
+            "Rows per page",
+        ),
+        parameter(
+            "offset",
+            "query",
+            json!({ "type": "integer", "minimum": 0, "default": 0 }),
+            "Rows to skip",
+        ),
+    ];
+    let date_parameters = [
+        parameter(
+            "from",
+            "query",
+            date.clone(),
+            "Only report dates on or after this date",
+        ),
+        parameter(
+            "to",
+            "query",
+            date,
+            "Only report dates on or before this date",
+        ),
+    ];
+    let page_of = |item: &str| {
+        json!({
+            "type": "object",
+            "properties": {
+                "items": { "type": "array", "items": { "$ref": format!("#/components/schemas/{item}") } },
+                "limit": { "type": "integer" },
+                "offset": { "type": "integer" },
+                "next_offset": { "type": "integer", "nullable": true },
+            },
+        })
+    };
+    let response = |description: &str, schema: Value| {
+        json!({
+            "200": {
+                "description": description,
+                "content": { "application/json": { "schema": schema } },
+            },
+            "default": {
+                "description": "Error",
+                "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Error" } } },
+            },
+        })
+    };
+    let rig_activity = json!({
+        "type": "object",
+        "properties": {
+            "contractor_ba_id": { "type": "string" },
+            "contractor_name": { "type": "string", "nullable": true },
+            "rig_number": { "type": "integer" },
+            "sequence": { "type": "integer" },
+            "activity_date": { "type": "string", "format": "date-time" },
+            "well_id": { "type": "string" },
+            "well_name": { "type": "string", "nullable": true },
+            "licence": { "type": "string", "nullable": true },
+            "licensee": { "type": "string", "nullable": true },
+            "activity_type": { "type": "string" },
+            "date": { "type": "string", "format": "date" },
+            "previous_well_id": { "type": "string", "nullable": true },
+            "days_since_previous": { "type": "number", "nullable": true },
+            "days_on_well": { "type": "number", "nullable": true },
+        },
+    });
+
+    let licence_parameters: Vec<Value> = [
+        parameter(
+            "licensee",
+            "query",
+            string.clone(),
+            "Licensee, in any spelling",
+        ),
+        parameter("field_centre", "query", string.clone(), "AER field centre"),
+        parameter(
+            "meridian",
+            "query",
+            integer.clone(),
+            "DLS meridian of the unique identifier",
+        ),
+        parameter(
+            "range",
+            "query",
+            integer.clone(),
+            "DLS range of the unique identifier",
+        ),
+        parameter(
+            "township",
+            "query",
+            integer.clone(),
+            "DLS township of the unique identifier",
+        ),
+    ]
+    .into_iter()
+    .chain(date_parameters.clone())
+    .chain(page_parameters.clone())
+    .collect();
+    let spud_parameters: Vec<Value> = [
+        parameter("licence", "query", string.clone(), "Licence number"),
+        parameter("well_id", "query", string.clone(), "Well ID"),
+        parameter(
+            "licensee",
+            "query",
+            string.clone(),
+            "Licensee, in any spelling",
+        ),
+        parameter(
+            "contractor",
+            "query",
+            string.clone(),
+            "Drilling contractor BA ID, or name in any spelling",
+        ),
+    ]
+    .into_iter()
+    .chain(date_parameters)
+    .chain(page_parameters.clone())
+    .collect();
+    let rig_parameters: Vec<Value> = [
+        parameter(
+            "contractor",
+            "path",
+            string.clone(),
+            "Drilling contractor BA ID, or name",
+        ),
+        parameter("rig", "path", integer, "Rig number"),
+    ]
+    .into_iter()
+    .chain(page_parameters)
+    .collect();
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "AER licence and spud data",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "ST-1 well licences and ST-49 spud data from the local Delta tables.",
+        },
+        "paths": {
+            "/licences": {
+                "get": {
+                    "summary": "ST-1 licence listings, latest report date first",
+                    "parameters": licence_parameters,
+                    "responses": response("A page of licence listings", page_of("Licence")),
+                },
+            },
+            "/licences/{number}": {
+                "get": {
+                    "summary": "Every listing of one licence, oldest first",
+                    "parameters": [parameter("number", "path", string, "Licence number")],
+                    "responses": response("The licence listings", json!({
+                        "type": "object",
+                        "properties": {
+                            "licence_number": { "type": "string" },
+                            "listings": { "type": "array", "items": { "$ref": "#/components/schemas/Licence" } },
+                        },
+                    })),
+                },
+            },
+            "/spuds": {
+                "get": {
+                    "summary": "ST-49 spud records, latest report date first",
+                    "parameters": spud_parameters,
+                    "responses": response("A page of spud records", page_of("Spud")),
+                },
+            },
+            "/rigs/{contractor}/{rig}": {
+                "get": {
+                    "summary": "The activities of one rig in order, from the rig_timeline table",
+                    "parameters": rig_parameters,
+                    "responses": response("A page of rig activities", page_of("RigActivity")),
+                },
+            },
+        },
+        "components": {
+            "schemas": {
+                "Licence": openapi_object(&DeltaReportType::St1.arrow_schema()),
+                "Spud": openapi_object(&DeltaReportType::St49.arrow_schema()),
+                "RigActivity": rig_activity,
+                "Error": { "type": "object", "properties": { "error": { "type": "string" } } },
+            },
+        },
+    })
+}
+
+/// The API routes over the tables of `options`.
+///
+/// # Returns
+/// A router to serve with [`axum::serve`], or to nest in a larger application
+pub fn router(options: ServeOptions) -> Router {
+    Router::new()
+        .route("/licences", get(list_licences))
+        .route("/licences/{number}", get(get_licence))
+        .route("/spuds", get(list_spuds))
+        .route("/rigs/{contractor}/{rig}", get(get_rig))
+        .route("/openapi.json", get(|| async { Json(openapi()) }))
+        .with_state(Arc::new(options))
+}
+
+/// Serve the API until the process is stopped.
+///
+/// # Arguments
+/// * `options` - Address to listen on and the tables to serve
+///
+/// # Returns
+/// Only on an error binding the address or accepting connections
+pub async fn serve(options: ServeOptions) -> Result<(), AppError> {
+    let listener = tokio::net::TcpListener::bind(options.address).await?;
+    info!(
+        "Serving the AER API on http://{} (OpenAPI description at /openapi.json)",
+        listener.local_addr()?
+    );
+    axum::serve(listener, router(options)).await?;
+    Ok(())
+}
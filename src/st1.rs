@@ -23,10 +23,17 @@
 //! st1::process_folder("TXT", "CSV").await?;
 //! ```
 
-use crate::parsers::common::{date_utils, file_ops, trim_and_remove_empty_lines, write_csv_records};
+use crate::parsers::common::{
+    date_utils, field_parsing, file_ops, trim_and_remove_empty_lines, write_report_csv,
+};
 use crate::parsers::error::ParseError;
+use crate::parsers::lineage::{source_line_numbers, ParsedReport, SourceFile};
+use crate::parsers::record_batch::{records_to_batch, typed_schema, ArrowRecord};
 use crate::AppError;
 use chrono::NaiveDate;
+use deltalake::arrow::array::RecordBatch;
+use deltalake::arrow::datatypes::{DataType, SchemaRef};
+use deltalake::arrow::error::ArrowError;
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 
@@ -88,6 +95,95 @@ pub struct License {
     pub surface_location: String,
 }
 
+/// Column names of [`License`] in CSV and Arrow output order
+pub const LICENSE_COLUMNS: [&str; 18] = [
+    "date",
+    "well_name",
+    "licence_number",
+    "mineral_rights",
+    "ground_elevation",
+    "unique_identifier",
+    "surface_coordinates",
+    "aer_field_centre",
+    "projected_depth",
+    "aer_classification",
+    "field",
+    "terminating_zone",
+    "drilling_operation",
+    "well_purpose",
+    "well_type",
+    "substance",
+    "licensee",
+    "surface_location",
+];
+
+/// Typed view of a [`License`] matching the Arrow schema
+#[derive(Serialize)]
+struct LicenseRow<'a> {
+    date: Option<NaiveDate>,
+    well_name: &'a str,
+    licence_number: &'a str,
+    mineral_rights: &'a str,
+    ground_elevation: Option<f64>,
+    unique_identifier: &'a str,
+    surface_coordinates: &'a str,
+    aer_field_centre: &'a str,
+    projected_depth: Option<f64>,
+    aer_classification: &'a str,
+    field: &'a str,
+    terminating_zone: &'a str,
+    drilling_operation: &'a str,
+    well_purpose: &'a str,
+    well_type: &'a str,
+    substance: &'a str,
+    licensee: &'a str,
+    surface_location: &'a str,
+}
+
+impl<'a> From<&'a License> for LicenseRow<'a> {
+    fn from(licence: &'a License) -> Self {
+        LicenseRow {
+            date: field_parsing::parse_report_date(&licence.date),
+            well_name: &licence.well_name,
+            licence_number: &licence.licence_number,
+            mineral_rights: &licence.mineral_rights,
+            ground_elevation: field_parsing::parse_metres(&licence.ground_elevation),
+            unique_identifier: &licence.unique_identifier,
+            surface_coordinates: &licence.surface_coordinates,
+            aer_field_centre: &licence.aer_field_centre,
+            projected_depth: field_parsing::parse_metres(&licence.projected_depth),
+            aer_classification: &licence.aer_classification,
+            field: &licence.field,
+            terminating_zone: &licence.terminating_zone,
+            drilling_operation: &licence.drilling_operation,
+            well_purpose: &licence.well_purpose,
+            well_type: &licence.well_type,
+            substance: &licence.substance,
+            licensee: &licence.licensee,
+            surface_location: &licence.surface_location,
+        }
+    }
+}
+
+impl ArrowRecord for License {
+    /// `date` is a Date column and the elevation/depth columns are metres as Doubles
+    fn arrow_schema() -> SchemaRef {
+        typed_schema(
+            &LICENSE_COLUMNS,
+            &[
+                ("date", DataType::Date32),
+                ("ground_elevation", DataType::Float64),
+                ("projected_depth", DataType::Float64),
+            ],
+        )
+    }
+
+    fn to_record_batch(records: &[Self]) -> Result<RecordBatch, ArrowError> {
+        let rows: Vec<LicenseRow> = records.iter().map(LicenseRow::from).collect();
+        records_to_batch(&rows, Self::arrow_schema())
+    }
+}
+
 /// Extract license data lines from ST1 report content
 ///
 /// # Arguments
@@ -209,43 +305,78 @@ fn extract_license(lines: Vec<String>, date: NaiveDate) -> Vec<License> {
     licences
 }
 
-/// Process a single ST1 file and convert to CSV
+/// Parse a single ST1 file into license records
 ///
 /// # Arguments
 /// * `filename_stem` - Base filename without extension (e.g., "WELLS0102")
 /// * `txt_input_dir` - Directory containing input .TXT files
-/// * `csv_output_dir` - Directory for output .CSV files
 ///
 /// # Returns
-/// The parsed date from the report
+/// The report date, the extracted licenses and the line each license starts on
 ///
 /// # Example
-/// ```rust
-/// let date = st1::process_file("WELLS0102", "TXT", "CSV").await?;
+/// ```rust,ignore
+/// let report = st1::parse_file("WELLS0102", "TXT").await?;
+/// println!("{} licences on {}", report.records.len(), report.date);
 /// ```
-pub async fn process_file(
+pub async fn parse_file(
     filename_stem: &str,
     txt_input_dir: &str,
-    csv_output_dir: &str,
-) -> Result<NaiveDate, AppError> {
+) -> Result<ParsedReport<License>, AppError> {
     let filename = format!("{}/{}.TXT", txt_input_dir, filename_stem);
     let content = file_ops::read_file_content(&filename)?;
     let lines: Vec<String> = content.lines().map(|s| s.to_string()).collect();
     let lines_trimmed = trim_and_remove_empty_lines(lines);
 
-    let extracted_date = date_utils::extract_st1_date(&lines_trimmed)
-        .map_err(|e| AppError::FileProcessing(e))?;
+    let extracted_date =
+        date_utils::extract_st1_date(&lines_trimmed).map_err(AppError::FileProcessing)?;
 
     let licences_lines = extract_licences_lines(&lines_trimmed)?;
     let licences_lines_trimmed = trim_and_remove_empty_lines(licences_lines);
+    // Each licence spans five lines; only complete blocks become licences
+    let first_lines: Vec<&String> = licences_lines_trimmed
+        .chunks(5)
+        .filter(|chunk| chunk.len() == 5)
+        .map(|chunk| &chunk[0])
+        .collect();
+    let source_lines = source_line_numbers(&content, &first_lines);
     let licences = extract_license(licences_lines_trimmed, extracted_date);
 
-    if !licences.is_empty() {
+    Ok(ParsedReport {
+        date: extracted_date,
+        records: licences,
+        source_lines,
+        source: SourceFile::from_contents(Path::new(&filename), content.as_bytes()),
+    })
+}
+
+/// Process a single ST1 file and convert to CSV
+///
+/// # Arguments
+/// * `filename_stem` - Base filename without extension (e.g., "WELLS0102")
+/// * `txt_input_dir` - Directory containing input .TXT files
+/// * `csv_output_dir` - Directory for output .CSV files
+///
+/// # Returns
+/// The parsed date from the report
+///
+/// # Example
+/// ```rust
+/// let date = st1::process_file("WELLS0102", "TXT", "CSV").await?;
+/// ```
+pub async fn process_file(
+    filename_stem: &str,
+    txt_input_dir: &str,
+    csv_output_dir: &str,
+) -> Result<NaiveDate, AppError> {
+    let report = parse_file(filename_stem, txt_input_dir).await?;
+
+    if !report.records.is_empty() {
         let output_path = Path::new(csv_output_dir);
-        write_csv_records(&licences, output_path, "WELLS", extracted_date)?;
+        write_report_csv(&report, output_path, "WELLS")?;
     }
 
-    Ok(extracted_date)
+    Ok(report.date)
 }
 
 /// Process all ST1 files in a folder
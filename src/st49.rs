@@ -23,10 +23,17 @@
 //! st49::process_folder("TXT", "CSV").await?;
 //! ```
 
-use crate::parsers::common::{date_utils, file_ops, trim_and_remove_empty_lines, write_csv_records};
+use crate::parsers::common::{
+    date_utils, field_parsing, file_ops, trim_and_remove_empty_lines, write_report_csv,
+};
 use crate::parsers::error::ParseError;
+use crate::parsers::lineage::{source_line_numbers, ParsedReport, SourceFile};
+use crate::parsers::record_batch::{records_to_batch, typed_schema, ArrowRecord};
 use crate::AppError;
-use chrono::NaiveDate;
+use chrono::{NaiveDate, NaiveDateTime};
+use deltalake::arrow::array::RecordBatch;
+use deltalake::arrow::datatypes::{DataType, SchemaRef, TimeUnit};
+use deltalake::arrow::error::ArrowError;
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 
@@ -73,6 +80,87 @@ pub struct SpudData {
     pub activity_type: String,
 }
 
+/// Column names of [`SpudData`] in CSV and Arrow output order
+pub const SPUD_COLUMNS: [&str; 13] = [
+    "date",
+    "well_id",
+    "well_name",
+    "licence",
+    "contractor_ba_id",
+    "contractor_name",
+    "rig_number",
+    "activity_date",
+    "field_centre",
+    "ba_id",
+    "licensee",
+    "new_projected_total_depth",
+    "activity_type",
+];
+
+/// Typed view of a [`SpudData`] record matching the Arrow schema
+#[derive(Serialize)]
+struct SpudRow<'a> {
+    date: Option<NaiveDate>,
+    well_id: &'a str,
+    well_name: &'a str,
+    licence: &'a str,
+    contractor_ba_id: &'a str,
+    contractor_name: &'a str,
+    rig_number: Option<i32>,
+    activity_date: Option<NaiveDateTime>,
+    field_centre: &'a str,
+    ba_id: &'a str,
+    licensee: &'a str,
+    new_projected_total_depth: Option<f64>,
+    activity_type: &'a str,
+}
+
+impl<'a> From<&'a SpudData> for SpudRow<'a> {
+    fn from(spud: &'a SpudData) -> Self {
+        SpudRow {
+            date: field_parsing::parse_report_date(&spud.date),
+            well_id: &spud.well_id,
+            well_name: &spud.well_name,
+            licence: &spud.licence,
+            contractor_ba_id: &spud.contractor_ba_id,
+            contractor_name: &spud.contractor_name,
+            rig_number: field_parsing::parse_integer(&spud.rig_number),
+            activity_date: field_parsing::parse_activity_datetime(&spud.activity_date),
+            field_centre: &spud.field_centre,
+            ba_id: &spud.ba_id,
+            licensee: &spud.licensee,
+            new_projected_total_depth: field_parsing::parse_metres(
+                &spud.new_projected_total_depth,
+            ),
+            activity_type: &spud.activity_type,
+        }
+    }
+}
+
+impl ArrowRecord for SpudData {
+    /// `date` is a Date column, `activity_date` a timestamp without time zone,
+    /// `rig_number` an Integer and the projected depth metres as a Double
+    fn arrow_schema() -> SchemaRef {
+        typed_schema(
+            &SPUD_COLUMNS,
+            &[
+                ("date", DataType::Date32),
+                ("rig_number", DataType::Int32),
+                (
+                    "activity_date",
+                    DataType::Timestamp(TimeUnit::Microsecond, None),
+                ),
+                ("new_projected_total_depth", DataType::Float64),
+            ],
+        )
+    }
+
+    fn to_record_batch(records: &[Self]) -> Result<RecordBatch, ArrowError> {
+        let rows: Vec<SpudRow> = records.iter().map(SpudRow::from).collect();
+        records_to_batch(&rows, Self::arrow_schema())
+    }
+}
+
 /// Extract data and separator line from ST49 report content
 ///
 /// # Arguments
@@ -224,42 +312,71 @@ fn extract_spud_data(lines: Vec<String>, date: NaiveDate, separator: &str) -> Ve
     spud_data_list
 }
 
-/// Process a single ST49 file and convert to CSV
+/// Parse a single ST49 file into spud records
 ///
 /// # Arguments
 /// * `filename_stem` - Base filename without extension (e.g., "SPUD0101")
 /// * `txt_input_dir` - Directory containing input .TXT files
-/// * `csv_output_dir` - Directory for output .CSV files
 ///
 /// # Returns
-/// The parsed date from the report
+/// The report date, the extracted spud records and the line of each record
 ///
 /// # Example
-/// ```rust
-/// let date = st49::process_file("SPUD0101", "TXT", "CSV").await?;
+/// ```rust,ignore
+/// let report = st49::parse_file("SPUD0101", "TXT").await?;
+/// println!("{} spuds on {}", report.records.len(), report.date);
 /// ```
-pub async fn process_file(
+pub async fn parse_file(
     filename_stem: &str,
     txt_input_dir: &str,
-    csv_output_dir: &str,
-) -> Result<NaiveDate, AppError> {
+) -> Result<ParsedReport<SpudData>, AppError> {
     let filename = format!("{}/{}.txt", txt_input_dir, filename_stem);
     let content = file_ops::read_file_content(&filename)?;
     let lines: Vec<String> = content.lines().map(|s| s.to_string()).collect();
     let lines_trimmed = trim_and_remove_empty_lines(lines);
 
-    let extracted_date = date_utils::extract_st49_date(&lines_trimmed)
-        .map_err(|e| AppError::FileProcessing(e))?;
+    let extracted_date =
+        date_utils::extract_st49_date(&lines_trimmed).map_err(AppError::FileProcessing)?;
 
     let (spud_data_lines, separator_line) = extract_data_and_separator(&lines_trimmed)?;
+    let source_lines = source_line_numbers(&content, &spud_data_lines);
     let spud_data = extract_spud_data(spud_data_lines, extracted_date, &separator_line);
 
-    if !spud_data.is_empty() {
+    Ok(ParsedReport {
+        date: extracted_date,
+        records: spud_data,
+        source_lines,
+        source: SourceFile::from_contents(Path::new(&filename), content.as_bytes()),
+    })
+}
+
+/// Process a single ST49 file and convert to CSV
+///
+/// # Arguments
+/// * `filename_stem` - Base filename without extension (e.g., "SPUD0101")
+/// * `txt_input_dir` - Directory containing input .TXT files
+/// * `csv_output_dir` - Directory for output .CSV files
+///
+/// # Returns
+/// The parsed date from the report
+///
+/// # Example
+/// ```rust
+/// let date = st49::process_file("SPUD0101", "TXT", "CSV").await?;
+/// ```
+pub async fn process_file(
+    filename_stem: &str,
+    txt_input_dir: &str,
+    csv_output_dir: &str,
+) -> Result<NaiveDate, AppError> {
+    let report = parse_file(filename_stem, txt_input_dir).await?;
+
+    if !report.records.is_empty() {
         let output_path = Path::new(csv_output_dir);
-        write_csv_records(&spud_data, output_path, "SPUD", extracted_date)?;
+        write_report_csv(&report, output_path, "SPUD")?;
     }
 
-    Ok(extracted_date)
+    Ok(report.date)
 }
 
 /// Process all ST49 files in a folder
@@ -0,0 +1,337 @@
+//! Local paths and object store URIs behind one set of file operations
+//!
+//! Table paths, CSV and Parquet outputs and raw TXT archives may be local
+//! paths or URIs such as `s3://bucket/prefix`. A location whose URL scheme
+//! has a registered object store (`s3`, `s3a`, `file`, `memory`) goes through
+//! [`object_store`], with credentials and endpoints taken from the
+//! environment (`AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`, `AWS_REGION`,
+//! `AWS_ENDPOINT_URL`, ...); anything else is a local path.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Once};
+
+use datafusion::prelude::SessionContext;
+use deltalake::logstore::object_store::path::Path as ObjectPath;
+use deltalake::logstore::object_store::{ObjectStore, PutPayload};
+use deltalake::logstore::{object_store_factories, store_for};
+use deltalake::ObjectStoreError;
+use futures::TryStreamExt;
+use log::info;
+use tempfile::TempDir;
+use url::Url;
+
+use crate::AppError;
+
+/// Register the `s3://` and `s3a://` schemes with deltalake.
+///
+/// Called by every function here that resolves a URI, so it only needs to be
+/// called directly before handing a URI to deltalake yourself.
+pub fn register_object_stores() {
+    static REGISTER: Once = Once::new();
+    REGISTER.call_once(|| deltalake::aws::register_handlers(None));
+}
+
+/// Whether a location is an object store URI rather than a local path.
+///
+/// Only schemes with a registered object store count, so a local path such
+/// as `data:2024/st1` or `C:\data` is not mistaken for a URI. `file://` URIs
+/// count as object store URIs, which makes them a convenient local stand-in
+/// for a bucket.
+pub fn is_object_store_uri(location: &str) -> bool {
+    register_object_stores();
+    let Ok(url) = Url::parse(location) else {
+        return false;
+    };
+    Url::parse(&format!("{}://", url.scheme()))
+        .is_ok_and(|scheme| object_store_factories().contains_key(&scheme))
+}
+
+/// The object store holding a URI, rooted at its bucket, and the URI's path within it.
+///
+/// # Arguments
+/// * `uri` - Object store URI, e.g. `s3://bucket/st1/_delta_log`
+///
+/// # Returns
+/// The store and the object path, e.g. `st1/_delta_log`
+pub fn object_store(uri: &str) -> Result<(Arc<dyn ObjectStore>, ObjectPath), AppError> {
+    register_object_stores();
+    let url =
+        Url::parse(uri).map_err(|e| AppError::Cli(format!("Invalid location `{uri}`: {e}")))?;
+    let path = ObjectPath::from_url_path(url.path()).map_err(ObjectStoreError::from)?;
+    let mut root = url;
+    root.set_path("/");
+    let store = store_for(&root, std::iter::empty::<(String, String)>())?;
+    Ok((store, path))
+}
+
+/// Make the object store holding a URI available to DataFusion queries.
+///
+/// Delta tables register their own store; this is for files DataFusion reads
+/// or writes directly, such as CSV and Parquet files or export outputs.
+pub fn register_with_session(ctx: &SessionContext, uri: &str) -> Result<(), AppError> {
+    let (store, _) = object_store(uri)?;
+    let url =
+        Url::parse(uri).map_err(|e| AppError::Cli(format!("Invalid location `{uri}`: {e}")))?;
+    ctx.register_object_store(&url, store);
+    Ok(())
+}
+
+/// Append a file or directory name to a location.
+pub fn join(location: &str, name: &str) -> String {
+    if is_object_store_uri(location) {
+        format!("{}/{name}", location.trim_end_matches('/'))
+    } else {
+        Path::new(location)
+            .join(name)
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// Whether a file, or any file below a directory, exists at a location.
+pub async fn exists(location: &str) -> Result<bool, AppError> {
+    if !is_object_store_uri(location) {
+        return Ok(Path::new(location).exists());
+    }
+    let (store, path) = object_store(location)?;
+    if store.list(Some(&path)).try_next().await?.is_some() {
+        return Ok(true);
+    }
+    match store.head(&path).await {
+        Ok(_) => Ok(true),
+        Err(ObjectStoreError::NotFound { .. }) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Whether a location holds a delta table, i.e. has a `_delta_log` directory.
+pub async fn delta_table_exists(table_uri: &str) -> Result<bool, AppError> {
+    exists(&join(table_uri, "_delta_log")).await
+}
+
+/// Read a whole file, or `None` if it does not exist.
+pub async fn read(location: &str) -> Result<Option<Vec<u8>>, AppError> {
+    if !is_object_store_uri(location) {
+        return match std::fs::read(location) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        };
+    }
+    let (store, path) = object_store(location)?;
+    match store.get(&path).await {
+        Ok(result) => Ok(Some(result.bytes().await?.to_vec())),
+        Err(ObjectStoreError::NotFound { .. }) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Write a whole file, replacing it if it exists.
+pub async fn write(location: &str, contents: Vec<u8>) -> Result<(), AppError> {
+    if !is_object_store_uri(location) {
+        std::fs::write(location, contents)?;
+        return Ok(());
+    }
+    let (store, path) = object_store(location)?;
+    store.put(&path, PutPayload::from(contents)).await?;
+    Ok(())
+}
+
+/// Append to a file, creating it if it does not exist.
+///
+/// Object stores cannot append, so the object is rewritten; concurrent
+/// appends to the same object may lose one of the writes.
+pub async fn append(location: &str, contents: &[u8]) -> Result<(), AppError> {
+    if !is_object_store_uri(location) {
+        use std::io::Write;
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(location)?;
+        file.write_all(contents)?;
+        return Ok(());
+    }
+    let mut existing = read(location).await?.unwrap_or_default();
+    existing.extend_from_slice(contents);
+    write(location, existing).await
+}
+
+/// Names of the files directly inside a directory, sorted.
+pub async fn list_files(location: &str) -> Result<Vec<String>, AppError> {
+    let mut names = Vec::new();
+    if !is_object_store_uri(location) {
+        for entry in std::fs::read_dir(location)? {
+            let path = entry?.path();
+            if let (true, Some(name)) = (path.is_file(), path.file_name().and_then(|n| n.to_str()))
+            {
+                names.push(name.to_string());
+            }
+        }
+    } else {
+        let (store, path) = object_store(location)?;
+        let listing = store.list_with_delimiter(Some(&path)).await?;
+        names.extend(
+            listing
+                .objects
+                .iter()
+                .filter_map(|object| object.location.filename().map(str::to_string)),
+        );
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// Delete a file or a directory with everything below it.
+///
+/// # Returns
+/// Whether anything was deleted
+pub async fn remove_all(location: &str) -> Result<bool, AppError> {
+    if !is_object_store_uri(location) {
+        let path = Path::new(location);
+        if path.is_dir() {
+            std::fs::remove_dir_all(path)?;
+        } else if path.exists() {
+            std::fs::remove_file(path)?;
+        } else {
+            return Ok(false);
+        }
+        return Ok(true);
+    }
+    let (store, path) = object_store(location)?;
+    let objects: Vec<_> = store.list(Some(&path)).try_collect().await?;
+    for object in &objects {
+        store.delete(&object.location).await?;
+    }
+    Ok(!objects.is_empty())
+}
+
+/// A local directory to read the files of a possibly remote location from.
+///
+/// Local locations are used as they are. For an object store URI each file is
+/// downloaded to a temporary directory by [`FetchedDir::fetch`], which is
+/// removed when the `FetchedDir` is dropped.
+///
+/// # Example
+/// ```rust,ignore
+/// let txt_input = FetchedDir::new("s3://lakehouse/txt")?;
+/// txt_input.fetch("WELLS0102.TXT").await?;
+/// st1::process_file("WELLS0102", txt_input.path(), "data/csv").await?;
+/// ```
+#[derive(Debug)]
+pub struct FetchedDir {
+    location: String,
+    download: Option<(TempDir, String)>,
+}
+
+impl FetchedDir {
+    /// Read files from `location`.
+    pub fn new(location: &str) -> Result<Self, AppError> {
+        let download = if is_object_store_uri(location) {
+            let dir = TempDir::new()?;
+            let path = dir.path().to_str().map(str::to_string).ok_or_else(|| {
+                AppError::FileProcessing(format!("Invalid download directory: {:?}", dir.path()))
+            })?;
+            Some((dir, path))
+        } else {
+            None
+        };
+        Ok(Self {
+            location: location.to_string(),
+            download,
+        })
+    }
+
+    /// Local directory to read the fetched files from.
+    pub fn path(&self) -> &str {
+        match &self.download {
+            Some((_, path)) => path,
+            None => &self.location,
+        }
+    }
+
+    /// Make the file `name` of the location available in [`path`](Self::path).
+    ///
+    /// # Returns
+    /// The local path of the file
+    pub async fn fetch(&self, name: &str) -> Result<PathBuf, AppError> {
+        let local = Path::new(self.path()).join(name);
+        if self.download.is_some() && !local.exists() {
+            let location = join(&self.location, name);
+            let contents = read(&location)
+                .await?
+                .ok_or_else(|| AppError::FileProcessing(format!("{location} does not exist")))?;
+            std::fs::write(&local, contents)?;
+            info!("Downloaded {location} to {local:?}");
+        }
+        Ok(local)
+    }
+}
+
+/// A local directory to write files into that end up at a possibly remote location.
+///
+/// Local locations are used as they are. For an object store URI the files
+/// are written to a temporary directory and uploaded by [`StagedDir::publish`];
+/// files removed from the directory before then are not uploaded.
+///
+/// # Example
+/// ```rust,ignore
+/// let csv_output = StagedDir::new("s3://lakehouse/csv")?;
+/// st1::process_file("WELLS0102", "data/txt", csv_output.path()).await?;
+/// csv_output.publish().await?;
+/// ```
+#[derive(Debug)]
+pub struct StagedDir {
+    location: String,
+    staging: Option<(TempDir, String)>,
+}
+
+impl StagedDir {
+    /// Stage files for `location`.
+    pub fn new(location: &str) -> Result<Self, AppError> {
+        let staging = if is_object_store_uri(location) {
+            let dir = TempDir::new()?;
+            let path = dir.path().to_str().map(str::to_string).ok_or_else(|| {
+                AppError::FileProcessing(format!("Invalid staging directory: {:?}", dir.path()))
+            })?;
+            Some((dir, path))
+        } else {
+            None
+        };
+        Ok(Self {
+            location: location.to_string(),
+            staging,
+        })
+    }
+
+    /// Local directory to write the files into.
+    pub fn path(&self) -> &str {
+        match &self.staging {
+            Some((_, path)) => path,
+            None => &self.location,
+        }
+    }
+
+    /// Upload the staged files to the location.
+    ///
+    /// # Returns
+    /// The number of files uploaded, 0 for a local location
+    pub async fn publish(self) -> Result<usize, AppError> {
+        let Some((dir, _)) = self.staging else {
+            return Ok(0);
+        };
+        let mut uploaded = 0;
+        for entry in std::fs::read_dir(dir.path())? {
+            let path = entry?.path();
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            if path.is_file() {
+                let target = join(&self.location, name);
+                write(&target, std::fs::read(&path)?).await?;
+                info!("Uploaded {path:?} to {target}");
+                uploaded += 1;
+            }
+        }
+        Ok(uploaded)
+    }
+}
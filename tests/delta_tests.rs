@@ -0,0 +1,2293 @@
+//! End-to-end tests for the Delta Lake commands of the `aer_parser` binary
+
+#[allow(dead_code, unused_imports)]
+mod fixtures;
+
+use std::path::Path;
+use std::process::Command;
+use std::sync::Arc;
+
+use aer_st1::delta::{
+    create_or_open_delta_table, export_delta_table, has_lineage, load_delta, loaded_file_hashes, loaded_source_files,
+    log_loaded_csv, read_load_log, reconcile_delta, register_aer_functions, reload_delta, sha256_file, update_rig_timeline,
+    write_records, DeltaReportType, ExportFormat, ExportOptions, LoadDeltaOptions, LoadMode,
+    Partitioning, ReloadOptions, ReportWriter, SourceFile, StreamingOptions, TableConstraints,
+    SOURCE_FILES_KEY,
+};
+use aer_st1::server::{router, ServeOptions};
+use aer_st1::parsers::common::write_csv_records;
+use aer_st1::parsers::record_batch::ArrowRecord;
+use aer_st1::st1::{License, LICENSE_COLUMNS};
+use aer_st1::st49::SpudData;
+use aer_st1::storage::{self, StagedDir};
+use chrono::NaiveDate;
+use deltalake::arrow::array::{Array, ArrayRef, AsArray, Int32Array, RecordBatch, StringArray};
+use deltalake::arrow::compute::concat_batches;
+use deltalake::arrow::datatypes::{DataType as ArrowDataType, Date32Type, Field, Int32Type, Schema};
+use deltalake::kernel::{DataType, PrimitiveType, StructField};
+use deltalake::parquet::file::reader::{FileReader, SerializedFileReader};
+use deltalake::delta_datafusion::DataFusionMixins;
+use deltalake::{DeltaOps, DeltaTable};
+use fixtures::TestData;
+
+fn run_cli(args: &[&str]) {
+    let status = Command::new(env!("CARGO_BIN_EXE_aer_parser"))
+        .args(args)
+        .status()
+        .expect("failed to run aer_parser");
+    assert!(status.success(), "aer_parser {args:?} failed");
+}
+
+fn licence(date: &str, licence_number: &str) -> License {
+    License {
+        date: date.to_string(),
+        well_name: format!("WELL {licence_number}"),
+        licence_number: licence_number.to_string(),
+        mineral_rights: "FREEHOLD".to_string(),
+        ground_elevation: "931.70M".to_string(),
+        unique_identifier: "100/07-36-026-18W4/00".to_string(),
+        surface_coordinates: "155.2M".to_string(),
+        aer_field_centre: "MEDICINE HAT".to_string(),
+        projected_depth: "3950.0M".to_string(),
+        aer_classification: "DEV (NC)".to_string(),
+        field: "WAYNE-ROSEDALE".to_string(),
+        terminating_zone: "BASAL QUARTZ SD".to_string(),
+        drilling_operation: "HORIZONTAL".to_string(),
+        well_purpose: "NEW".to_string(),
+        well_type: "PRODUCTION".to_string(),
+        substance: "CRUDE OIL".to_string(),
+        licensee: "TORXEN ENERGY LTD.".to_string(),
+        surface_location: "04-30-026-17W4".to_string(),
+    }
+}
+
+/// Writes one WELLS CSV per report date into `csv_dir`.
+fn write_st1_csvs(csv_dir: &Path, days: &[(&str, &[&str])]) {
+    std::fs::create_dir_all(csv_dir).unwrap();
+    for (date, licences) in days {
+        let records: Vec<License> = licences.iter().map(|l| licence(date, l)).collect();
+        let report_date = NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap();
+        write_csv_records(&records, csv_dir, "WELLS", report_date).unwrap();
+    }
+}
+
+/// Creates an ST-1 table with the original all-string schema and two rows.
+async fn create_string_st1_table(table_path: &Path) {
+    let names: Vec<&str> = LICENSE_COLUMNS.iter().copied().chain(["report_year"]).collect();
+    let columns = names.iter().map(|name| {
+        StructField::new(
+            name.to_string(),
+            DataType::Primitive(PrimitiveType::String),
+            true,
+        )
+    });
+    let table = DeltaOps::try_from_uri(table_path.to_str().unwrap())
+        .await
+        .unwrap()
+        .create()
+        .with_columns(columns)
+        .with_partition_columns(["report_year"])
+        .await
+        .unwrap();
+
+    let schema = Arc::new(Schema::new(
+        names
+            .iter()
+            .map(|name| Field::new(*name, ArrowDataType::Utf8, true))
+            .collect::<Vec<_>>(),
+    ));
+    let rows = [
+        ("2025-01-02", "0516990", "931.70M", "3950.0M"),
+        ("2025-01-02", "0516991", "1089.20M", ""),
+    ];
+    let arrays: Vec<ArrayRef> = names
+        .iter()
+        .map(|name| {
+            let values: Vec<&str> = rows
+                .iter()
+                .map(|(date, licence, elevation, depth)| match *name {
+                    "date" => *date,
+                    "licence_number" => *licence,
+                    "ground_elevation" => *elevation,
+                    "projected_depth" => *depth,
+                    "report_year" => "2025",
+                    _ => "X",
+                })
+                .collect();
+            Arc::new(StringArray::from(values)) as ArrayRef
+        })
+        .collect();
+    let batch = RecordBatch::try_new(schema, arrays).unwrap();
+
+    DeltaOps(table).write(vec![batch]).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_migrate_delta_rewrites_string_table_as_new_version() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let table_path = temp_dir.path().join("st1");
+    create_string_st1_table(&table_path).await;
+
+    run_cli(&[
+        "migrate-delta",
+        "--report-type",
+        "st1",
+        "--table-path",
+        table_path.to_str().unwrap(),
+    ]);
+
+    let table = deltalake::open_table(table_path.to_str().unwrap())
+        .await
+        .unwrap();
+    // Seeding the load log, the rewrite and the table properties
+    assert_eq!(table.version(), Some(4));
+
+    let schema = table.schema().unwrap();
+    assert_eq!(
+        schema.field("date").unwrap().data_type(),
+        &DataType::Primitive(PrimitiveType::Date)
+    );
+    assert!(!schema.field("date").unwrap().is_nullable());
+    assert_eq!(
+        schema.field("report_year").unwrap().data_type(),
+        &DataType::Primitive(PrimitiveType::Integer)
+    );
+    let metadata = table.metadata().unwrap();
+    assert_eq!(metadata.partition_columns(), &vec!["report_year".to_string()]);
+    let config = metadata.configuration();
+    assert!(config.contains_key("delta.constraints.projected_depth_positive"));
+    assert_eq!(config.get("delta.enableChangeDataFeed").map(String::as_str), Some("true"));
+    assert_eq!(config.get("aer_parser.loadLogSeeded").map(String::as_str), Some("true"));
+    assert_eq!(
+        schema.field("ground_elevation").unwrap().data_type(),
+        &DataType::Primitive(PrimitiveType::Double)
+    );
+    assert_eq!(
+        schema.field("licence_number").unwrap().data_type(),
+        &DataType::Primitive(PrimitiveType::String)
+    );
+
+    let (_, stream) = DeltaOps(table).load().await.unwrap();
+    let batches = deltalake::operations::collect_sendable_stream(stream)
+        .await
+        .unwrap();
+    assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 2);
+
+    // The string-typed version is still readable through time travel
+    let mut previous = deltalake::open_table(table_path.to_str().unwrap())
+        .await
+        .unwrap();
+    previous.load_version(1).await.unwrap();
+    assert_eq!(
+        previous
+            .schema()
+            .unwrap()
+            .field("date")
+            .unwrap()
+            .data_type(),
+        &DataType::Primitive(PrimitiveType::String)
+    );
+}
+
+#[tokio::test]
+async fn test_load_delta_partitions_by_report_year_and_month() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let csv_dir = temp_dir.path().join("csv");
+    let table_path = temp_dir.path().join("st1");
+    write_st1_csvs(
+        &csv_dir,
+        &[
+            ("2024-12-31", &["0516001", "0516002"]),
+            ("2025-01-02", &["0516990"]),
+        ],
+    );
+
+    run_cli(&[
+        "load-delta",
+        "--report-type",
+        "st1",
+        "--csv-folder",
+        csv_dir.to_str().unwrap(),
+        "--table-path",
+        table_path.to_str().unwrap(),
+        "--partition-by",
+        "year-month",
+    ]);
+
+    let table = deltalake::open_table(table_path.to_str().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(
+        table.metadata().unwrap().partition_columns(),
+        &vec!["report_year".to_string(), "report_month".to_string()]
+    );
+
+    let mut files: Vec<String> = table
+        .get_files_iter()
+        .unwrap()
+        .map(|path| path.to_string())
+        .collect();
+    files.sort();
+    assert_eq!(files.len(), 2);
+    assert!(files[0].starts_with("report_year=2024/report_month=12/"));
+    assert!(files[1].starts_with("report_year=2025/report_month=1/"));
+}
+
+/// Reads every row of the table at `table_path`.
+async fn read_table(table_path: &Path) -> (DeltaTable, RecordBatch) {
+    let table = deltalake::open_table(table_path.to_str().unwrap())
+        .await
+        .unwrap();
+    let (table, stream) = DeltaOps(table).load().await.unwrap();
+    let batches = deltalake::operations::collect_sendable_stream(stream)
+        .await
+        .unwrap();
+    let batch = concat_batches(&batches[0].schema(), &batches).unwrap();
+    (table, batch)
+}
+
+fn load_delta_merge(csv_dir: &Path, table_path: &Path) {
+    run_cli(&[
+        "load-delta",
+        "--report-type",
+        "st1",
+        "--csv-folder",
+        csv_dir.to_str().unwrap(),
+        "--table-path",
+        table_path.to_str().unwrap(),
+        "--partition-by",
+        "year",
+        "--load-mode",
+        "merge",
+    ]);
+}
+
+#[tokio::test]
+async fn test_load_delta_merge_is_idempotent_and_updates_in_place() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let table_path = temp_dir.path().join("st1");
+    let days: &[(&str, &[&str])] = &[
+        ("2024-12-31", &["0516001", "0516002"]),
+        ("2025-01-02", &["0516990"]),
+    ];
+    write_st1_csvs(&temp_dir.path().join("csv"), days);
+    load_delta_merge(&temp_dir.path().join("csv"), &table_path);
+    let (table, batch) = read_table(&table_path).await;
+    let loaded_version = table.version();
+    assert_eq!(batch.num_rows(), 3);
+
+    // Regenerated CSVs under a new path with rows in another order, so their
+    // hashes differ, and with the load log gone
+    std::fs::remove_file(table_path.join("delta_load_log.json")).unwrap();
+    write_st1_csvs(
+        &temp_dir.path().join("regenerated"),
+        &[
+            ("2024-12-31", &["0516002", "0516001"]),
+            ("2025-01-02", &["0516990"]),
+        ],
+    );
+    load_delta_merge(&temp_dir.path().join("regenerated"), &table_path);
+    let (table, batch) = read_table(&table_path).await;
+    assert_eq!(batch.num_rows(), 3);
+    assert_eq!(table.version(), loaded_version);
+
+    // A corrected licensee replaces the existing row instead of adding one
+    let corrected_dir = temp_dir.path().join("corrected");
+    std::fs::create_dir_all(&corrected_dir).unwrap();
+    let mut corrected = licence("2025-01-02", "0516990");
+    corrected.licensee = "CORRECTED ENERGY LTD.".to_string();
+    let report_date = NaiveDate::from_ymd_opt(2025, 1, 2).unwrap();
+    write_csv_records(&[corrected], &corrected_dir, "WELLS", report_date).unwrap();
+    load_delta_merge(&corrected_dir, &table_path);
+
+    let (_, batch) = read_table(&table_path).await;
+    assert_eq!(batch.num_rows(), 3);
+    let licence_numbers = batch
+        .column_by_name("licence_number")
+        .unwrap()
+        .as_string::<i32>();
+    let licensees = batch.column_by_name("licensee").unwrap().as_string::<i32>();
+    let row = (0..batch.num_rows())
+        .find(|&row| licence_numbers.value(row) == "0516990")
+        .unwrap();
+    assert_eq!(licensees.value(row), "CORRECTED ENERGY LTD.");
+}
+
+#[tokio::test]
+async fn test_load_delta_library_api_skips_loaded_files() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let csv_dir = temp_dir.path().join("csv");
+    write_st1_csvs(
+        &csv_dir,
+        &[
+            ("2024-12-31", &["0516001", "0516002"]),
+            ("2025-01-02", &["0516990"]),
+        ],
+    );
+
+    let mut options = LoadDeltaOptions::new(DeltaReportType::St1, temp_dir.path().join("st1"));
+    options.csv_folder = Some(csv_dir);
+
+    let report = load_delta(&options).await.unwrap();
+    assert_eq!(report.summary.rows, 3);
+    assert_eq!(report.loaded_files.len(), 2);
+    assert!(report.failed_files.is_empty());
+    assert!(options.log_path().exists());
+
+    let report = load_delta(&options).await.unwrap();
+    assert_eq!(report.summary.rows, 0);
+    assert!(report.loaded_files.is_empty());
+    assert_eq!(report.skipped_files.len(), 2);
+}
+
+#[tokio::test]
+async fn test_load_delta_dedupes_by_content_hash_from_table_history() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let csv_dir = temp_dir.path().join("csv");
+    write_st1_csvs(&csv_dir, &[("2025-01-02", &["0516990", "0516991"])]);
+
+    let mut options = LoadDeltaOptions::new(DeltaReportType::St1, temp_dir.path().join("st1"));
+    options.csv_folder = Some(csv_dir.clone());
+    let report = load_delta(&options).await.unwrap();
+    assert_eq!(report.summary.rows, 2);
+
+    let expected_hash = sha256_file(&csv_dir.join("20250102_WELLS.csv")).unwrap();
+    let history = report.table.history(None).await.unwrap();
+    let recorded = history
+        .iter()
+        .find_map(|commit| commit.info.get(SOURCE_FILES_KEY))
+        .unwrap();
+    assert_eq!(recorded[0]["sha256"], expected_hash.as_str());
+    assert_eq!(recorded[0]["fileName"], "20250102_WELLS.csv");
+
+    // Moving the data directory and losing the JSON log does not reload the file
+    let moved_dir = temp_dir.path().join("moved");
+    std::fs::rename(&csv_dir, &moved_dir).unwrap();
+    std::fs::remove_file(options.log_path()).unwrap();
+    options.csv_folder = Some(moved_dir);
+    let report = load_delta(&options).await.unwrap();
+    assert_eq!(report.summary.rows, 0);
+    assert_eq!(report.skipped_files.len(), 1);
+
+    // After another tool restores the empty table the file's rows are gone, so
+    // it loads again when such restores are honoured
+    let (table, _) = DeltaOps(report.table)
+        .restore()
+        .with_version_to_restore(0)
+        .await
+        .unwrap();
+    assert!(loaded_file_hashes(&table).await.unwrap().is_empty());
+    let report = load_delta(&options).await.unwrap();
+    assert_eq!(report.summary.rows, 0);
+    options.external_restores = true;
+    let report = load_delta(&options).await.unwrap();
+    assert_eq!(report.summary.rows, 2);
+}
+
+#[tokio::test]
+async fn test_external_restore_is_replayed_by_commit_version() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let table_path = temp_dir.path().join("st1");
+    let mut options = LoadDeltaOptions::new(DeltaReportType::St1, &table_path);
+    for (dir, date, licence_number) in [("first", "2025-01-02", "0516990"), ("second", "2025-01-03", "0516991")] {
+        let csv_dir = temp_dir.path().join(dir);
+        write_st1_csvs(&csv_dir, &[(date, &[licence_number])]);
+        options.csv_folder = Some(csv_dir);
+        load_delta(&options).await.unwrap();
+    }
+
+    // Version 3 is written by a tool that records no commitInfo
+    let log_dir = table_path.join("_delta_log");
+    std::fs::write(
+        log_dir.join(format!("{:020}.json", 3)),
+        "{\"txn\":{\"appId\":\"other-tool\",\"version\":1}}\n",
+    )
+    .unwrap();
+    let table = deltalake::open_table(table_path.to_str().unwrap()).await.unwrap();
+    let (table, _) = DeltaOps(table)
+        .restore()
+        .with_version_to_restore(1)
+        .await
+        .unwrap();
+    assert_eq!(table.version(), Some(4));
+
+    let loaded: Vec<String> = loaded_source_files(&table)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|file| file.file_name)
+        .collect();
+    assert_eq!(loaded, vec!["20250102_WELLS.csv"]);
+
+    // Only the second file's rows were removed, so only it loads again
+    options.csv_folder = None;
+    options.csv_path = Some(temp_dir.path().join("first/20250102_WELLS.csv"));
+    options.external_restores = true;
+    assert_eq!(load_delta(&options).await.unwrap().summary.rows, 0);
+    options.csv_path = Some(temp_dir.path().join("second/20250103_WELLS.csv"));
+    assert_eq!(load_delta(&options).await.unwrap().summary.rows, 1);
+}
+
+#[tokio::test]
+async fn test_load_delta_remembers_files_after_log_cleanup_and_seeds_load_log() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let csv_dir = temp_dir.path().join("csv");
+    write_st1_csvs(
+        &csv_dir,
+        &[("2025-01-02", &["0516990"]), ("2025-01-03", &["0516991"])],
+    );
+    let table_path = temp_dir.path().join("st1");
+    let log_dir = table_path.join("_delta_log");
+
+    // A table created before loads were recorded in it, whose load log lists one CSV
+    create_or_open_delta_table(&table_path, DeltaReportType::St1, Partitioning::None, false)
+        .await
+        .unwrap();
+    let first_commit = log_dir.join(format!("{:020}.json", 0));
+    let actions: Vec<String> = std::fs::read_to_string(&first_commit)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let mut action: serde_json::Value = serde_json::from_str(line).unwrap();
+            if let Some(configuration) = action.pointer_mut("/metaData/configuration") {
+                configuration
+                    .as_object_mut()
+                    .unwrap()
+                    .remove("aer_parser.loadLogSeeded");
+            }
+            action.to_string()
+        })
+        .collect();
+    std::fs::write(&first_commit, actions.join("\n")).unwrap();
+    let mut options = LoadDeltaOptions::new(DeltaReportType::St1, &table_path);
+    log_loaded_csv(&options.log_path(), &csv_dir.join("20250102_WELLS.csv"))
+        .await
+        .unwrap();
+
+    options.csv_folder = Some(csv_dir.clone());
+    let report = load_delta(&options).await.unwrap();
+    assert_eq!(report.summary.rows, 1);
+    assert_eq!(report.skipped_files, vec![csv_dir.join("20250102_WELLS.csv")]);
+    let version = report.table.version().unwrap();
+    assert_eq!(version, 2);
+
+    // Checkpoint, then remove the commits before it as log cleanup would
+    deltalake::checkpoints::create_checkpoint(&report.table, None)
+        .await
+        .unwrap();
+    for old_version in 0..version {
+        std::fs::remove_file(log_dir.join(format!("{old_version:020}.json"))).unwrap();
+    }
+    std::fs::remove_file(options.log_path()).unwrap();
+
+    let report = load_delta(&options).await.unwrap();
+    assert_eq!(report.summary.rows, 0);
+    assert_eq!(report.skipped_files.len(), 2);
+    assert_eq!(report.table.version(), Some(version));
+}
+
+#[tokio::test]
+async fn test_load_delta_lineage_columns_added_to_existing_table() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let table_path = temp_dir.path().join("st1");
+    write_st1_csvs(
+        &temp_dir.path().join("csv"),
+        &[("2024-12-31", &["0516001"])],
+    );
+    let mut options = LoadDeltaOptions::new(DeltaReportType::St1, &table_path);
+    options.csv_folder = Some(temp_dir.path().join("csv"));
+    options.load_mode = LoadMode::Merge;
+    load_delta(&options).await.unwrap();
+    let (table, _) = read_table(&table_path).await;
+    assert!(!has_lineage(&table));
+
+    write_st1_csvs(
+        &temp_dir.path().join("lineage"),
+        &[("2025-01-02", &["0516990", "0516991"])],
+    );
+    options.csv_folder = Some(temp_dir.path().join("lineage"));
+    options.lineage = true;
+    let report = load_delta(&options).await.unwrap();
+    assert_eq!(report.summary.inserted, 2);
+
+    let (table, batch) = read_table(&table_path).await;
+    assert!(has_lineage(&table));
+    let licence_numbers = batch
+        .column_by_name("licence_number")
+        .unwrap()
+        .as_string::<i32>();
+    let source_files = batch
+        .column_by_name("source_file")
+        .unwrap()
+        .as_string::<i32>();
+    let source_lines = batch
+        .column_by_name("source_line")
+        .unwrap()
+        .as_primitive::<Int32Type>();
+    let ingested_at = batch.column_by_name("ingested_at").unwrap();
+    for row in 0..batch.num_rows() {
+        match licence_numbers.value(row) {
+            // Rows loaded before lineage was enabled have none
+            "0516001" => {
+                assert!(source_files.is_null(row));
+                assert!(ingested_at.is_null(row));
+            }
+            number => {
+                assert_eq!(source_files.value(row), "20250102_WELLS.csv");
+                // Line 1 is the CSV header
+                let expected = if number == "0516990" { 2 } else { 3 };
+                assert_eq!(source_lines.value(row), expected);
+                assert!(ingested_at.is_valid(row));
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_load_delta_lineage_points_at_report_the_csv_was_written_from() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let txt_path = temp_dir.path().join("txt/WELLS01022025.TXT");
+    write_wells_report(
+        &txt_path,
+        "02 January 2025",
+        &[("0516990", "ALBERTA CROWN"), ("0516991", "FREEHOLD")],
+    );
+    let csv_dir = temp_dir.path().join("csv");
+    std::fs::create_dir_all(&csv_dir).unwrap();
+    aer_st1::st1::process_file(
+        "WELLS01022025",
+        txt_path.parent().unwrap().to_str().unwrap(),
+        csv_dir.to_str().unwrap(),
+    )
+    .await
+    .unwrap();
+    assert!(csv_dir.join("20250102_WELLS.csv.lineage.json").exists());
+
+    let mut options = LoadDeltaOptions::new(DeltaReportType::St1, temp_dir.path().join("st1"));
+    options.csv_folder = Some(csv_dir);
+    options.lineage = true;
+    let report = load_delta(&options).await.unwrap();
+    assert_eq!(report.summary.rows, 2);
+
+    let (_, batch) = read_table(&options.table_path).await;
+    let column = |name: &str| batch.column_by_name(name).unwrap().clone();
+    let txt_hash = sha256_file(&txt_path).unwrap();
+    for row in 0..batch.num_rows() {
+        assert_eq!(column("source_file").as_string::<i32>().value(row), "WELLS01022025.TXT");
+        assert_eq!(column("source_sha256").as_string::<i32>().value(row), txt_hash);
+        assert_eq!(
+            column("parser_version").as_string::<i32>().value(row),
+            env!("CARGO_PKG_VERSION")
+        );
+    }
+    let mut lines: Vec<i32> = column("source_line")
+        .as_primitive::<Int32Type>()
+        .iter()
+        .flatten()
+        .collect();
+    lines.sort_unstable();
+    assert_eq!(lines, vec![13, 18]);
+}
+
+#[tokio::test]
+async fn test_load_delta_streams_files_within_memory_budget() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let csv_dir = temp_dir.path().join("csv");
+    write_st1_csvs(
+        &csv_dir,
+        &[
+            ("2024-12-31", &["0516001", "0516002"]),
+            ("2025-01-01", &["0516500"]),
+            ("2025-01-02", &["0516990"]),
+        ],
+    );
+
+    let table_path = temp_dir.path().join("st1");
+    let mut options = LoadDeltaOptions::new(DeltaReportType::St1, &table_path);
+    options.csv_folder = Some(csv_dir);
+    // With no memory budget every CSV is uploaded as soon as it is written
+    options.streaming = StreamingOptions {
+        memory_budget: 0,
+        row_group_size: 1,
+    };
+    let report = load_delta(&options).await.unwrap();
+    assert_eq!(report.summary.rows, 4);
+
+    // All files were added by the single load commit
+    let loaded = deltalake::open_table_with_version(table_path.to_str().unwrap(), 1)
+        .await
+        .unwrap();
+    let files: Vec<String> = loaded.get_file_uris().unwrap().collect();
+    assert_eq!(files.len(), 3);
+
+    let row_groups: Vec<usize> = files
+        .iter()
+        .map(|file| {
+            let reader = SerializedFileReader::new(std::fs::File::open(file).unwrap()).unwrap();
+            reader.metadata().num_row_groups()
+        })
+        .collect();
+    assert_eq!(row_groups.iter().sum::<usize>(), 4);
+}
+
+#[tokio::test]
+async fn test_report_writer_streams_reports_into_one_commit() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let table_path = temp_dir.path().join("st1");
+    let mut table = create_or_open_delta_table(&table_path, DeltaReportType::St1, Partitioning::Year, true)
+        .await
+        .unwrap();
+    let streaming = StreamingOptions {
+        memory_budget: 0,
+        row_group_size: 1024,
+    };
+    let mut writer =
+        ReportWriter::for_table(&table, DeltaReportType::St1, LoadMode::Append, streaming).unwrap();
+
+    let report = |date: &str, licences: &[&str]| {
+        let records: Vec<License> = licences.iter().map(|l| licence(date, l)).collect();
+        let source = SourceFile {
+            file_name: format!("WELLS{}.TXT", &date[5..].replace('-', "")),
+            sha256: format!("{date}-hash"),
+        };
+        let lines = (0..records.len()).map(|i| Some(10 + i as u32)).collect();
+        (License::to_record_batch(&records).unwrap(), source, lines)
+    };
+    let (batch, source, lines) = report("2024-12-31", &["0516001", "0516002"]);
+    writer.write(batch, source, lines).await.unwrap();
+    // A report breaking the constraints is rejected without spoiling the others
+    let (batch, source, lines) = report("2025-01-01", &["0516500", "51"]);
+    let error = writer.write(batch, source, lines).await.unwrap_err();
+    assert!(
+        error.to_string().contains("WELLS0101.TXT line 11: licence_number_format"),
+        "{error}"
+    );
+    let (batch, source, lines) = report("2025-01-02", &["0516990"]);
+    writer.write(batch, source, lines).await.unwrap();
+
+    let summary = writer.commit(&mut table).await.unwrap();
+    assert_eq!(summary.rows, 3);
+    assert_eq!(summary.partitions.len(), 2);
+    assert_eq!(table.version(), Some(1));
+    let hashes = loaded_file_hashes(&table).await.unwrap();
+    assert!(hashes.contains("2024-12-31-hash") && hashes.contains("2025-01-02-hash"));
+    assert!(!hashes.contains("2025-01-01-hash"));
+    let (_, batch) = read_table(&table_path).await;
+    assert_eq!(batch.num_rows(), 3);
+}
+
+#[tokio::test]
+async fn test_report_writer_records_reports_without_rows() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let table_path = temp_dir.path().join("st1");
+    let mut table = create_or_open_delta_table(&table_path, DeltaReportType::St1, Partitioning::Year, false)
+        .await
+        .unwrap();
+
+    for (version, mode) in [(1, LoadMode::Append), (2, LoadMode::Merge)] {
+        let mut writer =
+            ReportWriter::for_table(&table, DeltaReportType::St1, mode, StreamingOptions::default())
+                .unwrap();
+        let source = SourceFile {
+            file_name: "WELLS0104.TXT".to_string(),
+            sha256: format!("empty-{version}-hash"),
+        };
+        let batch = License::to_record_batch(&[]).unwrap();
+        writer.write(batch, source, Vec::new()).await.unwrap();
+
+        let summary = writer.commit(&mut table).await.unwrap();
+        assert_eq!(summary.rows, 0);
+        assert_eq!(summary.source_files.len(), 1);
+        assert_eq!(table.version(), Some(version));
+        let hashes = loaded_file_hashes(&table).await.unwrap();
+        assert!(hashes.contains(&format!("empty-{version}-hash")), "{mode:?}");
+    }
+    assert_eq!(table.get_files_count(), 0);
+
+    // Nothing is committed without rows or source files
+    let writer =
+        ReportWriter::for_table(&table, DeltaReportType::St1, LoadMode::Append, StreamingOptions::default())
+            .unwrap();
+    writer.commit(&mut table).await.unwrap();
+    assert_eq!(table.version(), Some(2));
+}
+
+#[tokio::test]
+async fn test_report_writer_merges_in_groups_within_memory_budget() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let table_path = temp_dir.path().join("st1");
+    let mut table = create_or_open_delta_table(&table_path, DeltaReportType::St1, Partitioning::Year, false)
+        .await
+        .unwrap();
+    // With no memory budget every report is merged in a commit of its own
+    let streaming = StreamingOptions {
+        memory_budget: 0,
+        row_group_size: 1024,
+    };
+    let mut writer =
+        ReportWriter::for_table(&table, DeltaReportType::St1, LoadMode::Merge, streaming).unwrap();
+
+    let mut corrected = licence("2024-12-31", "0516001");
+    corrected.licensee = "CORRECTED ENERGY LTD.".to_string();
+    let reports = [
+        ("first", vec![licence("2024-12-31", "0516001"), licence("2024-12-31", "0516002")]),
+        ("second", vec![licence("2025-01-02", "0516990")]),
+        ("correction", vec![corrected]),
+    ];
+    for (hash, records) in reports {
+        let source = SourceFile {
+            file_name: format!("{hash}.TXT"),
+            sha256: hash.to_string(),
+        };
+        let lines = vec![None; records.len()];
+        writer
+            .write(License::to_record_batch(&records).unwrap(), source, lines)
+            .await
+            .unwrap();
+    }
+
+    let summary = writer.commit(&mut table).await.unwrap();
+    assert_eq!((summary.inserted, summary.updated), (3, 1));
+    assert_eq!(summary.source_files.len(), 3);
+    assert_eq!(table.version(), Some(3));
+    let hashes = loaded_file_hashes(&table).await.unwrap();
+    assert!(["first", "second", "correction"]
+        .iter()
+        .all(|hash| hashes.contains(*hash)));
+
+    // The later group's row wins, as in a single merge
+    let (_, batch) = read_table(&table_path).await;
+    assert_eq!(batch.num_rows(), 3);
+    let licence_numbers = batch
+        .column_by_name("licence_number")
+        .unwrap()
+        .as_string::<i32>();
+    let licensees = batch.column_by_name("licensee").unwrap().as_string::<i32>();
+    let row = (0..batch.num_rows())
+        .find(|&row| licence_numbers.value(row) == "0516001")
+        .unwrap();
+    assert_eq!(licensees.value(row), "CORRECTED ENERGY LTD.");
+}
+
+#[tokio::test]
+async fn test_reload_streams_csvs_into_one_commit_within_memory_budget() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let table_path = temp_dir.path().join("st1");
+    let csv_dir = temp_dir.path().join("csv");
+    write_st1_csvs(
+        &csv_dir,
+        &[
+            ("2025-01-02", &["0516990"]),
+            ("2025-01-03", &["0517000", "0517001"]),
+        ],
+    );
+    load_delta_merge(&csv_dir, &table_path);
+    let (table, _) = read_table(&table_path).await;
+    let loaded_version = table.version().unwrap();
+
+    write_st1_csvs(&csv_dir, &[("2025-01-03", &["0517000"])]);
+    let mut options = ReloadOptions::new(
+        DeltaReportType::St1,
+        &table_path,
+        NaiveDate::from_ymd_opt(2025, 1, 2).unwrap(),
+        NaiveDate::from_ymd_opt(2025, 1, 3).unwrap(),
+    );
+    options.csv_folder = Some(csv_dir);
+    options.streaming = StreamingOptions {
+        memory_budget: 0,
+        row_group_size: 1,
+    };
+    let report = reload_delta(&options).await.unwrap();
+    assert_eq!((report.summary.deleted, report.summary.rows), (3, 2));
+    assert_eq!(report.summary.partitions.len(), 1);
+
+    let reloaded = deltalake::open_table_with_version(
+        table_path.to_str().unwrap(),
+        loaded_version + 1,
+    )
+    .await
+    .unwrap();
+    let row_groups: usize = reloaded
+        .get_file_uris()
+        .unwrap()
+        .map(|file| {
+            let reader = SerializedFileReader::new(std::fs::File::open(file).unwrap()).unwrap();
+            reader.metadata().num_row_groups()
+        })
+        .sum();
+    assert_eq!(row_groups, 2);
+    assert_eq!(operations(&table_path).await[loaded_version as usize + 1], "WRITE");
+    let (_, batch) = read_table(&table_path).await;
+    assert_eq!(batch.num_rows(), 2);
+}
+
+fn run_cli_stdout(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_aer_parser"))
+        .args(args)
+        .output()
+        .expect("failed to run aer_parser");
+    assert!(output.status.success(), "aer_parser {args:?} failed");
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Operation of every commit in the table history, oldest first.
+async fn operations(table_path: &Path) -> Vec<String> {
+    let table = deltalake::open_table(table_path.to_str().unwrap())
+        .await
+        .unwrap();
+    let mut history = table.history(None).await.unwrap();
+    history.reverse();
+    history
+        .into_iter()
+        .map(|commit| commit.operation.unwrap_or_default())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_load_delta_skip_maintenance_and_maintain_command() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let table_path = temp_dir.path().join("st1");
+    let table = table_path.to_str().unwrap();
+    for (dir, date, licence) in [
+        ("first", "2025-01-02", "0516990"),
+        ("second", "2025-01-03", "0517000"),
+    ] {
+        let csv_dir = temp_dir.path().join(dir);
+        write_st1_csvs(&csv_dir, &[(date, &[licence])]);
+        run_cli(&[
+            "load-delta",
+            "--report-type",
+            "st1",
+            "--csv-folder",
+            csv_dir.to_str().unwrap(),
+            "--table-path",
+            table,
+            "--skip-maintenance",
+        ]);
+    }
+    assert_eq!(operations(&table_path).await, ["CREATE TABLE", "WRITE", "WRITE"]);
+
+    run_cli(&[
+        "maintain",
+        "--table-path",
+        table,
+        "--z-order",
+        "licence_number,licensee",
+        "--skip-vacuum",
+    ]);
+    let (table_state, batch) = read_table(&table_path).await;
+    assert_eq!(batch.num_rows(), 2);
+    assert_eq!(table_state.get_files_count(), 1);
+    assert_eq!(operations(&table_path).await.last().unwrap(), "OPTIMIZE");
+
+    // Z-ordering by a column the table does not have is refused
+    let status = Command::new(env!("CARGO_BIN_EXE_aer_parser"))
+        .args(["maintain", "--table-path", table, "--z-order", "licence"])
+        .status()
+        .unwrap();
+    assert!(!status.success());
+
+    // Let vacuum remove the two files replaced by the Z-order
+    DeltaOps(table_state)
+        .set_tbl_properties()
+        .with_properties(
+            [(
+                "delta.deletedFileRetentionDuration".to_string(),
+                "interval 0 hours".to_string(),
+            )]
+            .into(),
+        )
+        .await
+        .unwrap();
+    let data_files = || {
+        std::fs::read_dir(&table_path)
+            .unwrap()
+            .filter(|entry| {
+                entry
+                    .as_ref()
+                    .unwrap()
+                    .path()
+                    .extension()
+                    .is_some_and(|ext| ext == "parquet")
+            })
+            .count()
+    };
+    assert_eq!(data_files(), 3);
+
+    let vacuum_args = [
+        "maintain",
+        "--table-path",
+        table,
+        "--skip-optimize",
+        "--vacuum-retention-hours",
+        "0",
+    ];
+    let stdout = run_cli_stdout(&[&vacuum_args[..], &["--vacuum-dry-run"]].concat());
+    assert!(stdout.starts_with("Vacuum would delete 2 files:"));
+    assert_eq!(stdout.matches(".parquet").count(), 2);
+    assert_eq!(data_files(), 3);
+
+    run_cli(&vacuum_args);
+    assert_eq!(data_files(), 1);
+    let (_, batch) = read_table(&table_path).await;
+    assert_eq!(batch.num_rows(), 2);
+}
+
+#[tokio::test]
+async fn test_load_delta_maintains_licence_history() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let history_path = temp_dir.path().join("licence_history");
+    let mut options = LoadDeltaOptions::new(DeltaReportType::St1, temp_dir.path().join("st1"));
+    options.licence_history = Some(history_path.clone());
+
+    let first = temp_dir.path().join("first");
+    write_st1_csvs(&first, &[("2025-01-02", &["0516990", "0516991"])]);
+    options.csv_folder = Some(first);
+    let report = load_delta(&options).await.unwrap();
+    assert_eq!(report.licence_history.unwrap().inserted, 2);
+
+    // 0516990 reappears amended, 0516991 reappears unchanged and 0517000 is new
+    let second = temp_dir.path().join("second");
+    std::fs::create_dir_all(&second).unwrap();
+    let mut amended = licence("2025-01-06", "0516990");
+    amended.licensee = "AMENDED ENERGY LTD.".to_string();
+    let records = [
+        amended,
+        licence("2025-01-06", "0516991"),
+        licence("2025-01-06", "0517000"),
+    ];
+    let report_date = NaiveDate::from_ymd_opt(2025, 1, 6).unwrap();
+    write_csv_records(&records, &second, "WELLS", report_date).unwrap();
+    options.csv_folder = Some(second);
+    let report = load_delta(&options).await.unwrap();
+    let summary = report.licence_history.unwrap();
+    assert_eq!((summary.inserted, summary.updated, summary.deleted), (2, 1, 0));
+
+    let (history, batch) = read_table(&history_path).await;
+    assert_eq!(batch.num_rows(), 4);
+    let config = history.metadata().unwrap().configuration().clone();
+    assert_eq!(
+        config.get("delta.enableChangeDataFeed").cloned(),
+        Some("true".to_string())
+    );
+    // The update was recorded in the change data feed
+    assert!(history_path.join("_change_data").exists());
+
+    let licence_numbers = batch
+        .column_by_name("licence_number")
+        .unwrap()
+        .as_string::<i32>();
+    let licensees = batch.column_by_name("licensee").unwrap().as_string::<i32>();
+    let valid_from = batch
+        .column_by_name("valid_from")
+        .unwrap()
+        .as_primitive::<Date32Type>();
+    let valid_to = batch
+        .column_by_name("valid_to")
+        .unwrap()
+        .as_primitive::<Date32Type>();
+    let mut versions: Vec<(String, String, Option<NaiveDate>, Option<NaiveDate>)> = (0
+        ..batch.num_rows())
+        .map(|row| {
+            (
+                licence_numbers.value(row).to_string(),
+                licensees.value(row).to_string(),
+                valid_from.value_as_date(row),
+                valid_to.value_as_date(row).filter(|_| valid_to.is_valid(row)),
+            )
+        })
+        .collect();
+    versions.sort();
+    let day = |d| NaiveDate::from_ymd_opt(2025, 1, d);
+    let original = "TORXEN ENERGY LTD.".to_string();
+    assert_eq!(
+        versions,
+        vec![
+            ("0516990".to_string(), "AMENDED ENERGY LTD.".to_string(), day(6), None),
+            ("0516990".to_string(), original.clone(), day(2), day(6)),
+            ("0516991".to_string(), original.clone(), day(2), None),
+            ("0517000".to_string(), original, day(6), None),
+        ]
+    );
+
+    // Nothing new to derive, so nothing is committed
+    let version = history.version();
+    let report = load_delta(&options).await.unwrap();
+    assert_eq!(report.licence_history.unwrap(), Default::default());
+    let (history, _) = read_table(&history_path).await;
+    assert_eq!(history.version(), version);
+}
+
+#[tokio::test]
+async fn test_export_reads_past_versions_with_filters() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let table_path = temp_dir.path().join("st1");
+    let table = table_path.to_str().unwrap();
+    write_st1_csvs(
+        &temp_dir.path().join("first"),
+        &[("2024-12-31", &["0516001", "0516002"])],
+    );
+    load_delta_merge(&temp_dir.path().join("first"), &table_path);
+    let (first, _) = read_table(&table_path).await;
+    let first_version = first.version().unwrap().to_string();
+    write_st1_csvs(
+        &temp_dir.path().join("second"),
+        &[("2025-01-02", &["0516990"]), ("2025-01-03", &["0516991"])],
+    );
+    load_delta_merge(&temp_dir.path().join("second"), &table_path);
+
+    let export = |name: &str, extra: &[&str]| {
+        let output = temp_dir.path().join("exports").join(name);
+        let mut args = vec!["export", "--table-path", table, "--output"];
+        args.push(output.to_str().unwrap());
+        args.extend_from_slice(extra);
+        run_cli(&args);
+        output
+    };
+    let csv_lines = |path: &Path| -> Vec<String> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    };
+
+    // The version before the second load only has the first day
+    let past = export("past.csv", &["--as-of-version", &first_version]);
+    let lines = csv_lines(&past);
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("date|"));
+    // A time before the table existed is refused
+    let status = Command::new(env!("CARGO_BIN_EXE_aer_parser"))
+        .args(["export", "--table-path", table, "--output"])
+        .arg(temp_dir.path().join("never.csv"))
+        .args(["--as-of", "2000-01-01"])
+        .status()
+        .unwrap();
+    assert!(!status.success());
+
+    let filtered = export(
+        "filtered.csv",
+        &[
+            "--start-date",
+            "2025-01-01",
+            "--end-date",
+            "2025-01-02",
+            "--columns",
+            "licence_number,licensee",
+        ],
+    );
+    assert_eq!(
+        csv_lines(&filtered),
+        vec!["licence_number|licensee", "0516990|TORXEN ENERGY LTD."]
+    );
+
+    let json = export("latest.json", &["--format", "json"]);
+    assert_eq!(csv_lines(&json).len(), 4);
+
+    let parquet = export("latest.parquet", &["--format", "parquet"]);
+    let reader = SerializedFileReader::new(std::fs::File::open(parquet).unwrap()).unwrap();
+    assert_eq!(reader.metadata().file_metadata().num_rows(), 4);
+}
+
+#[tokio::test]
+async fn test_reload_replaces_date_range_in_one_commit() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let table_path = temp_dir.path().join("st1");
+    let csv_dir = temp_dir.path().join("csv");
+    write_st1_csvs(
+        &csv_dir,
+        &[
+            ("2025-01-02", &["0516990", "0516991"]),
+            ("2025-01-03", &["0517000"]),
+        ],
+    );
+    load_delta_merge(&csv_dir, &table_path);
+    let (table, _) = read_table(&table_path).await;
+    let loaded_version = table.version().unwrap();
+
+    // The AER republishes 2025-01-02 with 0516991 withdrawn and 0516990 corrected
+    let mut corrected = licence("2025-01-02", "0516990");
+    corrected.licensee = "CORRECTED ENERGY LTD.".to_string();
+    let report_date = NaiveDate::from_ymd_opt(2025, 1, 2).unwrap();
+    write_csv_records(&[corrected], &csv_dir, "WELLS", report_date).unwrap();
+    run_cli(&[
+        "reload",
+        "--report-type",
+        "st1",
+        "--start-date",
+        "2025-01-02",
+        "--end-date",
+        "2025-01-02",
+        "--csv-folder",
+        csv_dir.to_str().unwrap(),
+        "--table-path",
+        table_path.to_str().unwrap(),
+        "--skip-maintenance",
+    ]);
+
+    let (table, batch) = read_table(&table_path).await;
+    assert_eq!(table.version(), Some(loaded_version + 1));
+    assert_eq!(operations(&table_path).await.last().unwrap(), "WRITE");
+    let licence_numbers = batch
+        .column_by_name("licence_number")
+        .unwrap()
+        .as_string::<i32>();
+    let licensees = batch.column_by_name("licensee").unwrap().as_string::<i32>();
+    let mut rows: Vec<(&str, &str)> = (0..batch.num_rows())
+        .map(|row| (licence_numbers.value(row), licensees.value(row)))
+        .collect();
+    rows.sort();
+    assert_eq!(
+        rows,
+        vec![
+            ("0516990", "CORRECTED ENERGY LTD."),
+            ("0517000", "TORXEN ENERGY LTD."),
+        ]
+    );
+
+    // The load log lists each CSV once
+    let log = std::fs::read_to_string(table_path.join("delta_load_log.json")).unwrap();
+    assert_eq!(log.lines().count(), 2);
+    assert_eq!(log.matches("20250102_WELLS.csv").count(), 1);
+
+    // Rows dated outside the range are refused and nothing is committed
+    let status = Command::new(env!("CARGO_BIN_EXE_aer_parser"))
+        .args(["reload", "--report-type", "st1"])
+        .args(["--start-date", "2025-01-03", "--end-date", "2025-01-03"])
+        .arg("--csv-path")
+        .arg(csv_dir.join("20250102_WELLS.csv"))
+        .arg("--table-path")
+        .arg(&table_path)
+        .status()
+        .unwrap();
+    assert!(!status.success());
+    let (table, _) = read_table(&table_path).await;
+    assert_eq!(table.version(), Some(loaded_version + 1));
+}
+
+#[tokio::test]
+async fn test_restore_rolls_back_and_reconciles_load_log() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let table_path = temp_dir.path().join("st1");
+    let csv_dir = temp_dir.path().join("csv");
+    write_st1_csvs(&csv_dir, &[("2025-01-02", &["0516990", "0516991"])]);
+    load_delta_merge(&csv_dir, &table_path);
+    let (good, _) = read_table(&table_path).await;
+    let good_version = good.version().unwrap().to_string();
+
+    // A bad load we want to undo
+    write_st1_csvs(&csv_dir, &[("2025-01-03", &["0517000"])]);
+    load_delta_merge(&csv_dir, &table_path);
+    let log_path = table_path.join("delta_load_log.json");
+    assert_eq!(std::fs::read_to_string(&log_path).unwrap().lines().count(), 2);
+
+    let stdout = run_cli_stdout(&[
+        "restore",
+        "--table-path",
+        table_path.to_str().unwrap(),
+        "--to-version",
+        &good_version,
+    ]);
+    assert!(stdout.contains("Rolled back 20250103_WELLS.csv"));
+    assert_eq!(operations(&table_path).await.last().unwrap(), "RESTORE");
+    let (_, batch) = read_table(&table_path).await;
+    assert_eq!(batch.num_rows(), 2);
+    let log = std::fs::read_to_string(&log_path).unwrap();
+    assert_eq!(log.lines().count(), 1);
+    assert!(log.contains("20250102_WELLS.csv"));
+
+    // The rolled back file is loaded again, the other one is still skipped
+    load_delta_merge(&csv_dir, &table_path);
+    let (_, batch) = read_table(&table_path).await;
+    assert_eq!(batch.num_rows(), 3);
+    assert_eq!(std::fs::read_to_string(&log_path).unwrap().lines().count(), 2);
+}
+
+#[tokio::test]
+async fn test_reconcile_reports_missing_and_extra_rows_by_date() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let table_path = temp_dir.path().join("st1");
+    let csv_dir = temp_dir.path().join("csv");
+    write_st1_csvs(
+        &csv_dir,
+        &[
+            ("2025-01-02", &["0516990", "0516991"]),
+            ("2025-01-03", &["0517000"]),
+        ],
+    );
+    load_delta_merge(&csv_dir, &table_path);
+    let reconcile = || {
+        Command::new(env!("CARGO_BIN_EXE_aer_parser"))
+            .args(["reconcile", "--report-type", "st1", "--csv-folder"])
+            .arg(&csv_dir)
+            .arg("--table-path")
+            .arg(&table_path)
+            .output()
+            .unwrap()
+    };
+
+    let output = reconcile();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "date|missing|extra\n");
+
+    // 0516991 is withdrawn from one day's CSV and another day was never loaded
+    write_st1_csvs(
+        &csv_dir,
+        &[("2025-01-02", &["0516990"]), ("2025-01-06", &["0517001", "0517002"])],
+    );
+    let output = reconcile();
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "date|missing|extra\n2025-01-02|0|1\n2025-01-06|2|0\n"
+    );
+
+    // A CSV that cannot be read is reported on its own, not as compared
+    let broken = csv_dir.join("20250103_WELLS.csv");
+    std::fs::write(&broken, "not|a|parser|csv\n1|2|3|4\n").unwrap();
+    let report = reconcile_delta(DeltaReportType::St1, &csv_dir, &table_path)
+        .await
+        .unwrap();
+    assert!(!report.is_match());
+    assert!(!report.csv_files.contains(&broken));
+    assert_eq!(report.csv_files.len(), 2);
+    assert_eq!(report.unreadable_files.len(), 1);
+    assert_eq!(report.unreadable_files[0].0, broken);
+    let output = reconcile();
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("20250103_WELLS.csv\", not compared")
+    );
+}
+
+#[tokio::test]
+async fn test_new_tables_enforce_constraints_naming_source_rows() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let table_path = temp_dir.path().join("st1");
+    let table = create_or_open_delta_table(&table_path, DeltaReportType::St1, Partitioning::Year, false)
+        .await
+        .unwrap();
+    let schema = table.schema().unwrap();
+    assert!(!schema.field("date").unwrap().is_nullable());
+    assert!(!schema.field("licence_number").unwrap().is_nullable());
+    assert!(schema.field("licensee").unwrap().is_nullable());
+    let config = table.metadata().unwrap().configuration().clone();
+    assert_eq!(
+        config.get("delta.constraints.licence_number_format").cloned(),
+        Some("regexp_like(licence_number, '^[0-9]{7}$')".to_string())
+    );
+    assert!(config.contains_key("delta.constraints.date_range"));
+    assert!(config.contains_key("delta.constraints.projected_depth_positive"));
+
+    // Delta itself rejects rows breaking a CHECK constraint
+    let batch = License::to_record_batch(&[licence("2025-01-02", "51699")]).unwrap();
+    let year: ArrayRef = Arc::new(Int32Array::from(vec![2025]));
+    let mut columns = batch.columns().to_vec();
+    columns.push(year);
+    let schema = table.snapshot().unwrap().arrow_schema().unwrap();
+    let batch = RecordBatch::try_new(schema, columns).unwrap();
+    let error = DeltaOps(table).write(vec![batch]).await.unwrap_err();
+    assert!(error.to_string().contains("violated"), "{error}");
+
+    // Loads name the file and line of every offending row
+    let csv_dir = temp_dir.path().join("csv");
+    std::fs::create_dir_all(&csv_dir).unwrap();
+    let mut shallow = licence("2025-01-02", "0516992");
+    shallow.projected_depth = "0.0M".to_string();
+    let records = [
+        licence("2025-01-02", "0516990"),
+        licence("2025-01-02", "51699"),
+        shallow,
+    ];
+    let report_date = NaiveDate::from_ymd_opt(2025, 1, 2).unwrap();
+    write_csv_records(&records, &csv_dir, "WELLS", report_date).unwrap();
+    let mut options = ReloadOptions::new(DeltaReportType::St1, &table_path, report_date, report_date);
+    options.csv_folder = Some(csv_dir);
+    let error = reload_delta(&options).await.unwrap_err().to_string();
+    assert!(
+        error.contains("20250102_WELLS.csv line 3: licence_number_format"),
+        "{error}"
+    );
+    assert!(
+        error.contains("20250102_WELLS.csv line 4: projected_depth_positive"),
+        "{error}"
+    );
+    let mut table = deltalake::open_table(table_path.to_str().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(table.version(), Some(0));
+
+    // Record batches written through the library are checked as well
+    let batch = License::to_record_batch(&records).unwrap();
+    for mode in [LoadMode::Append, LoadMode::Merge] {
+        let error = write_records(&mut table, DeltaReportType::St1, vec![batch.clone()], mode, &[])
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("batch 1 record 2: licence_number_format"),
+            "{error}"
+        );
+    }
+    assert_eq!(table.version(), Some(0));
+}
+
+#[tokio::test]
+async fn test_rows_are_checked_against_constraints_the_table_declares() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let table_path = temp_dir.path().join("st1");
+    let table = create_or_open_delta_table(&table_path, DeltaReportType::St1, Partitioning::None, false)
+        .await
+        .unwrap();
+    // Like a table created before the constraint existed
+    let mut table = DeltaOps(table)
+        .drop_constraints()
+        .with_constraint("projected_depth_positive")
+        .await
+        .unwrap();
+    assert_eq!(
+        TableConstraints::for_table(&table).unwrap().not_null,
+        vec!["date".to_string(), "licence_number".to_string()]
+    );
+
+    let mut shallow = licence("2025-01-02", "0516992");
+    shallow.projected_depth = "0.0M".to_string();
+    let batch = License::to_record_batch(&[shallow]).unwrap();
+    let summary = write_records(&mut table, DeltaReportType::St1, vec![batch], LoadMode::Append, &[])
+        .await
+        .unwrap();
+    assert_eq!(summary.rows, 1);
+
+    // The constraints the table still declares are enforced
+    let batch = License::to_record_batch(&[licence("2025-01-02", "51699")]).unwrap();
+    let error = write_records(&mut table, DeltaReportType::St1, vec![batch], LoadMode::Merge, &[])
+        .await
+        .unwrap_err()
+        .to_string();
+    assert!(
+        error.contains("batch 1 record 1: licence_number_format"),
+        "{error}"
+    );
+}
+
+/// Loads, logs, exports and stages files under `root`, a local directory URI
+/// or a bucket, then removes everything written there.
+async fn object_store_round_trip(root: &str, csv_dir: &Path) {
+    let table_uri = storage::join(root, "st1");
+    let mut options = LoadDeltaOptions::new(DeltaReportType::St1, &table_uri);
+    options.csv_folder = Some(csv_dir.to_path_buf());
+
+    let report = load_delta(&options).await.unwrap();
+    assert_eq!(report.summary.rows, 3);
+    assert!(storage::delta_table_exists(&table_uri).await.unwrap());
+    let log_path = options.log_path();
+    let log = read_load_log(&log_path).await.unwrap();
+    assert_eq!(log.len(), 2);
+    let report = load_delta(&options).await.unwrap();
+    assert_eq!(report.skipped_files.len(), 2);
+
+    let output = storage::join(root, "exports/st1.csv");
+    let export = ExportOptions::new(&table_uri, &output, ExportFormat::Csv);
+    let summary = export_delta_table(&export).await.unwrap();
+    assert_eq!(summary.rows, 3);
+    let exported = storage::read(&output).await.unwrap().unwrap();
+    assert_eq!(String::from_utf8(exported).unwrap().lines().count(), 4);
+
+    let csv_output = storage::join(root, "csv");
+    let staged = StagedDir::new(&csv_output).unwrap();
+    std::fs::copy(
+        csv_dir.join("20241231_WELLS.csv"),
+        Path::new(staged.path()).join("20241231_WELLS.csv"),
+    )
+    .unwrap();
+    let staged_file = storage::join(&csv_output, "20241231_WELLS.csv");
+    assert!(!storage::exists(&staged_file).await.unwrap());
+    assert_eq!(staged.publish().await.unwrap(), 1);
+    assert!(storage::exists(&staged_file).await.unwrap());
+
+    // TXT reports are read from the location, for processing and for queries
+    let txt_uri = storage::join(root, "txt");
+    let local_txt = csv_dir.parent().unwrap().join("upload/WELLS01022025.TXT");
+    write_wells_report(&local_txt, "02 January 2025", &[("0516990", "ALBERTA CROWN")]);
+    storage::write(
+        &storage::join(&txt_uri, "WELLS01022025.TXT"),
+        std::fs::read(&local_txt).unwrap(),
+    )
+    .await
+    .unwrap();
+    let processed_dir = csv_dir.parent().unwrap().join("processed");
+    std::fs::create_dir_all(&processed_dir).unwrap();
+    aer_st1::process_folder(
+        aer_st1::ReportType::St1,
+        &txt_uri,
+        processed_dir.to_str().unwrap(),
+    )
+    .await
+    .unwrap();
+    assert!(processed_dir.join("20250102_WELLS.csv").exists());
+    let ctx = datafusion::prelude::SessionContext::new();
+    aer_st1::delta::register_raw_report_functions(&ctx);
+    let batches = ctx
+        .sql(&format!("SELECT licence_number FROM st1_raw('{txt_uri}')"))
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+    assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 1);
+
+    assert!(storage::remove_all(root).await.unwrap());
+    let first_commit = storage::join(&table_uri, "_delta_log/00000000000000000000.json");
+    assert_eq!(storage::read(&first_commit).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn test_object_store_uris_for_tables_logs_and_outputs() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let csv_dir = temp_dir.path().join("csv");
+    write_st1_csvs(
+        &csv_dir,
+        &[
+            ("2024-12-31", &["0516001", "0516002"]),
+            ("2025-01-02", &["0516990"]),
+        ],
+    );
+    // `file://` URIs go through the object store code path
+    let root = format!("file://{}", temp_dir.path().join("lake").display());
+    assert!(storage::is_object_store_uri(&root));
+    assert!(!storage::is_object_store_uri(temp_dir.path().to_str().unwrap()));
+    // Only schemes with a registered object store are URIs
+    assert!(!storage::is_object_store_uri("data:2024/st1"));
+
+    object_store_round_trip(&root, &csv_dir).await;
+}
+
+/// Runs against S3-compatible storage when `AER_TEST_S3_URI` is set, e.g. to
+/// `s3://aer-test/lake` with MinIO:
+///
+/// ```text
+/// docker run -d -p 9000:9000 minio/minio server /data
+/// AWS_ENDPOINT_URL=http://localhost:9000 AWS_ALLOW_HTTP=true AWS_REGION=us-east-1 \
+///   AWS_ACCESS_KEY_ID=minioadmin AWS_SECRET_ACCESS_KEY=minioadmin \
+///   AWS_S3_ALLOW_UNSAFE_RENAME=true AER_TEST_S3_URI=s3://aer-test/lake cargo test s3
+/// ```
+#[tokio::test]
+async fn test_s3_tables_logs_and_outputs() {
+    let Ok(root) = std::env::var("AER_TEST_S3_URI") else {
+        eprintln!("AER_TEST_S3_URI is not set; skipping");
+        return;
+    };
+    let temp_dir = tempfile::tempdir().unwrap();
+    let csv_dir = temp_dir.path().join("csv");
+    write_st1_csvs(
+        &csv_dir,
+        &[
+            ("2024-12-31", &["0516001", "0516002"]),
+            ("2025-01-02", &["0516990"]),
+        ],
+    );
+    let run = temp_dir.path().file_name().unwrap().to_str().unwrap();
+    object_store_round_trip(&storage::join(&root, run), &csv_dir).await;
+}
+
+#[tokio::test]
+async fn test_query_runs_sql_over_tables_and_files() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let csv_dir = temp_dir.path().join("csv");
+    write_st1_csvs(
+        &csv_dir,
+        &[
+            ("2024-12-31", &["0516001", "0516002"]),
+            ("2025-01-02", &["0516990"]),
+        ],
+    );
+    let table_path = temp_dir.path().join("st1");
+    load_delta_merge(&csv_dir, &table_path);
+    let parquet = temp_dir.path().join("st1.parquet");
+    let mut options = ExportOptions::new(&table_path, &parquet, ExportFormat::Parquet);
+    options.columns = vec!["licence_number".to_string()];
+    export_delta_table(&options).await.unwrap();
+
+    let st1 = table_path.to_str().unwrap();
+    let csv_folder = csv_dir.to_str().unwrap();
+    let query = |format: &str, sql: &str, extra: &[&str]| {
+        let mut args = vec![
+            "query",
+            "--st1-table-path",
+            st1,
+            "--csv-folder",
+            csv_folder,
+            "--format",
+            format,
+        ];
+        args.extend_from_slice(extra);
+        args.push(sql);
+        run_cli_stdout(&args)
+    };
+
+    // Several statements, with the header kept for an empty result
+    let output = query(
+        "csv",
+        "SELECT count(*) AS n FROM st1; SELECT licence_number FROM st1 WHERE false",
+        &[],
+    );
+    assert_eq!(output, "n\n3\n\nlicence_number\n");
+
+    // The parser CSVs are typed like the table, so they compare row for row
+    let output = query(
+        "csv",
+        "SELECT count(*) AS n FROM (SELECT * FROM st1_csv EXCEPT SELECT date, well_name, \
+         licence_number, mineral_rights, ground_elevation, unique_identifier, \
+         surface_coordinates, aer_field_centre, projected_depth, aer_classification, field, \
+         terminating_zone, drilling_operation, well_purpose, well_type, substance, licensee, \
+         surface_location FROM st1)",
+        &[],
+    );
+    assert_eq!(output, "n\n0\n");
+
+    // They are read when scanned, only the CSVs named for the filtered dates
+    let output = query(
+        "csv",
+        "EXPLAIN SELECT licence_number FROM st1_csv WHERE date = '2025-01-02'",
+        &[],
+    );
+    assert!(
+        output.contains("ReportCsvExec: report_type=St1, files=[20250102_WELLS]"),
+        "{output}"
+    );
+
+    // Other CSV and Parquet files, with CSV columns kept as text
+    let wells = csv_dir.join("20250102_WELLS.csv");
+    let wells_table = format!("wells={}", wells.display());
+    let parquet_table = format!("exported={}", parquet.display());
+    let output = query(
+        "json",
+        "SELECT w.licence_number, w.ground_elevation FROM wells w \
+         JOIN exported e ON w.licence_number = e.licence_number",
+        &["--csv", &wells_table, "--parquet", &parquet_table],
+    );
+    assert_eq!(
+        output,
+        "{\"licence_number\":\"0516990\",\"ground_elevation\":\"931.70M\"}\n"
+    );
+
+    let output = query("table", "SELECT licensee, count(*) AS n FROM st1 GROUP BY licensee", &[]);
+    assert!(output.contains("| TORXEN ENERGY LTD. | 3 |"), "{output}");
+
+    // Queries cannot change the tables
+    let status = Command::new(env!("CARGO_BIN_EXE_aer_parser"))
+        .args(["query", "--st1-table-path", st1, "DELETE FROM st1"])
+        .status()
+        .unwrap();
+    assert!(!status.success());
+    let (_, batch) = read_table(&table_path).await;
+    assert_eq!(batch.num_rows(), 3);
+}
+
+#[tokio::test]
+async fn test_query_reads_raw_txt_reports_pruning_by_filename_date() {
+    let data = TestData::new().unwrap();
+    data.create_st1_sample("WELLS01022024.TXT", "02 January 2024").unwrap();
+    data.create_st1_sample("WELLS01032024.TXT", "03 January 2024").unwrap();
+    // Without a year in the name the file is read whatever the filter
+    data.create_st1_sample("WELLS0104.TXT", "04 January 2024").unwrap();
+    data.create_malformed_st1("WELLS01052024.TXT").unwrap();
+    let txt_dir = data.st1_valid.to_str().unwrap();
+    let query = |sql: &str| run_cli_stdout(&["query", "--format", "csv", &sql.replace("{dir}", txt_dir)]);
+
+    // Unparseable reports are skipped
+    let output = query(
+        "SELECT mineral_rights, count(*) AS n, min(date) AS first FROM st1_raw('{dir}') \
+         GROUP BY mineral_rights ORDER BY mineral_rights",
+    );
+    assert_eq!(output, "mineral_rights|n|first\nCROWN|3|2024-01-02\nFREEHOLD|3|2024-01-02\n");
+
+    let output = query(
+        "SELECT date, mineral_rights FROM st1_raw('{dir}') \
+         WHERE date >= DATE '2024-01-03' ORDER BY date, mineral_rights",
+    );
+    assert_eq!(
+        output,
+        "date|mineral_rights\n2024-01-03|CROWN\n2024-01-03|FREEHOLD\n\
+         2024-01-04|CROWN\n2024-01-04|FREEHOLD\n"
+    );
+
+    let output = query("EXPLAIN SELECT mineral_rights FROM st1_raw('{dir}') WHERE date = '2024-01-03'");
+    assert!(output.contains("projection=[date, mineral_rights]"), "{output}");
+    assert!(
+        output.contains("RawReportExec: report_type=St1, files=[WELLS01032024, WELLS0104]"),
+        "{output}"
+    );
+
+    let output = query("SELECT count(*) AS n FROM st49_raw('{dir}')");
+    assert_eq!(output, "n\n0\n");
+}
+
+#[tokio::test]
+async fn test_aer_sql_functions_decode_report_fields() {
+    // Library users register the functions on their own session
+    let ctx = datafusion::prelude::SessionContext::new();
+    register_aer_functions(&ctx);
+    let mut confidential = licence("2025-01-02", "0516991");
+    confidential.aer_classification = "XPL (C)".to_string();
+    confidential.unique_identifier = "00/05-15-050-22W5/0".to_string();
+    confidential.ground_elevation = "n/a".to_string();
+    let records = vec![licence("2025-01-02", "0516990"), confidential];
+    let batch = License::to_record_batch(&records).unwrap();
+    ctx.register_batch("wells", batch).unwrap();
+
+    let batches = ctx
+        .sql(
+            "SELECT uwi_lsd(unique_identifier) AS lsd, uwi_section(unique_identifier) AS sec, \
+             uwi_township(unique_identifier) AS twp, uwi_range(unique_identifier) AS rge, \
+             uwi_meridian(unique_identifier) AS mer, metres(ground_elevation) AS elevation, \
+             classification_code(aer_classification) AS class, \
+             is_confidential(aer_classification) AS confidential \
+             FROM wells ORDER BY licence_number",
+        )
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+    let rows = deltalake::arrow::util::pretty::pretty_format_batches(&batches)
+        .unwrap()
+        .to_string();
+    assert!(
+        rows.contains("| 7   | 36  | 26  | 18  | 4   | 931.7     | DEV   | false        |"),
+        "{rows}"
+    );
+    assert!(
+        rows.contains("| 5   | 15  | 50  | 22  | 5   |           | XPL   | true         |"),
+        "{rows}"
+    );
+
+    // The query command registers them too
+    let temp_dir = tempfile::tempdir().unwrap();
+    let csv_dir = temp_dir.path().join("csv");
+    write_st1_csvs(&csv_dir, &[("2025-01-02", &["0516990"])]);
+    let output = run_cli_stdout(&[
+        "query",
+        "--st1-table-path",
+        temp_dir.path().join("st1").to_str().unwrap(),
+        "--csv-folder",
+        csv_dir.to_str().unwrap(),
+        "--format",
+        "csv",
+        "SELECT uwi_meridian(surface_location) AS mer, round(dls_to_lat(surface_location), 1) AS lat, \
+         round(dls_to_lon(surface_location), 1) AS lon, metres(projected_depth) AS depth, \
+         uwi_township('not a uwi') AS bad FROM st1_csv",
+    ]);
+    assert_eq!(output, "mer|lat|lon|depth|bad\n4|51.2|-112.4|3950.0|\n");
+}
+
+fn spud(date: &str, well_id: &str, licence: &str, activity_date: &str) -> SpudData {
+    SpudData {
+        date: date.to_string(),
+        well_id: well_id.to_string(),
+        well_name: format!("WELL {licence}"),
+        licence: licence.to_string(),
+        contractor_ba_id: "A978".to_string(),
+        contractor_name: "Bear Drilling Corp.".to_string(),
+        rig_number: "1".to_string(),
+        activity_date: activity_date.to_string(),
+        field_centre: "Drayton Valley".to_string(),
+        ba_id: "A573".to_string(),
+        licensee: "Tourmaline Oil Corp.".to_string(),
+        new_projected_total_depth: String::new(),
+        activity_type: "Drill To LD".to_string(),
+    }
+}
+
+#[tokio::test]
+async fn test_licence_spud_links_spuds_to_first_licence_listing() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let csv_dir = temp_dir.path().join("csv");
+    write_st1_csvs(
+        &csv_dir,
+        &[
+            ("2024-12-31", &["0516001"]),
+            ("2025-01-02", &["0516001", "0516990"]),
+        ],
+    );
+    let st1_path = temp_dir.path().join("st1");
+    load_delta_merge(&csv_dir, &st1_path);
+
+    let st49_path = temp_dir.path().join("st49");
+    let mut st49 =
+        create_or_open_delta_table(&st49_path, DeltaReportType::St49, Partitioning::None, false)
+            .await
+            .unwrap();
+    let spuds = vec![
+        spud("2025-01-10", "00/05-15-050-22W5/0", "0516001", "10 Jan 2025 08:00:00 AM"),
+        // No activity time, so the report date counts
+        spud("2025-01-10", "00/04-02-052-11W5/0", "0516990", ""),
+        spud("2025-01-10", "00/12-21-059-26W5/0", "0599999", "09 Jan 2025 11:45:00 PM"),
+    ];
+    let batch = SpudData::to_record_batch(&spuds).unwrap();
+    write_records(&mut st49, DeltaReportType::St49, vec![batch], LoadMode::Append, &[])
+        .await
+        .unwrap();
+
+    let licence_spud_path = temp_dir.path().join("licence_spud");
+    let licence_spud = |expected: &str| {
+        let output = run_cli_stdout(&[
+            "licence-spud",
+            "--st1-table-path",
+            st1_path.to_str().unwrap(),
+            "--st49-table-path",
+            st49_path.to_str().unwrap(),
+            "--table-path",
+            licence_spud_path.to_str().unwrap(),
+        ]);
+        assert_eq!(output, format!("{expected}\n"));
+    };
+    let rows = || {
+        run_cli_stdout(&[
+            "query",
+            "--st1-table-path",
+            st1_path.to_str().unwrap(),
+            "--delta",
+            &format!("licence_spud={}", licence_spud_path.display()),
+            "--format",
+            "csv",
+            "SELECT licence, licence_date, days_to_spud, licence_in_st1 FROM licence_spud \
+             ORDER BY licence",
+        ])
+    };
+
+    licence_spud("2 spuds linked, 1 without an ST1 licence (3 rows inserted, 0 updated, 0 deleted)");
+    assert_eq!(
+        rows(),
+        "licence|licence_date|days_to_spud|licence_in_st1\n\
+         0516001|2024-12-31|10|true\n\
+         0516990|2025-01-02|8|true\n\
+         0599999|||false\n"
+    );
+
+    // Loading the missing ST-1 day links the flagged spud
+    write_st1_csvs(&csv_dir, &[("2025-01-03", &["0599999"])]);
+    load_delta_merge(&csv_dir, &st1_path);
+    licence_spud("3 spuds linked, 0 without an ST1 licence (0 rows inserted, 1 updated, 0 deleted)");
+    assert!(rows().ends_with("0599999|2025-01-03|6|true\n"));
+    let versions = operations(&licence_spud_path).await.len();
+
+    licence_spud("3 spuds linked, 0 without an ST1 licence (0 rows inserted, 0 updated, 0 deleted)");
+    assert_eq!(operations(&licence_spud_path).await.len(), versions);
+}
+
+#[tokio::test]
+async fn test_rig_timeline_orders_moves_of_each_rig() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let st49_path = temp_dir.path().join("st49");
+    let mut st49 =
+        create_or_open_delta_table(&st49_path, DeltaReportType::St49, Partitioning::None, false)
+            .await
+            .unwrap();
+    let on_rig = |rig: &str, mut spud: SpudData| {
+        spud.rig_number = rig.to_string();
+        spud
+    };
+    let mut set_surface = spud("2025-01-05", "W2", "0516002", "05 Jan 2025 08:00:00 PM");
+    set_surface.activity_type = "Set Surface".to_string();
+    let spuds = vec![
+        spud("2025-01-01", "W1", "0516001", "01 Jan 2025 08:00:00 AM"),
+        set_surface,
+        spud("2025-01-09", "W3", "0516003", "09 Jan 2025 08:00:00 AM"),
+        on_rig("2", spud("2025-01-02", "W4", "0516004", "02 Jan 2025 12:00:00 PM")),
+        // Without a rig the activity cannot be placed
+        on_rig("", spud("2025-01-03", "W5", "0516005", "03 Jan 2025 12:00:00 PM")),
+    ];
+    let batch = SpudData::to_record_batch(&spuds).unwrap();
+    write_records(&mut st49, DeltaReportType::St49, vec![batch], LoadMode::Append, &[])
+        .await
+        .unwrap();
+
+    let timeline_path = temp_dir.path().join("rig_timeline");
+    let rig_timeline = |extra: &[&str]| {
+        let mut args = vec![
+            "rig-timeline",
+            "--st49-table-path",
+            st49_path.to_str().unwrap(),
+            "--table-path",
+            timeline_path.to_str().unwrap(),
+            "--format",
+            "csv",
+        ];
+        args.extend_from_slice(extra);
+        run_cli_stdout(&args)
+    };
+
+    let output = rig_timeline(&["--contractor", "bear drilling corp.", "--rig", "1"]);
+    let moves: Vec<String> = output
+        .lines()
+        .skip(1)
+        .map(|line| {
+            let fields: Vec<&str> = line.split('|').collect();
+            [3, 5, 9, 11, 12, 13].map(|i| fields[i]).join("|")
+        })
+        .collect();
+    assert_eq!(
+        moves,
+        [
+            "1|W1|Drill To LD|||4.5",
+            "2|W2|Set Surface|W1|4.5|3.5",
+            "3|W3|Drill To LD|W2|3.5|",
+        ]
+    );
+    assert!(output.starts_with(
+        "contractor_ba_id|contractor_name|rig_number|sequence|activity_date|well_id|"
+    ));
+
+    let output = rig_timeline(&["--contractor", "A978"]);
+    assert_eq!(output.lines().count(), 5, "{output}");
+    assert!(output.ends_with("|W4|WELL 0516004|0516004|Tourmaline Oil Corp.|Drill To LD|2025-01-02|||\n"));
+
+    // The rig's next move completes its days on the last well
+    let next_move = spud("2025-01-12", "W6", "0516006", "12 Jan 2025 08:00:00 AM");
+    let batch = SpudData::to_record_batch(&[next_move]).unwrap();
+    write_records(&mut st49, DeltaReportType::St49, vec![batch], LoadMode::Append, &[])
+        .await
+        .unwrap();
+    let output = rig_timeline(&["--rig", "1"]);
+    assert!(
+        output.contains("|W3|WELL 0516003|0516003|Tourmaline Oil Corp.|Drill To LD|2025-01-09|W2|3.5|3.0\n"),
+        "{output}"
+    );
+    assert!(output.ends_with("|W6|WELL 0516006|0516006|Tourmaline Oil Corp.|Drill To LD|2025-01-12|W3|3.0|\n"));
+    // Created, then one merge per update
+    assert_eq!(operations(&timeline_path).await.len(), 3);
+}
+
+#[tokio::test]
+async fn test_companies_registry_resolves_st1_licensees() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let csv_dir = temp_dir.path().join("csv");
+    std::fs::create_dir_all(&csv_dir).unwrap();
+    let licensed = |licence_number: &str, licensee: &str| License {
+        licensee: licensee.to_string(),
+        ..licence("2025-01-02", licence_number)
+    };
+    let licences = vec![
+        licensed("0516001", "TOURMALINE OIL CORP."),
+        licensed("0516002", "TORXEN ENERGY LTD."),
+        licensed("0516003", "ACME ENERGY LIMITED"),
+        licensed("0516004", "OLD PETROLEUM CORP."),
+    ];
+    let report_date = NaiveDate::from_ymd_opt(2025, 1, 2).unwrap();
+    write_csv_records(&licences, &csv_dir, "WELLS", report_date).unwrap();
+    let st1_path = temp_dir.path().join("st1");
+    load_delta_merge(&csv_dir, &st1_path);
+
+    let st49_path = temp_dir.path().join("st49");
+    let mut st49 =
+        create_or_open_delta_table(&st49_path, DeltaReportType::St49, Partitioning::None, false)
+            .await
+            .unwrap();
+    let mut torxen = spud("2025-01-10", "W2", "0516002", "10 Jan 2025 08:00:00 AM");
+    torxen.ba_id = "A111".to_string();
+    torxen.licensee = "Torxen Energy Ltd.".to_string();
+    let spuds = vec![
+        spud("2025-01-10", "W1", "0516001", "10 Jan 2025 06:00:00 AM"),
+        spud("2025-01-11", "W3", "0516005", "11 Jan 2025 06:00:00 AM"),
+        torxen,
+    ];
+    let batch = SpudData::to_record_batch(&spuds).unwrap();
+    write_records(&mut st49, DeltaReportType::St49, vec![batch], LoadMode::Append, &[])
+        .await
+        .unwrap();
+
+    let registry_path = temp_dir.path().join("company_registry");
+    let licensees_path = temp_dir.path().join("st1_licensees");
+    let companies = |aliases: Option<&Path>| {
+        let mut args = vec![
+            "companies",
+            "--st1-table-path",
+            st1_path.to_str().unwrap(),
+            "--st49-table-path",
+            st49_path.to_str().unwrap(),
+            "--registry-path",
+            registry_path.to_str().unwrap(),
+            "--licensees-path",
+            licensees_path.to_str().unwrap(),
+        ];
+        if let Some(aliases) = aliases {
+            args.extend(["--aliases", aliases.to_str().unwrap()]);
+        }
+        run_cli_stdout(&args)
+    };
+    let query = |sql: &str| {
+        run_cli_stdout(&[
+            "query",
+            "--delta",
+            &format!("company_registry={}", registry_path.display()),
+            "--delta",
+            &format!("st1_licensees={}", licensees_path.display()),
+            "--format",
+            "csv",
+            sql,
+        ])
+    };
+
+    assert_eq!(
+        companies(None),
+        "3 companies under 3 names in the registry (3 rows inserted, 0 updated, 0 deleted)\n\
+         2 of 4 ST1 licensees resolved to a BA ID (4 rows inserted, 0 updated, 0 deleted)\n"
+    );
+    assert_eq!(
+        query(
+            "SELECT ba_id, name, first_seen, last_seen, records, as_licensee, as_contractor \
+             FROM company_registry ORDER BY ba_id"
+        ),
+        "ba_id|name|first_seen|last_seen|records|as_licensee|as_contractor\n\
+         A111|Torxen Energy Ltd.|2025-01-10|2025-01-10|1|true|false\n\
+         A573|Tourmaline Oil Corp.|2025-01-10|2025-01-11|2|true|false\n\
+         A978|Bear Drilling Corp.|2025-01-10|2025-01-11|3|false|true\n"
+    );
+
+    // An alias maps the name a company had before its acquisition
+    let aliases_path = temp_dir.path().join("company_aliases.csv");
+    std::fs::write(&aliases_path, "name,ba_id\nOld Petroleum Corporation, A573\n").unwrap();
+    assert_eq!(
+        companies(Some(&aliases_path)),
+        "3 companies under 4 names in the registry (1 rows inserted, 0 updated, 0 deleted)\n\
+         3 of 4 ST1 licensees resolved to a BA ID (0 rows inserted, 1 updated, 0 deleted)\n"
+    );
+    assert_eq!(
+        query(
+            "SELECT licensee, licences, ba_id, ba_name, resolved_by FROM st1_licensees \
+             ORDER BY licensee"
+        ),
+        "licensee|licences|ba_id|ba_name|resolved_by\n\
+         ACME ENERGY LIMITED|1|||\n\
+         OLD PETROLEUM CORP.|1|A573|Old Petroleum Corporation|alias\n\
+         TORXEN ENERGY LTD.|1|A111|Torxen Energy Ltd.|st49\n\
+         TOURMALINE OIL CORP.|1|A573|Tourmaline Oil Corp.|st49\n"
+    );
+}
+
+/// Writes a fixed-width WELLS report listing `(licence, mineral rights)` pairs.
+fn write_wells_report(path: &Path, date: &str, licences: &[(&str, &str)]) {
+    let mut report = format!(
+        "ALBERTA ENERGY REGULATOR\nDAILY WELL LICENCES LIST\nDATE: {date}\n\n\
+         WELL LICENCES ISSUED\n{}\n\
+         WELL NAME                            LICENCE NUMBER  MINERAL RIGHTS GROUND ELEV\n\
+         UNIQUE IDENTIFIER                    SURF COORD AER FIELD CENTRE    PROJECTED DEPTH\n\
+         AER CLASSIFICATION                   FIELD                          TERMINATING ZONE\n\
+         DRILLING OPERATION                   PURPOSE   WELL TYPE            SUBSTANCE\n\
+         LICENSEE                                                            SURFACE LOCATION\n{}\n",
+        "-".repeat(92),
+        "-".repeat(92)
+    );
+    for (licence, mineral_rights) in licences {
+        report += &format!(
+            "{:<37}{licence:<10}{mineral_rights:<21}931.70M\n\
+             {:<37}{:<10}{:<21}3950.0M\n\
+             {:<37}{:<31}BASAL QUARTZ SD\n\
+             {:<37}{:<10}{:<21}CRUDE OIL\n\
+             {:<68}04-30-026-17W4\n",
+            format!("WELL {licence}"),
+            "100/07-36-026-18W4/00",
+            "155.2M",
+            "MEDICINE HAT",
+            "DEV (NC)",
+            "WAYNE-ROSEDALE",
+            "HORIZONTAL",
+            "NEW",
+            "PRODUCTION",
+            "TORXEN ENERGY LTD.",
+        );
+    }
+    report += "END OF WELL LICENCES DAILY LIST\n";
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, report).unwrap();
+}
+
+#[tokio::test]
+async fn test_diff_compares_reports_and_report_dates() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let first = temp_dir.path().join("first/WELLS01022025.TXT");
+    write_wells_report(
+        &first,
+        "02 January 2025",
+        &[("0516001", "FREEHOLD"), ("0516002", "CROWN")],
+    );
+    let republished = temp_dir.path().join("republished/WELLS01022025.TXT");
+    write_wells_report(
+        &republished,
+        "02 January 2025",
+        &[("0516002", "FREEHOLD"), ("0516003", "CROWN")],
+    );
+    let diff = |args: &[&str]| {
+        let mut cli_args = vec!["diff", "--report-type", "st1"];
+        cli_args.extend(args);
+        run_cli_stdout(&cli_args)
+    };
+
+    let (first, republished) = (first.to_str().unwrap(), republished.to_str().unwrap());
+    assert_eq!(
+        diff(&[first, republished]),
+        format!(
+            "{first} -> {republished}\n\
+             The report for 2025-01-02 was republished with different content\n\
+             Added (1):\n  + 0516003  WELL 0516003\n\
+             Removed (1):\n  - 0516001  WELL 0516001\n\
+             Changed (1):\n  ~ 0516002\n      mineral_rights: \"CROWN\" -> \"FREEHOLD\"\n\
+             1 added, 1 removed, 1 changed, 0 unchanged\n"
+        )
+    );
+    assert!(diff(&[first, first]).ends_with("\n0 added, 0 removed, 0 changed, 2 unchanged\n"));
+
+    // Report dates and date ranges are read from the Delta table
+    let csv_dir = temp_dir.path().join("csv");
+    write_st1_csvs(
+        &csv_dir,
+        &[
+            ("2025-01-02", &["0516001", "0516002"]),
+            ("2025-01-03", &["0516002", "0516003"]),
+            ("2025-01-06", &["0516004"]),
+        ],
+    );
+    let st1_path = temp_dir.path().join("st1");
+    load_delta_merge(&csv_dir, &st1_path);
+    let table = ["--st1-table-path", st1_path.to_str().unwrap()];
+    assert_eq!(
+        diff(&[&table[..], &["2025-01-02", "2025-01-03"]].concat()),
+        "2025-01-02 -> 2025-01-03\n\
+         Added (1):\n  + 0516003  WELL 0516003\n\
+         Removed (1):\n  - 0516001  WELL 0516001\n\
+         1 added, 1 removed, 0 changed, 1 unchanged\n"
+    );
+    assert!(diff(&[&table[..], &["2025-01-02..2025-01-03", "2025-01-06"]].concat())
+        .ends_with("1 added, 3 removed, 0 changed, 0 unchanged\n"));
+
+    // A fresh download against the day already loaded tells whether the AER
+    // republished it
+    let output = diff(&[&table[..], &["2025-01-02", first]].concat());
+    assert!(
+        output.contains("The report for 2025-01-02 was republished with different content"),
+        "{output}"
+    );
+}
+
+#[tokio::test]
+async fn test_stats_summarises_licences_and_spuds() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let csv_dir = temp_dir.path().join("csv");
+    std::fs::create_dir_all(&csv_dir).unwrap();
+    let days: [(&str, &[(&str, &str)]); 4] = [
+        ("2025-01-02", &[("0516001", "CRUDE OIL"), ("0516002", "GAS")]),
+        // 0516001 is listed again after an amendment
+        ("2025-01-03", &[("0516001", "CRUDE OIL"), ("0516003", "GAS")]),
+        ("2025-01-06", &[("0516004", "GAS")]),
+        ("2025-02-03", &[("0516005", "WATER")]),
+    ];
+    for (date, licences) in days {
+        let records: Vec<License> = licences
+            .iter()
+            .map(|(licence_number, substance)| License {
+                substance: substance.to_string(),
+                ..licence(date, licence_number)
+            })
+            .collect();
+        let report_date = NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap();
+        write_csv_records(&records, &csv_dir, "WELLS", report_date).unwrap();
+    }
+    let st1_path = temp_dir.path().join("st1");
+    load_delta_merge(&csv_dir, &st1_path);
+    let stats = |args: &[&str]| {
+        let mut cli_args = vec![
+            "stats",
+            "--st1-table-path",
+            st1_path.to_str().unwrap(),
+            "--format",
+            "csv",
+        ];
+        cli_args.extend(args);
+        run_cli_stdout(&cli_args)
+    };
+
+    let per_week = "week|licences\n2024-12-30|3\n2025-01-06|1\n2025-02-03|1\n";
+    assert_eq!(stats(&["licences-per-week"]), per_week);
+    // The parser CSVs give the same summary as the table they were loaded into
+    assert_eq!(
+        stats(&["licences-per-week", "--csv-folder", csv_dir.to_str().unwrap()]),
+        per_week
+    );
+    assert_eq!(
+        stats(&["licences-per-month"]),
+        "month|licences\n2025-01-01|4\n2025-02-01|1\n"
+    );
+    assert_eq!(
+        stats(&["licences-by-substance"]),
+        "substance|licences|percent\nGAS|3|60.0\nCRUDE OIL|1|20.0\nWATER|1|20.0\n"
+    );
+    assert_eq!(
+        stats(&["licences-by-substance", "--start-date", "2025-01-03", "--limit", "1"]),
+        "substance|licences|percent\nGAS|2|50.0\n"
+    );
+
+    let st49_path = temp_dir.path().join("st49");
+    let mut st49 =
+        create_or_open_delta_table(&st49_path, DeltaReportType::St49, Partitioning::None, false)
+            .await
+            .unwrap();
+    let mut other_rig = spud("2025-01-10", "W3", "0516003", "10 Jan 2025 09:00:00 AM");
+    other_rig.contractor_ba_id = "A123".to_string();
+    other_rig.contractor_name = "Precision Drilling".to_string();
+    let mut set_surface = spud("2025-01-11", "W1", "0516001", "11 Jan 2025 08:00:00 AM");
+    set_surface.activity_type = "Set Surface".to_string();
+    let spuds = vec![
+        spud("2025-01-10", "W1", "0516001", "10 Jan 2025 06:00:00 AM"),
+        spud("2025-01-10", "W2", "0516002", "10 Jan 2025 07:00:00 AM"),
+        set_surface,
+        other_rig,
+    ];
+    let batch = SpudData::to_record_batch(&spuds).unwrap();
+    write_records(&mut st49, DeltaReportType::St49, vec![batch], LoadMode::Append, &[])
+        .await
+        .unwrap();
+    let output = run_cli_stdout(&[
+        "stats",
+        "spuds-by-contractor",
+        "--st49-table-path",
+        st49_path.to_str().unwrap(),
+        "--format",
+        "json",
+    ]);
+    assert_eq!(
+        output,
+        "{\"contractor_ba_id\":\"A978\",\"contractor_name\":\"Bear Drilling Corp.\",\
+         \"spuds\":2,\"percent\":66.7}\n\
+         {\"contractor_ba_id\":\"A123\",\"contractor_name\":\"Precision Drilling\",\
+         \"spuds\":1,\"percent\":33.3}\n"
+    );
+}
+
+#[tokio::test]
+async fn test_serve_answers_paginated_json_requests() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let st1_path = temp_dir.path().join("st1");
+    let mut st1 =
+        create_or_open_delta_table(&st1_path, DeltaReportType::St1, Partitioning::None, false)
+            .await
+            .unwrap();
+    let licences = vec![
+        licence("2025-01-02", "0516001"),
+        licence("2025-01-02", "0516002"),
+        // 0516001 is listed again after an amendment
+        licence("2025-01-03", "0516001"),
+        License {
+            licensee: "Tourmaline Oil Corporation".to_string(),
+            unique_identifier: "100/01-01-050-05W5/00".to_string(),
+            ..licence("2025-01-03", "0516003")
+        },
+    ];
+    let batch = License::to_record_batch(&licences).unwrap();
+    write_records(&mut st1, DeltaReportType::St1, vec![batch], LoadMode::Append, &[])
+        .await
+        .unwrap();
+    let st49_path = temp_dir.path().join("st49");
+    let mut st49 =
+        create_or_open_delta_table(&st49_path, DeltaReportType::St49, Partitioning::None, false)
+            .await
+            .unwrap();
+    let spuds = vec![
+        spud("2025-01-04", "W1", "0516001", "04 Jan 2025 08:00:00 AM"),
+        spud("2025-01-06", "W2", "0516002", "06 Jan 2025 08:00:00 AM"),
+    ];
+    let batch = SpudData::to_record_batch(&spuds).unwrap();
+    write_records(&mut st49, DeltaReportType::St49, vec![batch], LoadMode::Append, &[])
+        .await
+        .unwrap();
+    let timeline_path = temp_dir.path().join("rig_timeline");
+    update_rig_timeline(&st49, &timeline_path).await.unwrap();
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let options = ServeOptions {
+        address,
+        st1_table_path: st1_path,
+        st49_table_path: st49_path,
+        rig_timeline_path: timeline_path,
+    };
+    tokio::spawn(async move { axum::serve(listener, router(options)).await });
+    let client = reqwest::Client::new();
+    let get = |path: &str| {
+        let request = client.get(format!("http://{address}{path}"));
+        async move {
+            let response = request.send().await.unwrap();
+            let status = response.status().as_u16();
+            (status, response.json::<serde_json::Value>().await.unwrap())
+        }
+    };
+    let licence_numbers = |page: &serde_json::Value| -> Vec<String> {
+        page["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| {
+                let date = item["date"].as_str().unwrap();
+                format!("{date} {}", item["licence_number"].as_str().unwrap())
+            })
+            .collect()
+    };
+
+    let (status, page) = get("/licences?limit=3").await;
+    assert_eq!(status, 200, "{page}");
+    assert_eq!(
+        licence_numbers(&page),
+        ["2025-01-03 0516001", "2025-01-03 0516003", "2025-01-02 0516001"]
+    );
+    assert_eq!(page["next_offset"], 3);
+    assert_eq!(page["items"][0]["projected_depth"], 3950.0);
+    let (_, page) = get("/licences?limit=3&offset=3").await;
+    assert_eq!(licence_numbers(&page), ["2025-01-02 0516002"]);
+    assert!(page["next_offset"].is_null());
+
+    // Licensees match in any spelling, areas by the DLS unique identifier
+    let (_, page) = get("/licences?licensee=tourmaline%20oil%20corp.").await;
+    assert_eq!(licence_numbers(&page), ["2025-01-03 0516003"]);
+    let (_, page) = get("/licences?meridian=4&township=26&from=2025-01-03").await;
+    assert_eq!(licence_numbers(&page), ["2025-01-03 0516001"]);
+
+    let (status, licence) = get("/licences/0516001").await;
+    assert_eq!(status, 200);
+    assert_eq!(licence["listings"].as_array().unwrap().len(), 2);
+    assert_eq!(licence["listings"][0]["date"], "2025-01-02");
+    let (status, error) = get("/licences/0599999").await;
+    assert_eq!(status, 404);
+    assert_eq!(error["error"], "No licence 0599999");
+    let (status, _) = get("/licences?limit=5000").await;
+    assert_eq!(status, 400);
+
+    let (_, page) = get("/spuds?contractor=bear%20drilling%20corporation&to=2025-01-05").await;
+    let items = page["items"].as_array().unwrap();
+    assert_eq!(items.len(), 1, "{page}");
+    assert_eq!(items[0]["well_id"], "W1");
+    assert_eq!(items[0]["new_projected_total_depth"], serde_json::Value::Null);
+
+    let (status, page) = get("/rigs/A978/1?limit=1").await;
+    assert_eq!(status, 200, "{page}");
+    assert_eq!(page["items"][0]["well_id"], "W1");
+    assert_eq!(page["items"][0]["sequence"], 1);
+    assert_eq!(page["next_offset"], 1);
+    let (_, page) = get("/rigs/bear%20drilling%20corp./1?offset=1").await;
+    assert_eq!(page["items"][0]["previous_well_id"], "W1");
+    let (status, _) = get("/rigs/A978/7").await;
+    assert_eq!(status, 404);
+
+    let (status, openapi) = get("/openapi.json").await;
+    assert_eq!(status, 200);
+    assert_eq!(openapi["openapi"], "3.0.3");
+    for path in ["/licences", "/licences/{number}", "/spuds", "/rigs/{contractor}/{rig}"] {
+        assert!(openapi["paths"][path]["get"].is_object(), "{path}");
+    }
+    assert_eq!(
+        openapi["components"]["schemas"]["Licence"]["properties"]["date"]["format"],
+        "date"
+    );
+}
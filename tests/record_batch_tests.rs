@@ -0,0 +1,234 @@
+//! Tests for direct parse-to-RecordBatch conversion
+//!
+//! These cover the path that feeds Delta Lake without writing CSV files.
+
+#[allow(dead_code, unused_imports)]
+mod fixtures;
+
+use aer_st1::parsers::lineage::{sha256_file, source_line_numbers, PARSER_VERSION};
+use aer_st1::parsers::record_batch::ArrowRecord;
+use aer_st1::st1::{License, LICENSE_COLUMNS};
+use aer_st1::st49::{SpudData, SPUD_COLUMNS};
+use aer_st1::{parse_file_to_batch, ReportType};
+use chrono::NaiveDate;
+use deltalake::arrow::array::{
+    Array, Date32Array, Float64Array, Int32Array, StringArray, TimestampMicrosecondArray,
+};
+use fixtures::TestData;
+
+#[tokio::test]
+async fn test_st1_parse_file_to_batch_without_csv() {
+    let data = TestData::new().unwrap();
+    data.create_st1_sample("WELLS0102.TXT", "02 January 2024").unwrap();
+    let txt_dir = data.st1_valid.to_str().unwrap();
+
+    let batch = parse_file_to_batch(
+        ReportType::St1,
+        "WELLS0102",
+        txt_dir,
+        None,
+        None,
+        None,
+        false,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(batch.num_rows(), 2);
+    assert_eq!(batch.num_columns(), LICENSE_COLUMNS.len());
+    assert_eq!(batch.schema(), License::arrow_schema());
+
+    let dates = batch
+        .column_by_name("date")
+        .unwrap()
+        .as_any()
+        .downcast_ref::<Date32Array>()
+        .unwrap();
+    assert_eq!(
+        dates.value_as_date(0),
+        NaiveDate::from_ymd_opt(2024, 1, 2)
+    );
+
+    // No CSV output directory was given, so only the TXT file remains
+    assert_eq!(std::fs::read_dir(&data.st1_valid).unwrap().count(), 1);
+}
+
+#[tokio::test]
+async fn test_st1_parse_file_to_batch_outside_range() {
+    let data = TestData::new().unwrap();
+    data.create_st1_sample("WELLS0102.TXT", "02 January 2024").unwrap();
+    let txt_dir = data.st1_valid.to_str().unwrap();
+
+    let result = parse_file_to_batch(
+        ReportType::St1,
+        "WELLS0102",
+        txt_dir,
+        None,
+        NaiveDate::from_ymd_opt(2024, 2, 1),
+        NaiveDate::from_ymd_opt(2024, 2, 28),
+        false,
+    )
+    .await;
+
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_st1_parse_file_to_batch_with_lineage() {
+    let data = TestData::new().unwrap();
+    let path = data.create_st1_sample("WELLS0102.TXT", "02 January 2024").unwrap();
+    let txt_dir = data.st1_valid.to_str().unwrap();
+
+    let batch = parse_file_to_batch(
+        ReportType::St1,
+        "WELLS0102",
+        txt_dir,
+        None,
+        None,
+        None,
+        true,
+    )
+    .await
+    .unwrap();
+
+    // ingested_at is only added when the rows are written to Delta
+    assert_eq!(batch.num_columns(), LICENSE_COLUMNS.len() + 4);
+    let strings = |name: &str| {
+        batch
+            .column_by_name(name)
+            .unwrap()
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap()
+            .clone()
+    };
+    assert_eq!(strings("source_file").value(0), "WELLS0102.TXT");
+    assert_eq!(strings("source_sha256").value(1), sha256_file(&path).unwrap());
+    assert_eq!(strings("parser_version").value(0), PARSER_VERSION);
+
+    // Each licence starts on the line with its well name
+    let lines = batch
+        .column_by_name("source_line")
+        .unwrap()
+        .as_any()
+        .downcast_ref::<Int32Array>()
+        .unwrap();
+    assert_eq!(lines.values().to_vec(), vec![13, 18]);
+}
+
+#[test]
+fn test_source_line_numbers_skip_repeated_text() {
+    let content = "HEADER\n\nA\nB\n  A  \nC\n";
+    assert_eq!(
+        source_line_numbers(content, &["A", "A", "C", "D"]),
+        vec![Some(3), Some(5), Some(6), None]
+    );
+}
+
+#[test]
+fn test_spud_records_to_batch() {
+    let records = vec![SpudData {
+        date: "2025-01-01".to_string(),
+        well_id: "00/05-15-050-22W5/0".to_string(),
+        well_name: "TOURMALINE HZ LAMBERT 5-15-50-22".to_string(),
+        licence: "0515496".to_string(),
+        contractor_ba_id: "A978".to_string(),
+        contractor_name: "Bear Drilling Corp.".to_string(),
+        rig_number: "1".to_string(),
+        activity_date: "01 Jan 2025 12:30:00 PM".to_string(),
+        field_centre: "Drayton Valley".to_string(),
+        ba_id: "A573".to_string(),
+        licensee: "Tourmaline Oil Corp.".to_string(),
+        new_projected_total_depth: "".to_string(),
+        activity_type: "Drill To LD".to_string(),
+    }];
+
+    let batch = SpudData::to_record_batch(&records).unwrap();
+    assert_eq!(batch.num_rows(), 1);
+    assert_eq!(batch.num_columns(), SPUD_COLUMNS.len());
+
+    let licence = batch
+        .column_by_name("licence")
+        .unwrap()
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .unwrap();
+    assert_eq!(licence.value(0), "0515496");
+    assert!(!licence.is_null(0));
+
+    let rig_number = batch
+        .column_by_name("rig_number")
+        .unwrap()
+        .as_any()
+        .downcast_ref::<Int32Array>()
+        .unwrap();
+    assert_eq!(rig_number.value(0), 1);
+
+    let activity_date = batch
+        .column_by_name("activity_date")
+        .unwrap()
+        .as_any()
+        .downcast_ref::<TimestampMicrosecondArray>()
+        .unwrap();
+    assert_eq!(
+        activity_date.value_as_datetime(0),
+        NaiveDate::from_ymd_opt(2025, 1, 1).and_then(|d| d.and_hms_opt(12, 30, 0))
+    );
+
+    // A blank projected depth is stored as null rather than an empty string
+    let depth = batch.column_by_name("new_projected_total_depth").unwrap();
+    assert!(depth.is_null(0));
+}
+
+#[test]
+fn test_licence_metres_are_typed() {
+    let licence = License {
+        date: "2025-01-02".to_string(),
+        well_name: "TXNE 4B-30 HZ WAYNE 7-36-26-18".to_string(),
+        licence_number: "0516990".to_string(),
+        mineral_rights: "FREEHOLD".to_string(),
+        ground_elevation: "931.70M".to_string(),
+        unique_identifier: "100/07-36-026-18W4/00".to_string(),
+        surface_coordinates: "N  120.0M  E  155.2M".to_string(),
+        aer_field_centre: "MEDICINE HAT".to_string(),
+        projected_depth: "3950.0M".to_string(),
+        aer_classification: "DEV (NC)".to_string(),
+        field: "WAYNE-ROSEDALE".to_string(),
+        terminating_zone: "BASAL QUARTZ SD".to_string(),
+        drilling_operation: "HORIZONTAL".to_string(),
+        well_purpose: "NEW".to_string(),
+        well_type: "PRODUCTION".to_string(),
+        substance: "CRUDE OIL".to_string(),
+        licensee: "TORXEN ENERGY LTD.".to_string(),
+        surface_location: "04-30-026-17W4".to_string(),
+    };
+
+    let batch = License::to_record_batch(&[licence]).unwrap();
+    let column = |name: &str| {
+        batch
+            .column_by_name(name)
+            .unwrap()
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap()
+            .value(0)
+    };
+    assert_eq!(column("ground_elevation"), 931.7);
+    assert_eq!(column("projected_depth"), 3950.0);
+
+    let licence_number = batch
+        .column_by_name("licence_number")
+        .unwrap()
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .unwrap();
+    // Licence numbers keep their leading zeros
+    assert_eq!(licence_number.value(0), "0516990");
+}
+
+#[test]
+fn test_empty_records_to_batch() {
+    let batch = License::to_record_batch(&[]).unwrap();
+    assert_eq!(batch.num_rows(), 0);
+    assert_eq!(batch.num_columns(), LICENSE_COLUMNS.len());
+}
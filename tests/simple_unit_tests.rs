@@ -2,7 +2,9 @@
 //!
 //! This module contains basic unit tests that don't require public access to internal functions.
 
+use aer_st1::parsers::common::field_parsing::{normalize_company_name, parse_classification};
 use aer_st1::parsers::common::{date_utils, file_ops, trim_and_remove_empty_lines};
+use aer_st1::parsers::location::DlsLocation;
 use chrono::NaiveDate;
 
 #[test]
@@ -83,3 +85,58 @@ fn test_file_ops_read_file_lines() {
     assert_eq!(lines[1], "Line 2");
     assert_eq!(lines[2], "Line 3");
 }
+
+#[test]
+fn test_dls_location_parse() {
+    let expected = DlsLocation {
+        lsd: 7,
+        section: 36,
+        township: 26,
+        range: 18,
+        meridian: 4,
+    };
+    assert_eq!(DlsLocation::parse("100/07-36-026-18W4/00"), Some(expected));
+    assert_eq!(DlsLocation::parse("100/07-36-026-18W4/00  N  120.0M  E"), Some(expected));
+    assert_eq!(DlsLocation::parse("07-36-026-18W4"), Some(expected));
+    assert_eq!(
+        DlsLocation::parse("00/05-15-050-22W5/0").map(|l| (l.lsd, l.township, l.meridian)),
+        Some((5, 50, 5))
+    );
+    assert_eq!(DlsLocation::parse("17-36-026-18W4"), None);
+    assert_eq!(DlsLocation::parse("07-36-026-18E4"), None);
+    assert_eq!(DlsLocation::parse(""), None);
+}
+
+#[test]
+fn test_dls_location_approximate_lat_lon() {
+    // LSD 1 of section 1 is the south-east corner of a township, LSD 13 of
+    // section 6 the south-west corner
+    let corner = |value: &str| DlsLocation::parse(value).unwrap().approximate_lat_lon();
+    let (south_east_lat, south_east_lon) = corner("01-01-001-01W4");
+    assert!((south_east_lat - 49.0018).abs() < 0.001, "{south_east_lat}");
+    assert!((south_east_lon + 110.0028).abs() < 0.001, "{south_east_lon}");
+    let (_, south_west_lon) = corner("04-06-001-01W4");
+    assert!(south_west_lon < south_east_lon);
+    let (north_lat, _) = corner("16-36-001-01W4");
+    assert!((north_lat - 49.0850).abs() < 0.001, "{north_lat}");
+}
+
+#[test]
+fn test_parse_classification() {
+    assert_eq!(parse_classification("DEV (NC)"), Some(("DEV", false)));
+    assert_eq!(parse_classification(" XPL (C) "), Some(("XPL", true)));
+    assert_eq!(parse_classification("DEV"), None);
+}
+
+#[test]
+fn test_normalize_company_name() {
+    let key = Some("TOURMALINE OIL CORP".to_string());
+    assert_eq!(normalize_company_name("TOURMALINE OIL CORP."), key);
+    assert_eq!(normalize_company_name("Tourmaline Oil Corp."), key);
+    assert_eq!(normalize_company_name(" Tourmaline  Oil Corporation "), key);
+    assert_eq!(
+        normalize_company_name("Cenovus Energy & Partners Limited"),
+        Some("CENOVUS ENERGY AND PARTNERS LTD".to_string())
+    );
+    assert_eq!(normalize_company_name(" . "), None);
+}